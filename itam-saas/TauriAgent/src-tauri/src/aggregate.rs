@@ -0,0 +1,341 @@
+// ============================================================================
+// Violation Aggregation Module
+// ============================================================================
+// scan_processes reports every new PID, so an app that respawns or a
+// browser with a dozen helper processes used to create one /api/alerts row
+// per PID. Violations are now grouped per (device, rule, executable):
+//
+// - The first scan that sees a group reports it once, with every PID that
+//   scan found (aggregate.count / aggregate.process_ids)
+// - Further PIDs of the same group are folded in silently
+// - The group closes after window_secs without a new PID; the next match
+//   opens a new group and alerts again
+//
+// Escalation (settings.aggregation) - an open group is re-alerted, with its
+// running totals and the previous alert id, when new PIDs arrived since the
+// last alert and either:
+// - realert_after_secs have passed since that alert, or
+// - realert_after_count new PIDs have arrived since that alert
+// With escalate_severity, every re-alert raises the severity by one step
+// (Low -> Medium -> High -> Critical).
+//
+// A group only counts as alerted once report_violation succeeded
+// (mark_reported), so alerts that failed while offline are retried on the
+// next scan with the totals collected in the meantime. With aggregation
+// disabled the monitor has the scanners forget a failed report instead, so
+// its process is reported again on the next scan.
+//
+// On shutdown, take_unreported() hands over every group with PIDs no alert
+// has covered yet (never alerted, delivery failed, or folded in since the
+// last alert) so the monitor can queue them in the persisted outbox.
+//
+// Only enforced violations are aggregated; audit matches go to the outbox
+// unchanged.
+// ============================================================================
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::forbidden::ViolationReport;
+use crate::settings::AggregationSettings;
+
+/// Group summary attached to a ViolationReport
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ViolationAggregate {
+    /// PIDs seen in this group so far
+    pub count: u32,
+    /// Up to settings.aggregation.max_pids of them, oldest first
+    pub process_ids: Vec<u32>,
+    /// Seconds since UNIX epoch
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub duration_secs: u64,
+    /// 1 for the first alert of a group, 2+ for re-alerts
+    pub alert_number: u32,
+    /// Alert created for this group by the previous report, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_alert_id: Option<i64>,
+}
+
+#[derive(Debug)]
+struct ViolationGroup {
+    /// First violation of the group; evidence and severity come from it
+    report: ViolationReport,
+    process_ids: Vec<u32>,
+    count: u32,
+    first_seen: u64,
+    last_seen: u64,
+    /// Count and time of the last successful alert
+    alerted_count: u32,
+    alerted_at: Option<u64>,
+    alerts_sent: u32,
+    last_alert_id: Option<i64>,
+}
+
+pub struct ViolationAggregator {
+    settings: AggregationSettings,
+    groups: HashMap<String, ViolationGroup>,
+}
+
+/// Group key: device, rule (server id, else process name) and executable
+fn group_key(violation: &ViolationReport) -> String {
+    let rule = match (&violation.network, violation.rule_id) {
+        (Some(network), _) => format!(
+            "net:{}",
+            network
+                .network_rule_id
+                .map(|id| id.to_string())
+                .unwrap_or_else(|| network.rule_name.clone())
+        ),
+        (None, Some(id)) => format!("rule:{}", id),
+        (None, None) => format!("app:{}", violation.app_detected),
+    };
+    let exe = violation
+        .evidence
+        .as_ref()
+        .and_then(|evidence| evidence.exe_path.clone())
+        .unwrap_or_else(|| violation.app_detected.clone());
+    format!("{}|{}|{}", violation.device_id, rule, exe)
+}
+
+/// One step up the Low / Medium / High / Critical ladder
+fn escalate(severity: &str) -> String {
+    match severity.to_lowercase().as_str() {
+        "low" => "Medium",
+        "medium" => "High",
+        _ => "Critical",
+    }
+    .to_string()
+}
+
+impl ViolationAggregator {
+    pub fn new(settings: AggregationSettings) -> Self {
+        Self {
+            settings,
+            groups: HashMap::new(),
+        }
+    }
+
+    /// Fold newly detected violations into their groups and return the
+    /// reports that are due now (new groups, retries and escalations).
+    /// With aggregation disabled every violation is passed through.
+    pub fn ingest(&mut self, violations: Vec<ViolationReport>, now: u64) -> Vec<ViolationReport> {
+        if !self.settings.enabled {
+            return violations;
+        }
+
+        // Close groups that went quiet; unreported ones are kept for retry
+        let window = self.settings.window_secs;
+        self.groups
+            .retain(|_, group| group.alerted_at.is_none() || now.saturating_sub(group.last_seen) <= window);
+
+        for violation in violations {
+            let key = group_key(&violation);
+            let pid = violation.process_id;
+            let group = self.groups.entry(key).or_insert_with(|| ViolationGroup {
+                report: violation,
+                process_ids: Vec::new(),
+                count: 0,
+                first_seen: now,
+                last_seen: now,
+                alerted_count: 0,
+                alerted_at: None,
+                alerts_sent: 0,
+                last_alert_id: None,
+            });
+            if group.process_ids.contains(&pid) {
+                continue;
+            }
+            group.count += 1;
+            group.last_seen = now;
+            if group.process_ids.len() < self.settings.max_pids {
+                group.process_ids.push(pid);
+            }
+        }
+
+        let mut due = Vec::new();
+        for group in self.groups.values() {
+            if self.is_due(group, now) {
+                due.push(self.build_report(group));
+            }
+        }
+        due
+    }
+
+    fn is_due(&self, group: &ViolationGroup, now: u64) -> bool {
+        let Some(alerted_at) = group.alerted_at else {
+            return true;
+        };
+        let new_since_alert = group.count.saturating_sub(group.alerted_count);
+        if new_since_alert == 0 {
+            return false;
+        }
+
+        let by_time = self.settings.realert_after_secs > 0
+            && now.saturating_sub(alerted_at) >= self.settings.realert_after_secs;
+        let by_count = self.settings.realert_after_count > 0
+            && new_since_alert >= self.settings.realert_after_count;
+        by_time || by_count
+    }
+
+    fn build_report(&self, group: &ViolationGroup) -> ViolationReport {
+        let mut report = group.report.clone();
+        if self.settings.escalate_severity {
+            for _ in 0..group.alerts_sent {
+                report.severity = escalate(&report.severity);
+            }
+        }
+        report.aggregate = Some(ViolationAggregate {
+            count: group.count,
+            process_ids: group.process_ids.clone(),
+            first_seen: group.first_seen,
+            last_seen: group.last_seen,
+            duration_secs: group.last_seen - group.first_seen,
+            alert_number: group.alerts_sent + 1,
+            previous_alert_id: group.last_alert_id,
+        });
+        report
+    }
+
+    /// Reports for every group with PIDs not covered by an alert yet, which
+    /// are then counted as alerted (the caller queues them in the outbox)
+    pub fn take_unreported(&mut self, now: u64) -> Vec<ViolationReport> {
+        let unreported: Vec<ViolationReport> = self
+            .groups
+            .values()
+            .filter(|group| group.alerted_at.is_none() || group.count > group.alerted_count)
+            .map(|group| self.build_report(group))
+            .collect();
+        for report in &unreported {
+            self.mark_reported(report, None, now);
+        }
+        unreported
+    }
+
+    /// Record that a report returned by `ingest` reached the server
+    pub fn mark_reported(&mut self, report: &ViolationReport, alert_id: Option<i64>, now: u64) {
+        let Some(aggregate) = &report.aggregate else {
+            return;
+        };
+        if let Some(group) = self.groups.get_mut(&group_key(report)) {
+            group.alerted_count = aggregate.count;
+            group.alerted_at = Some(now);
+            group.alerts_sent += 1;
+            group.last_alert_id = alert_id.or(group.last_alert_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn violation(app: &str, pid: u32) -> ViolationReport {
+        ViolationReport {
+            device_id: "test-device".to_string(),
+            app_detected: app.to_string(),
+            severity: "Low".to_string(),
+            process_id: pid,
+            rule_id: Some(7),
+            evidence: None,
+            mode: Default::default(),
+            alert_id: None,
+            network: None,
+            aggregate: None,
+        }
+    }
+
+    /// One-minute windows, no re-alerts
+    fn settings() -> AggregationSettings {
+        AggregationSettings {
+            window_secs: 60,
+            realert_after_secs: 0,
+            realert_after_count: 0,
+            ..AggregationSettings::default()
+        }
+    }
+
+    fn counts(reports: &[ViolationReport]) -> Vec<(u32, Vec<u32>, u32)> {
+        reports
+            .iter()
+            .map(|r| {
+                let aggregate = r.aggregate.as_ref().unwrap();
+                (aggregate.count, aggregate.process_ids.clone(), aggregate.alert_number)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pids_of_one_group_are_counted_in_one_report() {
+        let mut aggregator = ViolationAggregator::new(settings());
+
+        let due = aggregator.ingest(vec![violation("steam", 1), violation("steam", 2), violation("steam", 2)], 0);
+        assert_eq!(counts(&due), vec![(2, vec![1, 2], 1)]);
+        aggregator.mark_reported(&due[0], Some(40), 0);
+
+        // Folded in silently while the window is open
+        assert!(aggregator.ingest(vec![violation("steam", 3)], 30).is_empty());
+        assert!(aggregator.ingest(vec![violation("steam", 4)], 90).is_empty());
+
+        // Quiet for longer than window_secs: a new group alerts again
+        let due = aggregator.ingest(vec![violation("steam", 5)], 151);
+        assert_eq!(counts(&due), vec![(1, vec![5], 1)]);
+        assert_eq!(due[0].aggregate.as_ref().unwrap().previous_alert_id, None);
+    }
+
+    #[test]
+    fn unreported_groups_stay_due_with_their_running_totals() {
+        let mut aggregator = ViolationAggregator::new(AggregationSettings {
+            max_pids: 2,
+            ..settings()
+        });
+
+        let due = aggregator.ingest(vec![violation("steam", 1)], 0);
+        assert_eq!(counts(&due), vec![(1, vec![1], 1)]);
+
+        // Delivery failed; the group is due again, past its window too
+        let due = aggregator.ingest(vec![violation("steam", 2), violation("steam", 3)], 500);
+        assert_eq!(counts(&due), vec![(3, vec![1, 2], 1)]);
+        assert_eq!(due[0].aggregate.as_ref().unwrap().duration_secs, 500);
+    }
+
+    #[test]
+    fn realerts_escalate_and_link_the_previous_alert() {
+        let mut aggregator = ViolationAggregator::new(AggregationSettings {
+            realert_after_count: 2,
+            escalate_severity: true,
+            ..settings()
+        });
+
+        let due = aggregator.ingest(vec![violation("steam", 1)], 0);
+        aggregator.mark_reported(&due[0], Some(40), 0);
+        assert!(aggregator.ingest(vec![violation("steam", 2)], 10).is_empty());
+
+        let due = aggregator.ingest(vec![violation("steam", 3)], 20);
+        assert_eq!(counts(&due), vec![(3, vec![1, 2, 3], 2)]);
+        assert_eq!(due[0].severity, "Medium");
+        assert_eq!(due[0].aggregate.as_ref().unwrap().previous_alert_id, Some(40));
+    }
+
+    #[test]
+    fn groups_are_split_by_rule_and_executable() {
+        let mut aggregator = ViolationAggregator::new(settings());
+        let mut other_rule = violation("steam", 2);
+        other_rule.rule_id = Some(8);
+
+        let due = aggregator.ingest(vec![violation("steam", 1), other_rule, violation("steamwebhelper", 3)], 0);
+        assert_eq!(due.len(), 3);
+    }
+
+    #[test]
+    fn disabled_aggregation_passes_every_violation_through() {
+        let mut aggregator = ViolationAggregator::new(AggregationSettings {
+            enabled: false,
+            ..settings()
+        });
+
+        let due = aggregator.ingest(vec![violation("steam", 1), violation("steam", 2)], 0);
+        assert_eq!(due.len(), 2);
+        assert!(due.iter().all(|r| r.aggregate.is_none()));
+    }
+}
@@ -0,0 +1,99 @@
+// ============================================================================
+// API Client Module
+// ============================================================================
+// Where and how the agent talks to the backend.
+//
+// - Backend: settings.api.base_url (built in, settings.rs) and the request
+//   timeout. AgentState builds it once from the loaded settings and hands
+//   it to the monitor and every call that talks to the backend, so tests
+//   point an agent at a local mock backend without process-wide state
+// - Backend::client(): reqwest client with settings.api.request_timeout_secs
+//   applied. reqwest has no default timeout, so without it a backend that
+//   accepts the connection but never answers would stall a monitoring loop
+//   forever
+// - Backend::streaming_client(): same timeout for connecting only, for
+//   long-lived responses (push.rs detects a dead stream by its keepalives
+//   instead)
+// - send(): sends a request and records its latency and outcome per
+//   endpoint for the /metrics endpoint (metrics.rs)
+// - ensure_success(): turns a non-2xx response into a typed AgentError
+//   (error.rs), so callers can tell 401 from 429 from 503
+// ============================================================================
+
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::error::AgentError;
+use crate::metrics;
+use crate::settings::ApiSettings;
+
+/// Where the backend is and how long to wait for it, from settings.api
+#[derive(Debug, Clone)]
+pub struct Backend {
+    base_url: String,
+    timeout: Duration,
+    client: reqwest::Client,
+}
+
+impl Backend {
+    pub fn new(settings: &ApiSettings) -> Self {
+        let timeout = Duration::from_secs(settings.request_timeout_secs.max(1));
+        Self {
+            base_url: settings.base_url.trim_end_matches('/').to_string(),
+            timeout,
+            client: reqwest::Client::builder().timeout(timeout).build().unwrap_or_default(),
+        }
+    }
+
+    /// Backend base URL, without a trailing slash
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Absolute URL of an API path such as "/api/alerts"
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// HTTP client with the request timeout; clones share one connection pool
+    pub fn client(&self) -> reqwest::Client {
+        self.client.clone()
+    }
+
+    /// HTTP client for long-lived streams: bounded connect, unbounded body
+    pub fn streaming_client(&self) -> reqwest::Client {
+        reqwest::Client::builder()
+            .connect_timeout(self.timeout)
+            .build()
+            .unwrap_or_default()
+    }
+}
+
+/// Seconds since the Unix epoch, the timestamp format of every API payload
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Send a request built from client() and record it in the metrics
+pub async fn send(request: reqwest::RequestBuilder) -> Result<reqwest::Response, AgentError> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    let method = request.method().to_string();
+    let endpoint = metrics::endpoint_label(request.url().path());
+    let started = Instant::now();
+    let result = client.execute(request).await;
+    let status = result.as_ref().ok().map(|response| response.status().as_u16());
+    metrics::global().observe_http(&method, &endpoint, status, started.elapsed());
+    Ok(result?)
+}
+
+/// Pass a successful response through, classify anything else
+pub async fn ensure_success(response: reqwest::Response) -> Result<reqwest::Response, AgentError> {
+    if response.status().is_success() {
+        Ok(response)
+    } else {
+        Err(AgentError::from_response(response).await)
+    }
+}
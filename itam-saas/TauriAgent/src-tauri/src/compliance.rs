@@ -0,0 +1,582 @@
+// ============================================================================
+// Compliance Module: Security Posture Checks
+// ============================================================================
+// Declarative checks defined on the server, run by the "compliance" task
+// every settings.compliance.interval_secs:
+//
+//   GET /api/agent/compliance-checks?device_id=<id>
+//   [ { "id": "disk-encrypted", "name": "Root disk encrypted",
+//       "probe": "disk_encryption", "expected": true, "severity": "High",
+//       "weight": 3 },
+//     { "id": "patched", "probe": "pending_security_updates",
+//       "expected": { "op": "<=", "value": 0 } } ]
+//
+// `expected` is a plain value (compared for equality, strings ignoring
+// case) or { "op": "<=", "value": 0 } with a comparator from thresholds.rs.
+// A 404 (older backend) or an empty list means nothing to check and no
+// report.
+//
+// | probe                     | observes | Linux                  | Windows / macOS          |
+// |---------------------------|----------|------------------------|--------------------------|
+// | disk_encryption           | bool     | root on a LUKS device  | BitLocker C: / FileVault |
+// | firewall_enabled          | bool     | ufw, firewalld or an   | all netsh profiles on /  |
+// |                           |          | nftables input hook    | application firewall     |
+// | pending_security_updates  | number   | apt-get -s, dnf        | not applicable           |
+// | ssh_root_login            | string   | sshd -T, sshd_config   | not applicable / same    |
+// | screen_lock_enabled       | bool     | GNOME lock-enabled     | ScreenSaverIsSecure / -  |
+// | automatic_updates_enabled | bool     | unattended-upgrades,   | NoAutoUpdate policy /    |
+// |                           |          | dnf-automatic timer    | AutomaticCheckEnabled    |
+//
+// Each check passes, fails, errors (the probe could not tell) or is not
+// applicable on this device. The score is the weighted share of passing
+// checks among those that pass, fail or error; not applicable checks do not
+// count. The report goes through the outbox:
+//
+//   POST /api/agent/compliance
+//   { "device_id": "...", "checked_at": 1718000000, "score": 75,
+//     "passed": 3, "failed": 1, "errors": 0, "not_applicable": 1,
+//     "results": [ { "id": "disk-encrypted", "status": "fail",
+//                    "observed": false, "expected": true, ... } ] }
+//
+// Probes only read: fixed commands, each cut off after
+// settings.compliance.probe_timeout_secs, and a few config files. The last
+// report is kept for get_compliance_report.
+// ============================================================================
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::api::{self, unix_now, Backend};
+use crate::error::AgentError;
+use crate::forbidden::get_device_id;
+use crate::remote_tasks::id_string;
+use crate::state::AgentState;
+use crate::supervisor::lock;
+use crate::thresholds::Comparator;
+
+pub const CHECKS_PATH: &str = "/api/agent/compliance-checks";
+pub const REPORT_ENDPOINT: &str = "/api/agent/compliance";
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+fn default_severity() -> String {
+    "Medium".to_string()
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// One check as served by the backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComplianceCheck {
+    #[serde(deserialize_with = "id_string")]
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    pub probe: String,
+    pub expected: Expected,
+    #[serde(default = "default_severity")]
+    pub severity: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Expected {
+    Compare { op: Comparator, value: f64 },
+    Equals(Value),
+}
+
+impl Expected {
+    pub fn matches(&self, observed: &Value) -> bool {
+        match (self, observed) {
+            (Expected::Compare { op, value }, observed) => observed.as_f64().is_some_and(|o| op.holds(o, *value)),
+            (Expected::Equals(Value::String(expected)), Value::String(observed)) => {
+                expected.eq_ignore_ascii_case(observed)
+            }
+            (Expected::Equals(expected), observed) if expected.is_number() => expected.as_f64() == observed.as_f64(),
+            (Expected::Equals(expected), observed) => expected == observed,
+        }
+    }
+}
+
+/// What a probe found
+#[derive(Debug, Clone, PartialEq)]
+pub enum Observation {
+    Value(Value),
+    NotApplicable(String),
+    Error(String),
+}
+
+/// Read access to the device; tests replay recorded outputs
+pub trait Host {
+    fn os(&self) -> &str;
+    /// stdout of a command that ran to completion (any exit code), None if
+    /// it is not installed, failed to start or timed out
+    fn run(&self, program: &str, args: &[&str]) -> Option<String>;
+    fn read(&self, path: &str) -> Option<String>;
+}
+
+/// The real device
+pub struct LiveHost {
+    timeout: Duration,
+}
+
+impl LiveHost {
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+}
+
+impl Host for LiveHost {
+    fn os(&self) -> &str {
+        std::env::consts::OS
+    }
+
+    fn run(&self, program: &str, args: &[&str]) -> Option<String> {
+        let mut child = Command::new(program)
+            .args(args)
+            .env("LC_ALL", "C")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        // Drain stdout while waiting, a full pipe would block the child
+        let mut stdout = child.stdout.take()?;
+        let reader = std::thread::spawn(move || {
+            let mut output = String::new();
+            let _ = stdout.read_to_string(&mut output);
+            output
+        });
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match child.try_wait() {
+                Ok(Some(_)) => break,
+                Ok(None) if Instant::now() < deadline => std::thread::sleep(POLL_INTERVAL),
+                _ => {
+                    warn!(program, "Compliance probe command timed out");
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return None;
+                }
+            }
+        }
+        reader.join().ok()
+    }
+
+    fn read(&self, path: &str) -> Option<String> {
+        std::fs::read_to_string(path).ok()
+    }
+}
+
+fn not_supported(host: &dyn Host) -> Observation {
+    Observation::NotApplicable(format!("not supported on {}", host.os()))
+}
+
+fn flag(value: bool) -> Observation {
+    Observation::Value(Value::Bool(value))
+}
+
+fn disk_encryption(host: &dyn Host) -> Observation {
+    match host.os() {
+        "linux" => {
+            let Some(source) = host.run("findmnt", &["-no", "SOURCE", "/"]) else {
+                return Observation::Error("findmnt failed".to_string());
+            };
+            match host.run("lsblk", &["-s", "-no", "TYPE", source.trim()]) {
+                Some(types) => flag(types.lines().any(|t| t.trim() == "crypt")),
+                None => Observation::Error("lsblk failed".to_string()),
+            }
+        }
+        "windows" => match host.run("manage-bde", &["-status", "C:"]) {
+            Some(status) => flag(status.contains("Protection On")),
+            None => Observation::Error("manage-bde failed".to_string()),
+        },
+        "macos" => match host.run("fdesetup", &["status"]) {
+            Some(status) => flag(status.contains("FileVault is On")),
+            None => Observation::Error("fdesetup failed".to_string()),
+        },
+        _ => not_supported(host),
+    }
+}
+
+fn firewall_enabled(host: &dyn Host) -> Observation {
+    match host.os() {
+        "linux" => {
+            let ufw = host.run("ufw", &["status"]);
+            if ufw.as_deref().is_some_and(|s| s.contains("Status: active")) {
+                return flag(true);
+            }
+            let firewalld = host.run("systemctl", &["is-active", "firewalld"]);
+            if firewalld.as_deref().map(str::trim) == Some("active") {
+                return flag(true);
+            }
+            let nft = host.run("nft", &["list", "ruleset"]);
+            if nft.as_deref().is_some_and(|rules| rules.contains("hook input")) {
+                return flag(true);
+            }
+            if ufw.is_none() && nft.is_none() {
+                return Observation::Error("neither ufw nor nft available".to_string());
+            }
+            flag(false)
+        }
+        "windows" => match host.run("netsh", &["advfirewall", "show", "allprofiles", "state"]) {
+            Some(state) => {
+                let states: Vec<&str> = state
+                    .lines()
+                    .filter(|l| l.trim_start().starts_with("State"))
+                    .filter_map(|l| l.split_whitespace().last())
+                    .collect();
+                flag(!states.is_empty() && states.iter().all(|s| s.eq_ignore_ascii_case("ON")))
+            }
+            None => Observation::Error("netsh failed".to_string()),
+        },
+        "macos" => match host.run("/usr/libexec/ApplicationFirewall/socketfilterfw", &["--getglobalstate"]) {
+            Some(state) => flag(state.contains("enabled")),
+            None => Observation::Error("socketfilterfw failed".to_string()),
+        },
+        _ => not_supported(host),
+    }
+}
+
+fn pending_security_updates(host: &dyn Host) -> Observation {
+    if host.os() != "linux" {
+        return not_supported(host);
+    }
+    if let Some(simulated) = host.run("apt-get", &["-s", "-o", "Debug::NoLocking=1", "upgrade"]) {
+        let count = simulated
+            .lines()
+            .filter(|l| l.starts_with("Inst ") && l.contains("-security"))
+            .count();
+        return Observation::Value(json!(count));
+    }
+    if let Some(advisories) = host.run("dnf", &["-q", "updateinfo", "list", "--security", "--available"]) {
+        let count = advisories.lines().filter(|l| !l.trim().is_empty()).count();
+        return Observation::Value(json!(count));
+    }
+    Observation::NotApplicable("no apt-get or dnf".to_string())
+}
+
+fn ssh_root_login(host: &dyn Host) -> Observation {
+    if host.os() == "windows" {
+        return not_supported(host);
+    }
+    // Effective configuration, including Match blocks and includes
+    if let Some(effective) = host.run("sshd", &["-T"]) {
+        if let Some(value) = effective.lines().find_map(|l| l.strip_prefix("permitrootlogin ")) {
+            return Observation::Value(json!(value.trim()));
+        }
+    }
+    let Some(config) = host.read("/etc/ssh/sshd_config") else {
+        return Observation::NotApplicable("sshd is not installed".to_string());
+    };
+    // sshd takes the first occurrence; OpenSSH defaults to prohibit-password
+    let value = config
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.starts_with('#'))
+        .find_map(|l| {
+            let (key, value) = l.split_once(char::is_whitespace)?;
+            key.eq_ignore_ascii_case("PermitRootLogin").then(|| value.trim().to_lowercase())
+        })
+        .unwrap_or_else(|| "prohibit-password".to_string());
+    Observation::Value(json!(value))
+}
+
+fn screen_lock_enabled(host: &dyn Host) -> Observation {
+    match host.os() {
+        "linux" => match host.run("gsettings", &["get", "org.gnome.desktop.screensaver", "lock-enabled"]) {
+            Some(value) => flag(value.trim() == "true"),
+            None => Observation::NotApplicable("no GNOME settings".to_string()),
+        },
+        "windows" => match host.run("reg", &["query", r"HKCU\Control Panel\Desktop", "/v", "ScreenSaverIsSecure"]) {
+            Some(value) => flag(value.split_whitespace().last() == Some("1")),
+            None => flag(false),
+        },
+        _ => not_supported(host),
+    }
+}
+
+fn automatic_updates_enabled(host: &dyn Host) -> Observation {
+    match host.os() {
+        "linux" => {
+            if let Some(config) = host.read("/etc/apt/apt.conf.d/20auto-upgrades") {
+                return flag(config.contains(r#"APT::Periodic::Unattended-Upgrade "1""#));
+            }
+            for timer in ["dnf-automatic.timer", "dnf-automatic-install.timer"] {
+                if host.run("systemctl", &["is-enabled", timer]).as_deref().map(str::trim) == Some("enabled") {
+                    return flag(true);
+                }
+            }
+            if host.run("apt-get", &["--version"]).is_some() || host.run("dnf", &["--version"]).is_some() {
+                return flag(false);
+            }
+            Observation::NotApplicable("no apt-get or dnf".to_string())
+        }
+        "windows" => {
+            let key = r"HKLM\SOFTWARE\Policies\Microsoft\Windows\WindowsUpdate\AU";
+            match host.run("reg", &["query", key, "/v", "NoAutoUpdate"]) {
+                Some(value) => flag(value.split_whitespace().last() != Some("0x1")),
+                None => flag(true),
+            }
+        }
+        "macos" => {
+            let plist = "/Library/Preferences/com.apple.SoftwareUpdate";
+            match host.run("defaults", &["read", plist, "AutomaticCheckEnabled"]) {
+                Some(value) => flag(value.trim() == "1"),
+                None => Observation::Error("defaults failed".to_string()),
+            }
+        }
+        _ => not_supported(host),
+    }
+}
+
+/// Run one probe by name
+pub fn observe(probe: &str, host: &dyn Host) -> Observation {
+    match probe {
+        "disk_encryption" => disk_encryption(host),
+        "firewall_enabled" => firewall_enabled(host),
+        "pending_security_updates" => pending_security_updates(host),
+        "ssh_root_login" => ssh_root_login(host),
+        "screen_lock_enabled" => screen_lock_enabled(host),
+        "automatic_updates_enabled" => automatic_updates_enabled(host),
+        other => Observation::Error(format!("Unknown probe {:?}", other)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    Error,
+    NotApplicable,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub id: String,
+    pub name: String,
+    pub probe: String,
+    pub status: CheckStatus,
+    pub expected: Expected,
+    pub observed: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub severity: String,
+    pub weight: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ComplianceReport {
+    pub device_id: String,
+    /// Seconds since UNIX epoch
+    pub checked_at: u64,
+    /// 0-100, None when no check applies to this device
+    pub score: Option<u8>,
+    pub passed: usize,
+    pub failed: usize,
+    pub errors: usize,
+    pub not_applicable: usize,
+    pub results: Vec<CheckResult>,
+}
+
+/// Run every check against `host`
+pub fn evaluate(checks: &[ComplianceCheck], host: &dyn Host, device_id: &str, now: u64) -> ComplianceReport {
+    let results: Vec<CheckResult> = checks
+        .iter()
+        .map(|check| {
+            let (status, observed, detail) = match observe(&check.probe, host) {
+                Observation::Value(value) if check.expected.matches(&value) => (CheckStatus::Pass, Some(value), None),
+                Observation::Value(value) => (CheckStatus::Fail, Some(value), None),
+                Observation::NotApplicable(why) => (CheckStatus::NotApplicable, None, Some(why)),
+                Observation::Error(why) => (CheckStatus::Error, None, Some(why)),
+            };
+            CheckResult {
+                id: check.id.clone(),
+                name: check.name.clone(),
+                probe: check.probe.clone(),
+                status,
+                expected: check.expected.clone(),
+                observed,
+                detail,
+                severity: check.severity.clone(),
+                weight: check.weight,
+            }
+        })
+        .collect();
+
+    let count = |status| results.iter().filter(|r| r.status == status).count();
+    let weight = |counted: &dyn Fn(CheckStatus) -> bool| -> u64 {
+        results.iter().filter(|r| counted(r.status)).map(|r| r.weight as u64).sum()
+    };
+    let applicable = weight(&|s| s != CheckStatus::NotApplicable);
+    let score = (applicable > 0).then(|| (weight(&|s| s == CheckStatus::Pass) * 100 / applicable) as u8);
+    ComplianceReport {
+        device_id: device_id.to_string(),
+        checked_at: now,
+        score,
+        passed: count(CheckStatus::Pass),
+        failed: count(CheckStatus::Fail),
+        errors: count(CheckStatus::Error),
+        not_applicable: count(CheckStatus::NotApplicable),
+        results,
+    }
+}
+
+/// Parse the served checks, skipping the ones that are malformed
+pub fn parse_checks(served: Vec<Value>) -> Vec<ComplianceCheck> {
+    served
+        .into_iter()
+        .filter_map(|check| match serde_json::from_value::<ComplianceCheck>(check.clone()) {
+            Ok(check) => Some(check),
+            Err(e) => {
+                warn!(check = %check, "Skipping compliance check: {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// GET the checks; an endpoint the backend does not have means none
+pub async fn fetch_checks(backend: &Backend, token: &str) -> Result<Vec<ComplianceCheck>, AgentError> {
+    let url = format!("{}?device_id={}", backend.url(CHECKS_PATH), get_device_id());
+    let response = api::send(backend.client().get(&url).header("Authorization", format!("Bearer {}", token))).await?;
+    let response = match api::ensure_success(response).await {
+        Ok(response) => response,
+        Err(AgentError::Server { status: 404, .. }) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let served: Vec<Value> = response.json().await.map_err(|e| AgentError::Parse(e.to_string()))?;
+    Ok(parse_checks(served))
+}
+
+/// Fetch the checks, run them on this device and send the report; None
+/// when the server defines no checks
+pub async fn run_checks(state: &AgentState, token: &str) -> Result<Option<ComplianceReport>, AgentError> {
+    let checks = fetch_checks(state.backend(), token).await?;
+    if checks.is_empty() {
+        return Ok(None);
+    }
+    let timeout = Duration::from_secs(state.settings.compliance.probe_timeout_secs);
+    let report = tokio::task::spawn_blocking(move || {
+        evaluate(&checks, &LiveHost::new(timeout), &get_device_id(), unix_now())
+    })
+    .await
+    .map_err(|e| AgentError::Io(format!("compliance probes panicked: {}", e)))?;
+    info!(
+        score = ?report.score,
+        passed = report.passed,
+        failed = report.failed,
+        errors = report.errors,
+        "Compliance checks finished"
+    );
+
+    *lock(&state.compliance) = Some(report.clone());
+    let mut monitor = state.monitor.lock().await;
+    monitor.enqueue(REPORT_ENDPOINT, serde_json::to_value(&report)?);
+    monitor.flush_outbox(token).await?;
+    Ok(Some(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Recorded outputs; anything else is "not installed"
+    struct FakeHost {
+        os: &'static str,
+        commands: HashMap<String, String>,
+        files: HashMap<String, String>,
+    }
+
+    impl FakeHost {
+        fn linux(commands: &[(&str, &str)], files: &[(&str, &str)]) -> Self {
+            Self {
+                os: "linux",
+                commands: commands.iter().map(|(c, o)| (c.to_string(), o.to_string())).collect(),
+                files: files.iter().map(|(p, c)| (p.to_string(), c.to_string())).collect(),
+            }
+        }
+    }
+
+    impl Host for FakeHost {
+        fn os(&self) -> &str {
+            self.os
+        }
+
+        fn run(&self, program: &str, args: &[&str]) -> Option<String> {
+            let command = std::iter::once(program).chain(args.iter().copied()).collect::<Vec<_>>().join(" ");
+            self.commands.get(&command).cloned()
+        }
+
+        fn read(&self, path: &str) -> Option<String> {
+            self.files.get(path).cloned()
+        }
+    }
+
+    #[test]
+    fn linux_probes_parse_tool_output() {
+        let host = FakeHost::linux(
+            &[
+                ("findmnt -no SOURCE /", "/dev/mapper/root\n"),
+                ("lsblk -s -no TYPE /dev/mapper/root", "crypt\npart\ndisk\n"),
+                ("ufw status", "Status: inactive\n"),
+                ("nft list ruleset", "table inet filter {\n  chain input {\n    type filter hook input priority 0;\n"),
+                (
+                    "apt-get -s -o Debug::NoLocking=1 upgrade",
+                    "Inst libssl3 [3.0.2] (3.0.2-0ubuntu1.15 Ubuntu:22.04/jammy-security [amd64])\n\
+                     Inst vim [2:8.2] (2:8.2.3995-1ubuntu2.16 Ubuntu:22.04/jammy-updates [amd64])\n\
+                     Conf libssl3 (3.0.2-0ubuntu1.15 Ubuntu:22.04/jammy-security [amd64])\n",
+                ),
+            ],
+            &[
+                ("/etc/ssh/sshd_config", "# PermitRootLogin yes\nPort 22\nPermitRootLogin no\nPermitRootLogin yes\n"),
+                ("/etc/apt/apt.conf.d/20auto-upgrades", "APT::Periodic::Update-Package-Lists \"1\";\n"),
+            ],
+        );
+        assert_eq!(observe("disk_encryption", &host), Observation::Value(json!(true)));
+        assert_eq!(observe("firewall_enabled", &host), Observation::Value(json!(true)));
+        assert_eq!(observe("pending_security_updates", &host), Observation::Value(json!(1)));
+        assert_eq!(observe("ssh_root_login", &host), Observation::Value(json!("no")));
+        assert_eq!(observe("automatic_updates_enabled", &host), Observation::Value(json!(false)));
+        assert!(matches!(observe("screen_lock_enabled", &host), Observation::NotApplicable(_)));
+        assert!(matches!(observe("tpm_present", &host), Observation::Error(_)));
+    }
+
+    #[test]
+    fn report_scores_weighted_applicable_checks() {
+        let host = FakeHost::linux(
+            &[("sshd -T", "port 22\npermitrootlogin without-password\n"), ("ufw status", "Status: inactive\n")],
+            &[],
+        );
+        let checks = parse_checks(serde_json::from_value(json!([
+            { "id": 1, "probe": "ssh_root_login", "expected": "Without-Password", "weight": 3 },
+            { "id": 2, "probe": "firewall_enabled", "expected": true },
+            { "id": 3, "probe": "pending_security_updates", "expected": { "op": "<=", "value": 0 } },
+            { "id": 4, "probe": "disk_encryption", "expected": true },
+            { "id": 5, "probe": "screen_lock_enabled" },
+        ])).unwrap());
+        assert_eq!(checks.len(), 4);
+
+        let report = evaluate(&checks, &host, "laptop", 100);
+        let statuses: Vec<CheckStatus> = report.results.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            [CheckStatus::Pass, CheckStatus::Fail, CheckStatus::NotApplicable, CheckStatus::Error]
+        );
+        // 3 of 5 applicable weight passes; the error counts against the score
+        assert_eq!(report.score, Some(60));
+        assert_eq!((report.passed, report.failed, report.errors, report.not_applicable), (1, 1, 1, 1));
+
+        let nothing_applies = evaluate(&checks[2..3], &host, "laptop", 100);
+        assert_eq!(nothing_applies.score, None);
+    }
+}
@@ -0,0 +1,218 @@
+// ============================================================================
+// Agent Error Module
+// ============================================================================
+// One error type for every command and helper. What kind of failure it was
+// decides what happens next:
+//
+// | kind          | cause                                | retryable | re-auth |
+// |---------------|--------------------------------------|-----------|---------|
+// | network       | connect / DNS / TLS / broken body    | yes       |         |
+// | timeout       | settings.api.request_timeout_secs,   | yes       |         |
+// |               | or the server answered 408           |           |         |
+// | unauthorized  | 401, or no monitoring token set      |           | yes     |
+// | forbidden     | 403                                  |           |         |
+// | rate_limited  | 429 (honours Retry-After)            | yes       |         |
+// | server        | any other non-2xx (5xx retryable)    | 5xx only  |         |
+// | parse         | JSON we could not read or write      |           |         |
+// | io            | local files, sockets                 |           |         |
+// | policy        | request refused by the agent itself  |           |         |
+//
+// Retryable errors make the supervisor (supervisor.rs) retry with backoff
+// and the outbox keep the record; re-auth errors raise "auth expired".
+//
+// Commands return it as structured JSON to the frontend:
+// { "kind": "rate_limited", "message": "Rate limited by the server",
+//   "retryable": true, "retry_after_secs": 30 }
+// (`status` is added for server errors)
+// ============================================================================
+
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::time::Duration;
+
+/// Longest server error body kept in the message
+const MAX_BODY_CHARS: usize = 200;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum AgentError {
+    #[error("Network error: {0}")]
+    Network(String),
+    #[error("Request timed out: {0}")]
+    Timeout(String),
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+    #[error("Rate limited by the server")]
+    RateLimited { retry_after_secs: Option<u64> },
+    #[error("API error: {status} {message}")]
+    Server { status: u16, message: String },
+    #[error("Parse error: {0}")]
+    Parse(String),
+    #[error("I/O error: {0}")]
+    Io(String),
+    #[error("Policy error: {0}")]
+    Policy(String),
+}
+
+impl AgentError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AgentError::Network(_) => "network",
+            AgentError::Timeout(_) => "timeout",
+            AgentError::Unauthorized(_) => "unauthorized",
+            AgentError::Forbidden(_) => "forbidden",
+            AgentError::RateLimited { .. } => "rate_limited",
+            AgentError::Server { .. } => "server",
+            AgentError::Parse(_) => "parse",
+            AgentError::Io(_) => "io",
+            AgentError::Policy(_) => "policy",
+        }
+    }
+
+    /// Whether trying the same request again later can succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            AgentError::Network(_) | AgentError::Timeout(_) | AgentError::RateLimited { .. } => true,
+            AgentError::Server { status, .. } => *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// Whether the token was rejected and the user has to log in again
+    pub fn needs_reauth(&self) -> bool {
+        matches!(self, AgentError::Unauthorized(_))
+    }
+
+    /// How long the server asked us to wait (429 Retry-After)
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            AgentError::RateLimited {
+                retry_after_secs: Some(secs),
+            } => Some(Duration::from_secs(*secs)),
+            _ => None,
+        }
+    }
+
+    /// Classify a non-success HTTP status; `body` is the response text
+    pub fn from_status(status: StatusCode, body: &str, retry_after_secs: Option<u64>) -> Self {
+        let body = body.trim();
+        let message = if body.is_empty() {
+            status.canonical_reason().unwrap_or("").to_string()
+        } else {
+            body.chars().take(MAX_BODY_CHARS).collect()
+        };
+        match status {
+            StatusCode::UNAUTHORIZED => AgentError::Unauthorized(message),
+            StatusCode::FORBIDDEN => AgentError::Forbidden(message),
+            StatusCode::REQUEST_TIMEOUT => AgentError::Timeout(message),
+            StatusCode::TOO_MANY_REQUESTS => AgentError::RateLimited { retry_after_secs },
+            _ => AgentError::Server {
+                status: status.as_u16(),
+                message,
+            },
+        }
+    }
+
+    /// Build the error for a non-success response, reading its body
+    pub async fn from_response(response: reqwest::Response) -> Self {
+        let status = response.status();
+        // Only the delay-seconds form; HTTP dates are not worth a parser here
+        let retry_after_secs = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse().ok());
+        let body = response.text().await.unwrap_or_default();
+        Self::from_status(status, &body, retry_after_secs)
+    }
+}
+
+impl From<reqwest::Error> for AgentError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            AgentError::Timeout(e.to_string())
+        } else if e.is_decode() {
+            AgentError::Parse(e.to_string())
+        } else {
+            AgentError::Network(e.to_string())
+        }
+    }
+}
+
+impl From<serde_json::Error> for AgentError {
+    fn from(e: serde_json::Error) -> Self {
+        AgentError::Parse(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for AgentError {
+    fn from(e: std::io::Error) -> Self {
+        AgentError::Io(e.to_string())
+    }
+}
+
+/// Structured JSON for Tauri commands
+impl Serialize for AgentError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("AgentError", 5)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("retryable", &self.is_retryable())?;
+        match self {
+            AgentError::Server { status, .. } => state.serialize_field("status", status)?,
+            AgentError::RateLimited { retry_after_secs } => {
+                state.serialize_field("retry_after_secs", retry_after_secs)?
+            }
+            _ => {}
+        }
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn statuses_map_to_kinds() {
+        let kind = |code: u16| AgentError::from_status(StatusCode::from_u16(code).unwrap(), "", None).kind();
+        assert_eq!(kind(401), "unauthorized");
+        assert_eq!(kind(403), "forbidden");
+        assert_eq!(kind(408), "timeout");
+        assert_eq!(kind(429), "rate_limited");
+        assert_eq!(kind(400), "server");
+        assert_eq!(kind(503), "server");
+    }
+
+    #[test]
+    fn only_transient_failures_are_retryable() {
+        let error = |code: u16| AgentError::from_status(StatusCode::from_u16(code).unwrap(), "", Some(30));
+        assert!(error(503).is_retryable());
+        assert!(error(429).is_retryable());
+        assert_eq!(error(429).retry_after(), Some(Duration::from_secs(30)));
+        assert!(!error(400).is_retryable());
+        assert!(!error(401).is_retryable());
+        assert!(error(401).needs_reauth());
+        assert!(!AgentError::Parse("bad".into()).is_retryable());
+    }
+
+    #[test]
+    fn serializes_as_structured_json() {
+        let error = AgentError::from_status(StatusCode::INTERNAL_SERVER_ERROR, "db down\n", None);
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({
+                "kind": "server",
+                "message": "API error: 500 db down",
+                "retryable": true,
+                "status": 500,
+            })
+        );
+
+        let error = AgentError::RateLimited { retry_after_secs: Some(30) };
+        assert_eq!(serde_json::to_value(&error).unwrap()["retry_after_secs"], json!(30));
+    }
+}
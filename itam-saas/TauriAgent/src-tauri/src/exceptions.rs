@@ -0,0 +1,438 @@
+// ============================================================================
+// Violation Justification & Exception Module
+// ============================================================================
+// Gives the user a way to respond to a `violation-detected` alert:
+//
+// 1. UI shows the alert (the event carries the server's alert_id)
+// 2. User submits a justification, optionally requesting a temporary
+//    exception: PATCH /api/alerts/:id
+//      { "justification": "...", "exception_requested": true,
+//        "exception_duration_minutes": 120 }
+// 3. Admin reviews it in the dashboard
+// 4. Agent picks up the decision from GET /api/alerts/device/:deviceId
+//    (on demand from the UI, and on every policy sync). An alert row with
+//    exception_status = "approved" becomes a local exception until
+//    exception_expires_at (or, when the server sends no expiry, for the
+//    requested duration from the moment it was seen, at most
+//    MAX_EXCEPTION_SECS)
+// 5. scan_processes skips matches covered by an active exception
+//
+// Exceptions match the alert's rule_id when both the exception and the
+// match carry one, so an exception for one rule never silences another rule
+// on the same process. Without a rule_id on either side they fall back to
+// the exact (lowercased) process name that was reported.
+//
+// The store is an Exceptions handle on AgentState, shared with the monitor
+// and its scanner.
+//
+// Approved exceptions are never read from disk: any local user can write
+// the config directory. They are rebuilt from the alert rows on every
+// refresh, so an exception the admin revokes ends at the next sync.
+//
+// Persistence: <config_dir>/tauriagent/exceptions.json holds the submitted
+// requests only (alert id, requested duration, when approval was first
+// seen), so a decision made while the agent was stopped is still picked up.
+// ============================================================================
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+use crate::api::{self, unix_now, Backend};
+use crate::error::AgentError;
+use crate::settings::agent_config_dir;
+
+/// Longest exception derived from a local request; expiries sent by the
+/// server are used as they are
+pub const MAX_EXCEPTION_SECS: u64 = 7 * 86_400;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalException {
+    pub alert_id: i64,
+    pub rule_id: Option<i64>,
+    pub process_name: String,
+    /// Seconds since UNIX epoch
+    pub expires_at: u64,
+}
+
+impl LocalException {
+    /// Whether this exception covers a match of `rule_id` on `process_name`
+    pub fn covers(&self, rule_id: Option<i64>, process_name: &str) -> bool {
+        match (self.rule_id, rule_id) {
+            (Some(excepted), Some(matched)) => excepted == matched,
+            _ => self.process_name == process_name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingRequest {
+    pub alert_id: i64,
+    pub duration_secs: u64,
+    /// First refresh that saw the request approved, seconds since UNIX epoch
+    #[serde(default)]
+    pub approved_at: Option<u64>,
+}
+
+impl PendingRequest {
+    /// Expiry derived from the request, for approvals without one
+    fn expires_at(&self) -> Option<u64> {
+        self.approved_at
+            .map(|at| at.saturating_add(self.duration_secs.min(MAX_EXCEPTION_SECS)))
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ExceptionStore {
+    /// Rebuilt from the server, never persisted
+    #[serde(skip)]
+    approved: Vec<LocalException>,
+    pending: Vec<PendingRequest>,
+}
+
+/// The fields of a security_alerts row the agent cares about
+#[derive(Debug, Deserialize)]
+pub struct DeviceAlert {
+    pub id: i64,
+    #[serde(default)]
+    pub app_name: Option<String>,
+    #[serde(default)]
+    pub app_detected: Option<String>,
+    #[serde(default)]
+    pub rule_id: Option<i64>,
+    #[serde(default)]
+    pub exception_status: Option<String>,
+    /// ISO 8601 timestamp or seconds since UNIX epoch
+    #[serde(default)]
+    pub exception_expires_at: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JustificationRequest {
+    pub justification: String,
+    pub exception_requested: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exception_duration_minutes: Option<u64>,
+}
+
+fn get_store_path() -> PathBuf {
+    agent_config_dir().join("exceptions.json")
+}
+
+fn load_store() -> ExceptionStore {
+    fs::read_to_string(get_store_path())
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+fn save_store(store: &ExceptionStore) {
+    let result = serde_json::to_string_pretty(store)
+        .map_err(|e| format!("Serialize error: {}", e))
+        .and_then(|json| fs::write(get_store_path(), json).map_err(|e| format!("File write error: {}", e)));
+    if let Err(e) = result {
+        warn!("Failed to persist exceptions: {}", e);
+    }
+}
+
+fn parse_expiry(value: &serde_json::Value) -> Option<u64> {
+    match value {
+        serde_json::Value::Number(n) => n.as_u64(),
+        serde_json::Value::String(s) => chrono::DateTime::parse_from_rfc3339(s)
+            .ok()
+            .and_then(|t| u64::try_from(t.timestamp()).ok()),
+        _ => None,
+    }
+}
+
+/// Approved exceptions (in memory) and exception requests (exceptions.json).
+/// Clones share the same store.
+#[derive(Clone)]
+pub struct Exceptions {
+    store: Arc<Mutex<ExceptionStore>>,
+}
+
+impl Exceptions {
+    /// Start from the persisted requests, with no approved exception until
+    /// the first refresh
+    pub fn load() -> Self {
+        Self {
+            store: Arc::new(Mutex::new(load_store())),
+        }
+    }
+
+    /// Whether a match is covered by an approved, unexpired exception
+    pub fn is_excepted(&self, rule_id: Option<i64>, process_name: &str) -> bool {
+        let mut store = self.store.lock().unwrap();
+        let now = unix_now();
+        store.approved.retain(|e| e.expires_at > now);
+        store.approved.iter().any(|e| e.covers(rule_id, process_name))
+    }
+
+    /// Currently active exceptions (for the UI)
+    pub fn active(&self) -> Vec<LocalException> {
+        let now = unix_now();
+        self.store
+            .lock()
+            .unwrap()
+            .approved
+            .iter()
+            .filter(|e| e.expires_at > now)
+            .cloned()
+            .collect()
+    }
+
+    /// Exception requests that are waiting for a decision or still running
+    pub fn pending_requests(&self) -> usize {
+        self.store.lock().unwrap().pending.len()
+    }
+
+    /// Remember a submitted exception request so its decision gets polled
+    pub fn record_request(&self, alert_id: i64, duration_secs: u64) {
+        let mut store = self.store.lock().unwrap();
+        store.pending.retain(|p| p.alert_id != alert_id);
+        store.pending.push(PendingRequest {
+            alert_id,
+            duration_secs: duration_secs.min(MAX_EXCEPTION_SECS),
+            approved_at: None,
+        });
+        save_store(&store);
+    }
+
+    /// Fetch alert statuses and rebuild the approved exceptions from them
+    pub async fn refresh(
+        &self,
+        backend: &Backend,
+        token: &str,
+        device_id: &str,
+    ) -> Result<(Vec<serde_json::Value>, Vec<LocalException>), AgentError> {
+        let rows = fetch_device_alerts(backend, token, device_id).await?;
+        let alerts: Vec<DeviceAlert> = rows
+            .iter()
+            .filter_map(|row| serde_json::from_value(row.clone()).ok())
+            .collect();
+        let applied = self.apply_alert_statuses(&alerts);
+        Ok((rows, applied))
+    }
+
+    /// Replace the approved exceptions with the alerts the server currently
+    /// approves; returns the ones that were not applied before. Denied
+    /// requests and requests whose exception ran out are dropped.
+    pub fn apply_alert_statuses(&self, alerts: &[DeviceAlert]) -> Vec<LocalException> {
+        let mut store = self.store.lock().unwrap();
+        let now = unix_now();
+        let mut approved = Vec::new();
+
+        for alert in alerts {
+            let status = alert
+                .exception_status
+                .as_deref()
+                .unwrap_or("")
+                .to_lowercase();
+
+            match status.as_str() {
+                "approved" => {
+                    let requested = store
+                        .pending
+                        .iter_mut()
+                        .find(|p| p.alert_id == alert.id)
+                        .and_then(|p| {
+                            p.approved_at.get_or_insert(now);
+                            p.expires_at()
+                        });
+                    let expires_at = alert
+                        .exception_expires_at
+                        .as_ref()
+                        .and_then(parse_expiry)
+                        .or(requested);
+                    let Some(expires_at) = expires_at.filter(|t| *t > now) else {
+                        continue;
+                    };
+
+                    approved.push(LocalException {
+                        alert_id: alert.id,
+                        rule_id: alert.rule_id,
+                        process_name: alert
+                            .app_detected
+                            .clone()
+                            .or_else(|| alert.app_name.clone())
+                            .unwrap_or_default()
+                            .to_lowercase(),
+                        expires_at,
+                    });
+                }
+                "denied" | "rejected" => {
+                    store.pending.retain(|p| p.alert_id != alert.id);
+                }
+                _ => {}
+            }
+        }
+
+        store.pending.retain(|p| p.expires_at().is_none_or(|t| t > now));
+        let applied = approved
+            .iter()
+            .filter(|e| !store.approved.iter().any(|old| old.alert_id == e.alert_id))
+            .cloned()
+            .collect();
+        store.approved = approved;
+        save_store(&store);
+        applied
+    }
+}
+
+/// GET /api/alerts/device/:deviceId, with the device id percent-encoded
+fn device_alerts_url(api_url: &str, device_id: &str) -> Result<reqwest::Url, AgentError> {
+    let invalid = |e: String| AgentError::Network(format!("Invalid API URL {}: {}", api_url, e));
+    let mut url = reqwest::Url::parse(api_url).map_err(|e| invalid(e.to_string()))?;
+    url.path_segments_mut()
+        .map_err(|_| invalid("cannot be a base".to_string()))?
+        .pop_if_empty()
+        .extend(["api", "alerts", "device", device_id]);
+    Ok(url)
+}
+
+/// Fetch this device's alerts (raw rows, passed through to the UI)
+pub async fn fetch_device_alerts(
+    backend: &Backend,
+    token: &str,
+    device_id: &str,
+) -> Result<Vec<serde_json::Value>, AgentError> {
+    let url = device_alerts_url(backend.base_url(), device_id)?;
+
+    let client = backend.client();
+    let response = api::send(client.get(url).header("Authorization", format!("Bearer {}", token))).await?;
+    let response = api::ensure_success(response).await?;
+
+    response
+        .json()
+        .await
+        .map_err(|e| AgentError::Parse(e.to_string()))
+}
+
+/// Send a justification / exception request for one alert
+pub async fn submit_justification(
+    backend: &Backend,
+    token: &str,
+    alert_id: i64,
+    request: &JustificationRequest,
+) -> Result<serde_json::Value, AgentError> {
+    let url = backend.url(&format!("/api/alerts/{}", alert_id));
+
+    let client = backend.client();
+    let response = api::send(
+        client
+            .patch(&url)
+            .header("Authorization", format!("Bearer {}", token))
+            .json(request),
+    )
+    .await?;
+    // Rejections carry the server's explanation in the error message
+    let response = api::ensure_success(response).await?;
+
+    response
+        .json()
+        .await
+        .map_err(|e| AgentError::Parse(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exception(rule_id: Option<i64>, process_name: &str) -> LocalException {
+        LocalException {
+            alert_id: 1,
+            rule_id,
+            process_name: process_name.to_string(),
+            expires_at: u64::MAX,
+        }
+    }
+
+    #[test]
+    fn rule_exception_only_covers_its_own_rule() {
+        let excepted = exception(Some(31), "steam");
+
+        assert!(excepted.covers(Some(31), "steam"));
+        assert!(excepted.covers(Some(31), "steam-renamed"));
+        assert!(!excepted.covers(Some(32), "steam"));
+    }
+
+    #[test]
+    fn process_name_is_compared_without_a_rule_id() {
+        assert!(exception(None, "steam").covers(Some(32), "steam"));
+        assert!(!exception(None, "steam").covers(Some(32), "discord"));
+        assert!(exception(Some(31), "steam").covers(None, "steam"));
+        assert!(!exception(Some(31), "steam").covers(None, "discord"));
+    }
+
+    #[test]
+    fn device_id_is_encoded_into_the_path() {
+        let url = device_alerts_url("https://itam.example.com/", "host/01 #a").unwrap();
+        assert_eq!(url.as_str(), "https://itam.example.com/api/alerts/device/host%2F01%20%23a");
+
+        let url = device_alerts_url("https://itam.example.com/prefix", "dev-1").unwrap();
+        assert_eq!(url.as_str(), "https://itam.example.com/prefix/api/alerts/device/dev-1");
+    }
+
+    fn alert(id: i64, status: &str, expires_at: Option<serde_json::Value>) -> DeviceAlert {
+        DeviceAlert {
+            id,
+            app_name: Some("Steam".to_string()),
+            app_detected: None,
+            rule_id: Some(31),
+            exception_status: Some(status.to_string()),
+            exception_expires_at: expires_at,
+        }
+    }
+
+    fn in_memory() -> Exceptions {
+        Exceptions {
+            store: Arc::new(Mutex::new(ExceptionStore::default())),
+        }
+    }
+
+    #[test]
+    fn stored_approvals_are_not_trusted() {
+        let store: ExceptionStore = serde_json::from_str(
+            r#"{ "approved": [{ "alert_id": 1, "rule_id": null, "process_name": "steam",
+                                "expires_at": 18446744073709551615 }],
+                 "pending": [] }"#,
+        )
+        .unwrap();
+        assert!(store.approved.is_empty());
+    }
+
+    #[test]
+    fn revoked_exception_is_dropped_on_refresh() {
+        let exceptions = in_memory();
+        let later = serde_json::json!(unix_now() + 3600);
+
+        let applied = exceptions.apply_alert_statuses(&[alert(5, "approved", Some(later.clone()))]);
+        assert_eq!(applied.len(), 1);
+        assert!(exceptions.is_excepted(Some(31), "steam"));
+
+        // Unchanged on the next refresh: nothing newly applied
+        assert!(exceptions.apply_alert_statuses(&[alert(5, "approved", Some(later))]).is_empty());
+
+        exceptions.apply_alert_statuses(&[alert(5, "revoked", None)]);
+        assert!(!exceptions.is_excepted(Some(31), "steam"));
+    }
+
+    #[test]
+    fn requested_duration_is_capped() {
+        let exceptions = in_memory();
+        exceptions.store.lock().unwrap().pending.push(PendingRequest {
+            alert_id: 5,
+            duration_secs: u64::MAX,
+            approved_at: None,
+        });
+
+        let applied = exceptions.apply_alert_statuses(&[alert(5, "approved", None)]);
+        assert!(applied[0].expires_at <= unix_now() + MAX_EXCEPTION_SECS);
+
+        // Without a request or a server expiry there is nothing to go by
+        assert!(exceptions.apply_alert_statuses(&[alert(6, "approved", None)]).is_empty());
+    }
+}
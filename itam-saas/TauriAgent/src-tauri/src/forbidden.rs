@@ -2,55 +2,167 @@
 // Forbidden App Detection Module
 // ============================================================================
 // This module handles:
-// - Fetching forbidden app list from API
+// - Fetching forbidden app list from API (conditional, via ETag)
 // - Caching the list locally for offline operation
-// - Scanning running processes against forbidden list
-// - Reporting violations to backend API
+// - Tracking cache age so a stale policy can be reported
+// - Scanning running processes against forbidden list, honouring rule
+//   schedules, scope and exceptions (see policy.rs)
+// - Reporting violations to backend API, with evidence (see evidence.rs)
 // - Preventing duplicate alerts (PID tracking)
 //
-// DEPENDENCY NOTE: Uses sysinfo v0.30.13
-// - API changed from v0.29: ProcessExt/SystemExt traits removed
-// - process.name() now returns &str directly (not OsStr)
-// - No need to import ProcessExt/SystemExt anymore
+// Processes are read from a ProcessTable (see process_source.rs), never from
+// sysinfo directly, so scans can be replayed from recorded fixtures.
 // ============================================================================
 
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::fs;
-use std::path::PathBuf;
-use sysinfo::System; // v0.30 API: System struct only
+use std::path::{Path, PathBuf};
+use tracing::warn;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::api::{self, unix_now, Backend};
+use crate::aggregate::ViolationAggregate;
+use crate::error::AgentError;
+use crate::evidence::{EvidenceCollector, ViolationEvidence};
+use crate::exceptions::Exceptions;
+use crate::netpolicy::NetworkEvidence;
+use crate::policy::{
+    rule_active_for_device, rule_applies_to_user, warn_unknown_timezone, PolicyContext, RuleExceptions, RuleMode,
+    RuleSchedule, RuleScope,
+};
+use crate::process_source::ProcessTable;
+use crate::settings::{agent_config_dir, DeviceSettings};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ForbiddenApp {
+    /// Server-side rule id (forbidden_apps.id), absent in old caches
+    #[serde(default)]
+    pub id: Option<i64>,
     pub process_name: String,
     pub severity: String,
+    /// "enforce" or "audit", see policy.rs
+    #[serde(default)]
+    pub mode: RuleMode,
+    /// Optional qualifiers, see policy.rs. None = always / everywhere
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<RuleSchedule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<RuleScope>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exceptions: Option<RuleExceptions>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl ForbiddenApp {
+    /// Schedule, device scope and device exceptions allow this rule here and now
+    pub fn is_active_for_device(&self, ctx: &PolicyContext) -> bool {
+        rule_active_for_device(
+            self.schedule.as_ref(),
+            self.scope.as_ref(),
+            self.exceptions.as_ref(),
+            ctx,
+        )
+    }
+
+    /// User scope and user exceptions allow this rule for a process owner
+    pub fn applies_to_user(&self, os_user: Option<&str>) -> bool {
+        rule_applies_to_user(self.scope.as_ref(), self.exceptions.as_ref(), os_user)
+    }
+}
+
+/// On-disk copy of the forbidden list.
+///
+/// `last_updated` is the last time the server confirmed this list, either by
+/// sending it (200) or by answering 304 Not Modified to our ETag.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ForbiddenAppCache {
     pub apps: Vec<ForbiddenApp>,
     pub last_updated: u64,
+    #[serde(default)]
+    pub etag: Option<String>,
 }
 
+/// Log rules whose schedule names an unknown time zone (once per load or sync)
+pub fn warn_unknown_timezones(apps: &[ForbiddenApp]) {
+    for app in apps {
+        warn_unknown_timezone(&app.process_name, app.schedule.as_ref());
+    }
+}
+
+/// Result of a conditional fetch against /api/forbidden-apps
+#[derive(Debug)]
+pub enum FetchOutcome {
+    NotModified,
+    Modified {
+        apps: Vec<ForbiddenApp>,
+        etag: Option<String>,
+    },
+}
+
+/// Rules added and removed by a sync, emitted as `forbidden-list-updated`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ForbiddenListDiff {
+    pub added: Vec<ForbiddenApp>,
+    pub removed: Vec<ForbiddenApp>,
+    pub total: usize,
+}
+
+impl ForbiddenListDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Outcome of `sync_forbidden_list`: the cache to use from now on and what changed
+#[derive(Debug)]
+pub struct SyncOutcome {
+    pub cache: ForbiddenAppCache,
+    pub diff: ForbiddenListDiff,
+    pub not_modified: bool,
+}
+
+/// Age of the cached policy, emitted as `policy-stale` when `stale` flips
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyStaleness {
+    pub stale: bool,
+    pub age_secs: u64,
+    pub max_age_secs: u64,
+    pub last_updated: u64,
+}
+
+/// Payload for POST /api/alerts.
+///
+/// The first four fields are the original contract and are always sent.
+/// `rule_id` and `evidence` are optional additions that older servers ignore.
+/// `mode` is only serialized for audit matches, which never reach /api/alerts.
+/// `alert_id` is filled in from the server's response once reported, so the
+/// UI can attach a justification to the alert.
+/// `network` is set for hits of network destination rules (netpolicy.rs).
+/// `aggregate` summarises all PIDs of the same device, rule and executable
+/// (aggregate.rs); process_id stays the first PID of the group.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ViolationReport {
     pub device_id: String,
     pub app_detected: String,
     pub severity: String,
     pub process_id: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub evidence: Option<ViolationEvidence>,
+    #[serde(default, skip_serializing_if = "RuleMode::is_enforce")]
+    pub mode: RuleMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alert_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<NetworkEvidence>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregate: Option<ViolationAggregate>,
 }
 
-// Track reported PIDs to avoid duplicate alerts
-static mut REPORTED_PIDS: Option<HashSet<u32>> = None;
-
 fn get_cache_path() -> PathBuf {
-    let mut path = dirs::config_dir().unwrap_or_else(|| PathBuf::from("."));
-    path.push("tauriagent");
-    if !path.exists() {
-        let _ = fs::create_dir_all(&path);
-    }
-    path.push("forbidden_cache.json");
-    path
+    agent_config_dir().join("forbidden_cache.json")
 }
 
 // ============================================================================
@@ -62,151 +174,262 @@ fn get_cache_path() -> PathBuf {
 //
 // FIXED: Changed from incorrect /api/forbidden-apps/list to /api/forbidden-apps
 // The backend server.js only has /api/forbidden-apps route, not /list variant
+//
+// CONDITIONAL FETCH:
+// - Express attaches an ETag to every JSON response and answers a matching
+//   If-None-Match with 304 Not Modified, so an unchanged list costs no body
+// - Pass the ETag from the last successful fetch (None forces a full fetch)
 // ============================================================================
-pub async fn fetch_forbidden_list(api_url: &str, token: &str) -> Result<Vec<ForbiddenApp>, String> {
-    let url = format!("{}/api/forbidden-apps", api_url); // Correct endpoint
+pub async fn fetch_forbidden_list(
+    backend: &Backend,
+    token: &str,
+    etag: Option<&str>,
+) -> Result<FetchOutcome, AgentError> {
+    let url = backend.url("/api/forbidden-apps"); // Correct endpoint
     
-    let client = reqwest::Client::new();
-    let response = client
+    let client = backend.client();
+    let mut request = client
         .get(&url)
-        .header("Authorization", format!("Bearer {}", token))
-        .send()
-        .await
-        .map_err(|e| format!("Network error: {}", e))?;
+        .header("Authorization", format!("Bearer {}", token));
+    if let Some(etag) = etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    
+    let response = api::send(request).await?;
     
-    if !response.status().is_success() {
-        return Err(format!("API error: {}", response.status()));
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(FetchOutcome::NotModified);
     }
     
+    let response = api::ensure_success(response).await?;
+    
+    let etag = response
+        .headers()
+        .get(ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    
     let apps: Vec<ForbiddenApp> = response
         .json()
         .await
-        .map_err(|e| format!("Parse error: {}", e))?;
+        .map_err(|e| AgentError::Parse(e.to_string()))?;
     
-    Ok(apps)
+    Ok(FetchOutcome::Modified { apps, etag })
 }
 
 /// Save forbidden list to local cache
-pub fn cache_to_disk(apps: &[ForbiddenApp]) -> Result<(), String> {
-    let cache = ForbiddenAppCache {
-        apps: apps.to_vec(),
-        last_updated: std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-    };
-    
-    let json = serde_json::to_string_pretty(&cache)
-        .map_err(|e| format!("Serialize error: {}", e))?;
-    
-    fs::write(get_cache_path(), json)
-        .map_err(|e| format!("File write error: {}", e))?;
-    
-    Ok(())
+pub fn cache_to_disk(cache: &ForbiddenAppCache) -> Result<(), AgentError> {
+    write_cache(&get_cache_path(), cache)
+}
+
+/// Load forbidden list from local cache (empty cache if none saved yet)
+pub fn load_from_cache() -> Result<ForbiddenAppCache, AgentError> {
+    read_cache(&get_cache_path())
 }
 
-/// Load forbidden list from local cache
-pub fn load_from_cache() -> Result<Vec<ForbiddenApp>, String> {
+/// Delete the local cache, so the next sync fetches the full list
+pub fn clear_cache() -> Result<(), AgentError> {
     let path = get_cache_path();
-    
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+fn write_cache(path: &Path, cache: &ForbiddenAppCache) -> Result<(), AgentError> {
+    let json = serde_json::to_string_pretty(cache)?;
+    fs::write(path, json)?;
+    Ok(())
+}
+
+fn read_cache(path: &Path) -> Result<ForbiddenAppCache, AgentError> {
     if !path.exists() {
-        return Ok(Vec::new());
+        return Ok(ForbiddenAppCache::default());
     }
     
-    let json = fs::read_to_string(path)
-        .map_err(|e| format!("File read error: {}", e))?;
-    
-    let cache: ForbiddenAppCache = serde_json::from_str(&json)
-        .map_err(|e| format!("Parse error: {}", e))?;
-    
-    Ok(cache.apps)
+    let json = fs::read_to_string(path)?;
+    let cache: ForbiddenAppCache = serde_json::from_str(&json)?;
+    Ok(cache)
 }
 
-/// Scan running processes for forbidden apps
-/// 
-/// SYSINFO v0.30 API COMPATIBILITY:
-/// - process.name() returns &str (not OsStr like in v0.29)
-/// - Use .to_string() to convert &str to owned String
-/// - No need for ProcessExt trait imports
-/// 
+/// Compute which rules were added and removed between two lists.
+/// Rules compare by value, so editing a rule (severity, schedule, scope...)
+/// shows up as one removal and one addition.
+pub fn diff_forbidden_lists(old: &[ForbiddenApp], new: &[ForbiddenApp]) -> ForbiddenListDiff {
+    ForbiddenListDiff {
+        added: new
+            .iter()
+            .filter(|app| !old.contains(app))
+            .cloned()
+            .collect(),
+        removed: old
+            .iter()
+            .filter(|app| !new.contains(app))
+            .cloned()
+            .collect(),
+        total: new.len(),
+    }
+}
+
+/// Check cached policy age against the configured maximum.
+/// A cache that was never confirmed by the server is always stale.
+pub fn policy_staleness(cache: &ForbiddenAppCache, max_age_secs: u64) -> PolicyStaleness {
+    let age_secs = unix_now().saturating_sub(cache.last_updated);
+    PolicyStaleness {
+        stale: cache.last_updated == 0 || age_secs > max_age_secs,
+        age_secs,
+        max_age_secs,
+        last_updated: cache.last_updated,
+    }
+}
+
+/// Whether an approved exception covers a match of (rule id, process name)
+type ExceptionCheck = Box<dyn Fn(Option<i64>, &str) -> bool + Send>;
+
+/// Scans process tables for forbidden apps and remembers what it reported
+///
 /// DUPLICATE PREVENTION:
-/// - Uses static mut REPORTED_PIDS to track already-reported PIDs
-/// - Only reports each PID once to avoid spam
-/// - Warnings about static mut are expected (Rust 2024 edition warning)
-/// 
-/// Returns: Vec of ViolationReport structs for newly detected violations
-pub fn scan_processes(forbidden_list: &[ForbiddenApp]) -> Vec<ViolationReport> {
-    let mut violations = Vec::new();
-    let mut sys = System::new_all();
-    sys.refresh_processes();
-    
-    // Initialize reported PIDs set
-    unsafe {
-        if REPORTED_PIDS.is_none() {
-            REPORTED_PIDS = Some(HashSet::new());
+/// - A process is identified by (PID, start time), so a PID the OS recycles
+///   for a new process is reported again
+/// - Keyed by mode as well, so promoting an audit rule to enforce still
+///   alerts on running processes
+/// - Entries for processes missing from the latest table are forgotten
+pub struct ProcessScanner {
+    reported: HashSet<(u32, u64, RuleMode)>,
+    /// Approved exception lookup, Exceptions::is_excepted outside of tests
+    excepted: ExceptionCheck,
+}
+
+impl ProcessScanner {
+    pub fn new(exceptions: Exceptions) -> Self {
+        Self {
+            reported: HashSet::new(),
+            excepted: Box::new(move |rule_id, process_name| exceptions.is_excepted(rule_id, process_name)),
         }
     }
-    
-    for (pid, process) in sys.processes() {
-        // SYSINFO v0.30: process.name() returns &str, convert to String
-        let process_name = process.name().to_string().to_lowercase();
+
+    /// Forget a report that could not be delivered, so the next scan
+    /// reports its process again
+    pub fn forget(&mut self, report: &ViolationReport) {
+        self.reported
+            .retain(|(pid, _, mode)| !(*pid == report.process_id && *mode == report.mode));
+    }
+
+    /// Running processes currently in violation of an enforced rule
+    pub fn active_violations(&self) -> usize {
+        self.reported.iter().filter(|(_, _, mode)| mode.is_enforce()).count()
+    }
+
+    /// Scan a process table for forbidden apps
+    /// 
+    /// EVIDENCE:
+    /// - Collected only for newly reported PIDs (hashing is not free)
+    /// - What is captured is controlled by settings.evidence
+    /// 
+    /// POLICY:
+    /// - Rules outside their schedule / device scope are skipped up front
+    /// - User scope and exceptions are checked against each process owner
+    /// - Matches covered by an approved user exception (exceptions.rs) are
+    ///   skipped without being marked as reported, so they alert again once
+    ///   the exception expires
+    /// - `audit_only` (set for audit-only builds, ITAM_AGENT_AUDIT_ONLY in
+    ///   settings.rs) reports every match in audit mode; otherwise each
+    ///   rule's own mode is used
+    /// 
+    /// Returns: Vec of ViolationReport structs for newly detected violations
+    pub fn scan(
+        &mut self,
+        table: &ProcessTable,
+        forbidden_list: &[ForbiddenApp],
+        ctx: &PolicyContext,
+        evidence: &mut EvidenceCollector,
+        audit_only: bool,
+    ) -> Vec<ViolationReport> {
+        // Forget processes that exited (or whose PID now belongs to another)
+        self.reported.retain(|(pid, start_time, _)| {
+            table.get(*pid).map(|p| p.start_time) == Some(*start_time)
+        });
         
-        // Check if this process is forbidden
-        for forbidden in forbidden_list {
-            if process_name.contains(&forbidden.process_name.to_lowercase()) {
-                let pid_value = pid.as_u32();
-                
-                // Check if we've already reported this PID
-                let already_reported = unsafe {
-                    REPORTED_PIDS.as_ref().unwrap().contains(&pid_value)
-                };
-                
-                if !already_reported {
-                    violations.push(ViolationReport {
-                        device_id: get_device_id(),
-                        app_detected: process_name.clone(),
-                        severity: forbidden.severity.clone(),
-                        process_id: pid_value,
-                    });
+        let mut violations = Vec::new();
+        let active_rules: Vec<&ForbiddenApp> = forbidden_list
+            .iter()
+            .filter(|rule| rule.is_active_for_device(ctx))
+            .collect();
+        if active_rules.is_empty() {
+            return violations;
+        }
+        
+        for process in table.iter() {
+            let process_name = process.name.to_lowercase();
+            
+            // Check if this process is forbidden
+            for forbidden in &active_rules {
+                if process_name.contains(&forbidden.process_name.to_lowercase())
+                    && forbidden.applies_to_user(process.os_user.as_deref())
+                    && !(self.excepted)(forbidden.id, &process_name)
+                {
+                    let mode = if audit_only { RuleMode::Audit } else { forbidden.mode };
                     
-                    // Mark as reported
-                    unsafe {
-                        REPORTED_PIDS.as_mut().unwrap().insert(pid_value);
+                    // Only the first match per process and mode is reported
+                    if self.reported.insert((process.pid, process.start_time, mode)) {
+                        violations.push(ViolationReport {
+                            device_id: ctx.device_id.clone(),
+                            app_detected: process_name.clone(),
+                            severity: forbidden.severity.clone(),
+                            process_id: process.pid,
+                            rule_id: forbidden.id,
+                            evidence: Some(evidence.collect(table, process.pid)),
+                            mode,
+                            alert_id: None,
+                            network: None,
+                            aggregate: None,
+                        });
                     }
                 }
             }
         }
+        
+        violations
     }
-    
-    violations
 }
 
 /// Report violation to API
+/// 
+/// Returns the id of the created alert when the server sends one back
 pub async fn report_violation(
-    api_url: &str,
+    backend: &Backend,
     token: &str,
     violation: &ViolationReport,
-) -> Result<(), String> {
-    let url = format!("{}/api/alerts", api_url);
+) -> Result<Option<i64>, AgentError> {
+    let url = backend.url("/api/alerts");
     
-    let client = reqwest::Client::new();
-    let response = client
-        .post(&url)
-        .header("Authorization", format!("Bearer {}", token))
-        .json(violation)
-        .send()
-        .await
-        .map_err(|e| format!("Network error: {}", e))?;
+    let client = backend.client();
+    let response = api::send(
+        client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", token))
+            .json(violation),
+    )
+    .await?;
+    let response = api::ensure_success(response).await?;
     
-    if !response.status().is_success() {
-        return Err(format!("API error: {}", response.status()));
+    // 201 body is the security_alerts row; an unparseable body is not an error
+    let alert: serde_json::Value = response.json().await.unwrap_or_default();
+    Ok(alert.get("id").and_then(|id| id.as_i64()))
+}
+
+/// Policy context for this device at the current time
+pub fn device_policy_context(device: &DeviceSettings) -> PolicyContext {
+    PolicyContext {
+        device_id: get_device_id(),
+        device_tags: device.tags.clone(),
+        org_unit: device.org_unit.clone(),
+        now: chrono::Utc::now(),
     }
-    
-    Ok(())
 }
 
 /// Get device ID (hostname-based)
-fn get_device_id() -> String {
+pub fn get_device_id() -> String {
     hostname::get()
         .unwrap_or_else(|_| std::ffi::OsString::from("unknown"))
         .to_string_lossy()
@@ -214,28 +437,295 @@ fn get_device_id() -> String {
 }
 
 /// Sync forbidden list from API and cache it
-pub async fn sync_forbidden_list(api_url: &str, token: &str) -> Result<Vec<ForbiddenApp>, String> {
-    match fetch_forbidden_list(api_url, token).await {
-        Ok(apps) => {
-            // Cache the list
-            if let Err(e) = cache_to_disk(&apps) {
-                eprintln!("Warning: Failed to cache forbidden list: {}", e);
-            }
-            Ok(apps)
+///
+/// Sends the cached ETag so an unchanged list is answered with 304. Either
+/// way the cache's `last_updated` is refreshed, since the server has just
+/// confirmed the policy. On error the caller keeps using `current`.
+pub async fn sync_forbidden_list(
+    backend: &Backend,
+    token: &str,
+    current: &ForbiddenAppCache,
+) -> Result<SyncOutcome, AgentError> {
+    // Without any cached rules there is nothing to revalidate
+    let etag = if current.apps.is_empty() { None } else { current.etag.as_deref() };
+    
+    let outcome = match fetch_forbidden_list(backend, token, etag).await? {
+        FetchOutcome::NotModified => SyncOutcome {
+            cache: ForbiddenAppCache {
+                last_updated: unix_now(),
+                ..current.clone()
+            },
+            diff: ForbiddenListDiff {
+                total: current.apps.len(),
+                ..ForbiddenListDiff::default()
+            },
+            not_modified: true,
+        },
+        FetchOutcome::Modified { apps, etag } => SyncOutcome {
+            diff: diff_forbidden_lists(&current.apps, &apps),
+            cache: ForbiddenAppCache {
+                apps,
+                last_updated: unix_now(),
+                etag,
+            },
+            not_modified: false,
+        },
+    };
+    
+    if let Err(e) = cache_to_disk(&outcome.cache) {
+        warn!("Failed to cache forbidden list: {}", e);
+    }
+    
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process_source::{FixtureSource, ProcessSource};
+
+    const RESPAWN: &str = include_str!("../tests/fixtures/respawn_and_pid_reuse.json");
+    const MULTI_USER: &str = include_str!("../tests/fixtures/multi_user.json");
+
+    fn rule(process_name: &str) -> ForbiddenApp {
+        ForbiddenApp {
+            id: Some(1),
+            process_name: process_name.to_string(),
+            severity: "High".to_string(),
+            mode: RuleMode::Enforce,
+            schedule: None,
+            scope: None,
+            exceptions: None,
+        }
+    }
+
+    fn ctx() -> PolicyContext {
+        PolicyContext {
+            device_id: "test-device".to_string(),
+            device_tags: Vec::new(),
+            org_unit: None,
+            now: chrono::Utc::now(),
         }
-        Err(e) => {
-            // If fetch fails, try to load from cache
-            eprintln!("Failed to fetch forbidden list: {}. Loading from cache...", e);
-            load_from_cache()
+    }
+
+    /// Scanner that ignores the on-disk exception store
+    fn scanner() -> ProcessScanner {
+        ProcessScanner {
+            reported: HashSet::new(),
+            excepted: Box::new(|_, _| false),
         }
     }
-}
 
-/// Clear reported PIDs (for testing or periodic cleanup)
-pub fn clear_reported_pids() {
-    unsafe {
-        if let Some(ref mut pids) = REPORTED_PIDS {
-            pids.clear();
+    fn scan(scanner: &mut ProcessScanner, source: &mut FixtureSource, rules: &[ForbiddenApp]) -> Vec<ViolationReport> {
+        scanner.scan(&source.snapshot(), rules, &ctx(), &mut EvidenceCollector::default(), false)
+    }
+
+    fn pids(reports: &[ViolationReport]) -> Vec<u32> {
+        reports.iter().map(|r| r.process_id).collect()
+    }
+
+    #[test]
+    fn matches_case_insensitive_substring_with_evidence() {
+        let mut source = FixtureSource::from_json(RESPAWN).unwrap();
+        let reports = scan(&mut scanner(), &mut source, &[rule("STEAM")]);
+
+        assert_eq!(pids(&reports), vec![100, 101]);
+        assert_eq!(reports[0].app_detected, "steam");
+        assert_eq!(reports[0].device_id, "test-device");
+        assert_eq!(reports[0].rule_id, Some(1));
+
+        let helper = reports[1].evidence.as_ref().unwrap();
+        let chain: Vec<&str> = helper.parent_chain.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(chain, vec!["steam", "systemd"]);
+        assert_eq!(helper.os_user.as_deref(), Some("alice"));
+
+        let steam = reports[0].evidence.as_ref().unwrap();
+        assert_eq!(steam.command_line.as_deref(), Some("/usr/bin/steam -silent --password=[REDACTED]"));
+        assert_eq!(steam.process_start_time, 1700001000);
+    }
+
+    #[test]
+    fn running_process_is_reported_once() {
+        let mut source = FixtureSource::from_json(RESPAWN).unwrap();
+        let mut scanner = scanner();
+        let rules = [rule("steam")];
+
+        assert_eq!(scan(&mut scanner, &mut source, &rules).len(), 2);
+        assert!(scan(&mut scanner, &mut source, &rules).is_empty());
+    }
+
+    #[test]
+    fn respawned_process_is_reported_as_new() {
+        let mut source = FixtureSource::from_json(RESPAWN).unwrap();
+        let mut scanner = scanner();
+        let rules = [rule("steam")];
+
+        scan(&mut scanner, &mut source, &rules);
+        scan(&mut scanner, &mut source, &rules);
+        assert_eq!(pids(&scan(&mut scanner, &mut source, &rules)), vec![150]);
+    }
+
+    #[test]
+    fn recycled_pid_is_reported_again() {
+        let mut source = FixtureSource::from_json(RESPAWN).unwrap();
+        let mut scanner = scanner();
+        let rules = [rule("steam")];
+
+        for _ in 0..3 {
+            scan(&mut scanner, &mut source, &rules);
         }
+        let reports = scan(&mut scanner, &mut source, &rules);
+        assert_eq!(pids(&reports), vec![100]);
+        assert_eq!(reports[0].evidence.as_ref().unwrap().process_start_time, 1700002000);
+    }
+
+    #[test]
+    fn audit_only_reports_in_audit_mode_and_enforce_alerts_again() {
+        let mut source = FixtureSource::from_json(RESPAWN).unwrap();
+        let mut scanner = scanner();
+        let rules = [rule("firefox")];
+
+        let table = source.snapshot();
+        let audit = scanner.scan(&table, &rules, &ctx(), &mut EvidenceCollector::default(), true);
+        assert_eq!(audit.len(), 1);
+        assert_eq!(audit[0].mode, RuleMode::Audit);
+
+        let enforced = scanner.scan(&table, &rules, &ctx(), &mut EvidenceCollector::default(), false);
+        assert_eq!(enforced.len(), 1);
+        assert!(enforced[0].mode.is_enforce());
+    }
+
+    #[test]
+    fn user_scope_and_exceptions_follow_process_owner() {
+        let mut scoped = rule("steam");
+        scoped.scope = Some(RuleScope {
+            os_users: vec!["Alice".to_string(), "qa-bot".to_string()],
+            ..RuleScope::default()
+        });
+        scoped.exceptions = Some(RuleExceptions {
+            os_users: vec!["qa-bot".to_string()],
+            ..RuleExceptions::default()
+        });
+
+        let mut source = FixtureSource::from_json(MULTI_USER).unwrap();
+        assert_eq!(pids(&scan(&mut scanner(), &mut source, &[scoped])), vec![300]);
+
+        let mut unscoped = rule("steam");
+        unscoped.exceptions = Some(RuleExceptions {
+            os_users: vec!["bob".to_string()],
+            ..RuleExceptions::default()
+        });
+        let mut source = FixtureSource::from_json(MULTI_USER).unwrap();
+        assert_eq!(pids(&scan(&mut scanner(), &mut source, &[unscoped])), vec![300, 302, 303]);
+    }
+
+    #[test]
+    fn rule_scoped_to_other_device_is_ignored() {
+        let mut other_device = rule("steam");
+        other_device.scope = Some(RuleScope {
+            device_ids: vec!["someone-else".to_string()],
+            ..RuleScope::default()
+        });
+
+        let mut source = FixtureSource::from_json(RESPAWN).unwrap();
+        assert!(scan(&mut scanner(), &mut source, &[other_device]).is_empty());
+    }
+
+    #[test]
+    fn excepted_match_alerts_once_exception_is_gone() {
+        let mut source = FixtureSource::from_json(RESPAWN).unwrap();
+        let mut scanner = ProcessScanner {
+            reported: HashSet::new(),
+            excepted: Box::new(|_, name| name == "steam"),
+        };
+        let rules = [rule("steam")];
+
+        assert_eq!(pids(&scan(&mut scanner, &mut source, &rules)), vec![101]);
+
+        scanner.excepted = Box::new(|_, _| false);
+        assert_eq!(pids(&scan(&mut scanner, &mut source, &rules)), vec![100]);
+    }
+
+    #[test]
+    fn cache_round_trips_and_missing_cache_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("forbidden_cache.json");
+
+        let empty = read_cache(&path).unwrap();
+        assert!(empty.apps.is_empty());
+        assert_eq!(empty.last_updated, 0);
+
+        let cache = ForbiddenAppCache {
+            apps: vec![rule("steam"), rule("utorrent")],
+            last_updated: 1700000000,
+            etag: Some("W/\"2a-abc\"".to_string()),
+        };
+        write_cache(&path, &cache).unwrap();
+
+        let loaded = read_cache(&path).unwrap();
+        assert_eq!(loaded.apps, cache.apps);
+        assert_eq!(loaded.last_updated, cache.last_updated);
+        assert_eq!(loaded.etag, cache.etag);
+    }
+
+    #[test]
+    fn cache_written_before_rule_ids_still_loads() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("forbidden_cache.json");
+        fs::write(
+            &path,
+            r#"{ "apps": [ { "process_name": "steam", "severity": "Low" } ], "last_updated": 5 }"#,
+        )
+        .unwrap();
+
+        let loaded = read_cache(&path).unwrap();
+        assert_eq!(loaded.apps[0].id, None);
+        assert!(loaded.apps[0].mode.is_enforce());
+        assert_eq!(loaded.etag, None);
+    }
+
+    #[test]
+    fn corrupt_cache_is_a_parse_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("forbidden_cache.json");
+        fs::write(&path, "{ not json").unwrap();
+
+        let err = read_cache(&path).unwrap_err();
+        assert!(matches!(err, AgentError::Parse(_)));
+        assert!(err.to_string().starts_with("Parse error"));
+    }
+
+    #[test]
+    fn diff_reports_edited_rule_as_removed_and_added() {
+        let old = vec![rule("steam"), rule("utorrent")];
+        let mut edited = rule("steam");
+        edited.severity = "Critical".to_string();
+        let new = vec![edited.clone(), rule("utorrent"), rule("discord")];
+
+        let diff = diff_forbidden_lists(&old, &new);
+        assert_eq!(diff.added, vec![edited, rule("discord")]);
+        assert_eq!(diff.removed, vec![rule("steam")]);
+        assert_eq!(diff.total, 3);
+        assert!(diff_forbidden_lists(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn staleness_follows_last_server_confirmation() {
+        let never_confirmed = ForbiddenAppCache::default();
+        assert!(policy_staleness(&never_confirmed, 86_400).stale);
+
+        let fresh = ForbiddenAppCache {
+            last_updated: unix_now() - 60,
+            ..ForbiddenAppCache::default()
+        };
+        assert!(!policy_staleness(&fresh, 86_400).stale);
+
+        let old = ForbiddenAppCache {
+            last_updated: unix_now() - 90_000,
+            ..ForbiddenAppCache::default()
+        };
+        let staleness = policy_staleness(&old, 86_400);
+        assert!(staleness.stale);
+        assert!(staleness.age_secs >= 90_000);
     }
 }
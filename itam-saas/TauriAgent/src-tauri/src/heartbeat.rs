@@ -0,0 +1,95 @@
+// ============================================================================
+// Heartbeat Module
+// ============================================================================
+// The "heartbeat" task tells the backend this device is alive:
+// POST /api/agent/heartbeat every settings.heartbeat.interval_secs
+//
+// Payload (fields the backend stores for the device list):
+// { "device_id": "...", "timestamp": 1700000000, "hostname": "...",
+//   "os_name": "...", "os_version": "...", "agent_version": "0.1.4",
+//   "lifecycle": "running", "status": { "overall": "healthy", ... } }
+//
+// `lifecycle` is "stopping" for the last heartbeat sent on graceful
+// shutdown, so the backend can tell a quit from a crash or lost network.
+//
+// `status` is the full AgentStatus (status.rs), so the dashboard sees a
+// failing sync or a growing outbox instead of just "last seen".
+//
+// Response: the device's tags and org unit as assigned in the dashboard,
+// which rule scopes and script targets are matched against. They are only
+// ever taken from here, never from settings.json, so a user cannot drop
+// out of a scoped rule; a response without them keeps the last known ones.
+// { "device": { "tags": ["finance", "laptop"], "org_unit": "EMEA/Finance" } }
+// ============================================================================
+
+use serde::{Deserialize, Serialize};
+use sysinfo::System;
+
+use crate::api::{self, Backend};
+use crate::error::AgentError;
+use crate::forbidden::get_device_id;
+use crate::settings::DeviceSettings;
+use crate::status::AgentStatus;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Lifecycle {
+    Running,
+    Stopping,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HeartbeatPayload {
+    pub device_id: String,
+    pub timestamp: u64,
+    pub hostname: String,
+    pub os_name: String,
+    pub os_version: String,
+    pub agent_version: String,
+    pub lifecycle: Lifecycle,
+    pub status: AgentStatus,
+}
+
+impl HeartbeatPayload {
+    pub fn collect(status: AgentStatus, lifecycle: Lifecycle) -> Self {
+        Self {
+            device_id: get_device_id(),
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            hostname: System::host_name().unwrap_or_else(|| "unknown".to_string()),
+            os_name: System::name().unwrap_or_default(),
+            os_version: System::os_version().unwrap_or_default(),
+            agent_version: env!("CARGO_PKG_VERSION").to_string(),
+            lifecycle,
+            status,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct HeartbeatResponse {
+    #[serde(default)]
+    pub device: Option<DeviceSettings>,
+}
+
+pub async fn post_heartbeat(
+    backend: &Backend,
+    token: &str,
+    payload: &HeartbeatPayload,
+) -> Result<HeartbeatResponse, AgentError> {
+    let url = backend.url("/api/agent/heartbeat");
+
+    let response = api::send(
+        backend.client()
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", token))
+            .json(payload),
+    )
+    .await?;
+    let response = api::ensure_success(response).await?;
+
+    // Older backends answer { "success": true }
+    Ok(response.json().await.unwrap_or_default())
+}
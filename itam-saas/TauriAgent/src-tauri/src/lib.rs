@@ -3,40 +3,55 @@ use tauri_plugin_single_instance::init as single_instance_init;
 use std::{thread, time::{Duration, SystemTime}};
 use std::net::{TcpListener, TcpStream};
 use std::io::{Read, Write};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use sysinfo::System;
 use serde::{Deserialize, Serialize};
-use lazy_static::lazy_static;
+use tracing::{info, warn};
 
-mod forbidden;
-use forbidden::{ForbiddenApp, ViolationReport, sync_forbidden_list, scan_processes, report_violation};
-
-// ============================================================================
-// GLOBAL STATE: Authentication Token for Forbidden App Monitoring
-// ============================================================================
-// This global state allows sharing the authentication token between:
-// 1. The React frontend (via set_monitoring_token command)
-// 2. The background monitoring thread (start_forbidden_app_monitoring)
-//
-// Why Arc<Mutex<String>>?
-// - Arc: Allows multiple threads to own the same data (cheap cloning)
-// - Mutex: Ensures thread-safe read/write access (prevents data races)
-// - lazy_static: Initializes once, lives for entire program lifetime
-//
-// Flow: React login -> invoke('set_monitoring_token') -> AUTH_TOKEN updated ->
-//       Background thread reads token -> Makes authenticated API calls
-// ============================================================================
-lazy_static! {
-    static ref AUTH_TOKEN: Arc<Mutex<String>> = Arc::new(Mutex::new(String::new()));
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct UsageData {
-    app_name: String,
-    window_title: String,
-    duration: u64,
-    timestamp: u64,
-}
+pub mod aggregate;
+pub mod api;
+pub mod compliance;
+pub mod diagnostics;
+pub mod error;
+pub mod evidence;
+pub mod exceptions;
+pub mod forbidden;
+pub mod heartbeat;
+pub mod logging;
+pub mod metrics;
+pub mod monitor;
+pub mod netpolicy;
+pub mod notify;
+pub mod outbox;
+pub mod perf;
+pub mod policy;
+pub mod process_source;
+pub mod push;
+pub mod remote_tasks;
+pub mod scripts;
+pub mod settings;
+pub mod state;
+pub mod status;
+pub mod supervisor;
+pub mod thresholds;
+pub mod updater;
+pub mod usage;
+use compliance::ComplianceReport;
+use diagnostics::{DiagnosticsInput, DiagnosticsReport, default_bundle_path};
+use error::AgentError;
+use exceptions::{JustificationRequest, LocalException, submit_justification};
+use forbidden::get_device_id;
+use heartbeat::{HeartbeatPayload, Lifecycle, post_heartbeat};
+use monitor::TauriEvents;
+use notify::Notifier;
+use process_source::default_process_source;
+use push::{PushChannel, PushCommand};
+use settings::load_settings;
+use state::AgentState;
+use status::AgentStatus;
+use supervisor::{lock, Supervisor, TaskHealth};
+use updater::UpdateOutcome;
+use usage::UsageData;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct AgentConfig {
@@ -136,19 +151,17 @@ fn handle_oauth_connection(mut stream: TcpStream, expected_nonce: &str) -> Optio
 }
 
 #[tauri::command]
-fn start_oauth_callback_server(app: AppHandle) -> Result<serde_json::Value, String> {
+fn start_oauth_callback_server(app: AppHandle) -> Result<serde_json::Value, AgentError> {
     // Random-ish nonce without extra deps
     let nonce = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
-        .map_err(|e| e.to_string())?
+        .unwrap_or_default()
         .as_nanos()
         .to_string();
 
-    let listener = TcpListener::bind("127.0.0.1:0").map_err(|e| format!("Failed to bind localhost: {}", e))?;
-    let port = listener
-        .local_addr()
-        .map_err(|e| format!("Failed to get local addr: {}", e))?
-        .port();
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .map_err(|e| AgentError::Io(format!("Failed to bind localhost: {}", e)))?;
+    let port = listener.local_addr()?.port();
 
     let app_handle = app.clone();
     let expected_nonce = nonce.clone();
@@ -169,90 +182,88 @@ fn start_oauth_callback_server(app: AppHandle) -> Result<serde_json::Value, Stri
 }
 
 #[tauri::command]
-async fn get_user_from_token(token: String) -> Result<serde_json::Value, String> {
-    let client = reqwest::Client::new();
-    let url = "https://it-asset-project-production.up.railway.app/api/auth/me";
-
-    let response = client
-        .get(url)
-        .header("Authorization", format!("Bearer {}", token))
-        .send()
-        .await
-        .map_err(|e| format!("Network error: {}", e))?;
-
-    if !response.status().is_success() {
-        let error_text = response
-            .text()
-            .await
-            .unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(format!("Token validation failed: {}", error_text));
-    }
+async fn get_user_from_token(
+    token: String,
+    state: tauri::State<'_, Arc<AgentState>>,
+) -> Result<serde_json::Value, AgentError> {
+    let client = state.backend().client();
+    let url = state.backend().url("/api/auth/me");
+
+    let response = api::send(
+        client
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", token))
+    )
+    .await?;
+    let response = api::ensure_success(response).await?;
 
     response
         .json::<serde_json::Value>()
         .await
-        .map_err(|e| format!("Invalid response: {}", e))
+        .map_err(|e| AgentError::Parse(e.to_string()))
 }
 
 // Tauri commands
 #[tauri::command]
-async fn login_user(username: String, password: String) -> Result<String, String> {
-    let client = reqwest::Client::new();
-    let url = "https://it-asset-project-production.up.railway.app/api/auth/login";
+async fn login_user(
+    username: String,
+    password: String,
+    state: tauri::State<'_, Arc<AgentState>>,
+) -> Result<String, AgentError> {
+    let client = state.backend().client();
+    let url = state.backend().url("/api/auth/login");
     
     let login_data = LoginRequest { username, password };
     
-    let response = client
-        .post(url)
-        .json(&login_data)
-        .send()
-        .await
-        .map_err(|e| format!("Network error: {}", e))?;
-    
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(format!("Login failed: {}", error_text));
-    }
+    let response = api::send(
+        client
+            .post(&url)
+            .json(&login_data)
+    )
+    .await?;
+    // Wrong credentials come back as Unauthorized with the server's message
+    let response = api::ensure_success(response).await?;
     
     let login_response: LoginResponse = response
         .json()
         .await
-        .map_err(|e| format!("Invalid response: {}", e))?;
+        .map_err(|e| AgentError::Parse(e.to_string()))?;
     
     Ok(login_response.token)
 }
 
+/// Structured agent health (status.rs)
 #[tauri::command]
-fn get_agent_status() -> String {
-    "Monitoring Active".to_string()
+fn get_agent_status(state: tauri::State<'_, Arc<AgentState>>) -> AgentStatus {
+    state.status()
 }
 
 #[tauri::command]
 async fn send_usage_data(
     data: UsageData,
     config: AgentConfig,
-) -> Result<String, String> {
-    let client = reqwest::Client::new();
+    state: tauri::State<'_, Arc<AgentState>>,
+) -> Result<String, AgentError> {
+    let client = state.backend().client();
     let url = format!("{}/api/agent/usage", config.api_url);
     
-    let response = client
-        .post(&url)
-        .header("Authorization", format!("Bearer {}", config.auth_token))
-        .json(&data)
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
-    
-    if response.status().is_success() {
-        Ok("Data sent successfully".to_string())
-    } else {
-        Err(format!("Failed to send data: {}", response.status()))
-    }
+    let response = api::send(
+        client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", config.auth_token))
+            .json(&data)
+    )
+    .await?;
+    api::ensure_success(response).await?;
+    Ok("Data sent successfully".to_string())
 }
 
 #[tauri::command]
-async fn send_heartbeat(config: AgentConfig) -> Result<String, String> {
-    let client = reqwest::Client::new();
+async fn send_heartbeat(
+    config: AgentConfig,
+    state: tauri::State<'_, Arc<AgentState>>,
+) -> Result<String, AgentError> {
+    let client = state.backend().client();
     let url = format!("{}/api/agent/heartbeat", config.api_url);
     
     let payload = serde_json::json!({
@@ -263,19 +274,15 @@ async fn send_heartbeat(config: AgentConfig) -> Result<String, String> {
             .as_secs()
     });
     
-    let response = client
-        .post(&url)
-        .header("Authorization", format!("Bearer {}", config.auth_token))
-        .json(&payload)
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
-    
-    if response.status().is_success() {
-        Ok("Heartbeat sent".to_string())
-    } else {
-        Err(format!("Heartbeat failed: {}", response.status()))
-    }
+    let response = api::send(
+        client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", config.auth_token))
+            .json(&payload)
+    )
+    .await?;
+    api::ensure_success(response).await?;
+    Ok("Heartbeat sent".to_string())
 }
 
 #[tauri::command]
@@ -289,12 +296,15 @@ fn get_system_info() -> String {
 }
 
 #[tauri::command]
-async fn collect_and_send_usage(auth_token: String) -> Result<String, String> {
+async fn collect_and_send_usage(
+    auth_token: String,
+    state: tauri::State<'_, Arc<AgentState>>,
+) -> Result<String, AgentError> {
     let mut sys = System::new_all();
     sys.refresh_processes();
     
-    let client = reqwest::Client::new();
-    let url = "https://it-asset-project-production.up.railway.app/api/agent/usage";
+    let client = state.backend().client();
+    let url = state.backend().url("/api/agent/usage");
     
     // Get device info for device_id
     let hostname = System::host_name().unwrap_or_else(|| "unknown".to_string());
@@ -318,164 +328,457 @@ async fn collect_and_send_usage(auth_token: String) -> Result<String, String> {
             "timestamp": timestamp
         });
         
-        let response = client
-            .post(url)
-            .header("Authorization", format!("Bearer {}", auth_token))
-            .json(&usage_data)
-            .send()
-            .await
-            .map_err(|e| format!("Network error: {}", e))?;
-        
-        if response.status().is_success() {
-            success_count += 1;
-        } else {
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(format!("API error: {}", error_text));
-        }
+        let response = api::send(
+            client
+                .post(&url)
+                .header("Authorization", format!("Bearer {}", auth_token))
+                .json(&usage_data)
+        )
+        .await?;
+        api::ensure_success(response).await?;
+        success_count += 1;
     }
     
     Ok(format!("Successfully sent {} usage records", success_count))
 }
 
-// Background process monitoring
-fn start_process_monitoring(handle: AppHandle) {
-    thread::spawn(move || {
-        let mut sys = System::new_all();
-        let mut last_process_name = String::new();
-        let mut start_time = SystemTime::now();
-        
-        loop {
-            sys.refresh_processes();
-            
-            // Get the most active process (simplified - in production, use Windows API for foreground window)
-            let current_process = sys
-                .processes()
-                .iter()
-                .max_by_key(|(_, p)| (p.cpu_usage() * 100.0) as u64)
-                .map(|(_, p)| p.name().to_string())
-                .unwrap_or_else(|| "Unknown".to_string());
-            
-            // If process changed, emit update
-            if current_process != last_process_name {
-                let duration = start_time.elapsed().unwrap_or(Duration::from_secs(0)).as_secs();
-                
-                let usage_data = UsageData {
-                    app_name: last_process_name.clone(),
-                    window_title: "".to_string(),
-                    duration,
-                    timestamp: SystemTime::now()
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs(),
-                };
-                
-                // Emit to React frontend
+// ============================================================================
+// Background Tasks
+// ============================================================================
+// Every background job runs as a supervised periodic task on Tauri's Tokio
+// runtime (supervisor.rs: restart with backoff, panic capture, health):
+//
+// | task          | period                       | work                            |
+// |---------------|------------------------------|---------------------------------|
+// | usage         | 5s                           | current-activity / usage-update |
+// | heartbeat     | settings.heartbeat           | POST /api/agent/heartbeat       |
+// | policy-sync   | settings.policy.sync_interval| forbidden list, network rules,  |
+// |               |                              | exception decisions             |
+// | process-scan  | 60s                          | scan, report violations         |
+// | outbox        | 60s                          | upload queued telemetry         |
+// | status        | 30s                          | agent-status event              |
+// | update        | settings.update              | install a newer signed release, |
+// |               |                              | then restart (updater.rs)       |
+// | remote-tasks  | settings.remote_tasks        | run admin-queued tasks from the |
+// |               |                              | allowlist (remote_tasks.rs)     |
+// | push          | always connected             | server push stream (push.rs),   |
+// |               |                              | triggers the tasks above        |
+// | perf-sample   | settings.perf                | host performance sample, minute |
+// |               |                              | and hour rollups (perf.rs)      |
+// | perf-upload   | settings.perf                | finished rollups via the outbox |
+// | thresholds    | settings.thresholds          | evaluate threshold rules on the |
+// |               |                              | latest sample (thresholds.rs)   |
+// | compliance    | settings.compliance          | security posture checks, report |
+// |               |                              | and score (compliance.rs)       |
+// | metrics       | settings.metrics, on demand  | loopback Prometheus endpoint    |
+// |               |                              | (metrics.rs), off by default    |
+//
+// Push commands only run a task early or drop the token: the periodic
+// tasks keep polling, so an agent without a stream still converges.
+//
+// Tasks share the managed AgentState (state.rs). The network tasks do
+// nothing until the frontend sets the monitoring token
+// (set_monitoring_token); policy-sync, process-scan and outbox share its
+// ForbiddenMonitor (monitor.rs) behind an async mutex.
+// ============================================================================
+const USAGE_PERIOD: Duration = Duration::from_secs(5);
+const SCAN_PERIOD: Duration = Duration::from_secs(60);
+const OUTBOX_PERIOD: Duration = Duration::from_secs(60);
+const STATUS_PERIOD: Duration = Duration::from_secs(30);
+/// The push task reconnects on its own; this only spaces out restarts
+/// after a panic
+const PUSH_RESTART_PERIOD: Duration = Duration::from_secs(60);
+/// Spaces out retries when the metrics address cannot be bound
+const METRICS_RESTART_PERIOD: Duration = Duration::from_secs(60);
+
+/// Act on a command from the push channel
+fn handle_push_command(handle: &AppHandle, state: &AgentState, command: PushCommand) {
+    info!(command = ?command, "Push command received");
+    match &command {
+        PushCommand::PolicyUpdated => {
+            state.triggers.trigger("policy-sync");
+        }
+        PushCommand::HeartbeatRequested => {
+            state.triggers.trigger("heartbeat");
+        }
+        PushCommand::InventoryRequested => {
+            state.triggers.trigger("process-scan");
+            state.triggers.trigger("heartbeat");
+        }
+        PushCommand::TaskQueued => {
+            state.triggers.trigger("remote-tasks");
+        }
+        PushCommand::DeviceRevoked { reason } => {
+            warn!(reason = reason.as_deref().unwrap_or("none given"), "Device revoked by the server, monitoring stopped");
+            state.credentials.clear();
+        }
+    }
+    let _ = handle.emit("push-command", &command);
+}
+
+fn start_background_tasks(handle: AppHandle, state: Arc<AgentState>, runtime: tokio::runtime::Handle) -> Supervisor {
+    let settings = &state.settings;
+    let sync_period = Duration::from_secs(settings.policy.sync_interval_secs);
+    let heartbeat_period = Duration::from_secs(settings.heartbeat.interval_secs);
+    let mut supervisor = Supervisor::with_health(runtime, state.health.clone()).with_triggers(state.triggers.clone());
+    
+    // Usage tracking: emit the most active app to the frontend
+    let usage_state = state.clone();
+    let usage_handle = handle.clone();
+    supervisor.spawn_periodic("usage", USAGE_PERIOD, move || {
+        let state = usage_state.clone();
+        let handle = usage_handle.clone();
+        async move {
+            let (current_process, ended) = lock(&state.usage).poll();
+            if let Some(usage_data) = ended {
                 let _ = handle.emit("usage-update", &usage_data);
-                
-                last_process_name = current_process.clone();
-                start_time = SystemTime::now();
             }
-            
-            // Emit current activity every interval
             let _ = handle.emit("current-activity", &current_process);
-            
-            thread::sleep(Duration::from_secs(5)); // Poll every 5 seconds
+            Ok(())
+        }
+    });
+    
+    // Heartbeat, carrying the agent status
+    let heartbeat_state = state.clone();
+    supervisor.spawn_periodic("heartbeat", heartbeat_period, move || {
+        let state = heartbeat_state.clone();
+        async move {
+            let Some(token) = state.credentials.token() else {
+                return Ok(());
+            };
+            let payload = HeartbeatPayload::collect(state.status(), Lifecycle::Running);
+            let response = post_heartbeat(state.backend(), &token, &payload).await?;
+            if let Some(device) = response.device {
+                state.set_device(device);
+            }
+            Ok(())
+        }
+    });
+    
+    // Agent status for the frontend
+    let status_state = state.clone();
+    let status_handle = handle.clone();
+    supervisor.spawn_periodic("status", STATUS_PERIOD, move || {
+        let state = status_state.clone();
+        let handle = status_handle.clone();
+        async move {
+            let _ = handle.emit("agent-status", &state.status());
+            Ok(())
+        }
+    });
+    
+    // Forbidden app monitoring: sync, scan and upload share one monitor
+    let sync_state = state.clone();
+    supervisor.spawn_periodic("policy-sync", sync_period, move || {
+        let state = sync_state.clone();
+        async move {
+            let Some(token) = state.credentials.token() else {
+                return Ok(());
+            };
+            let mut monitor = state.monitor.lock().await;
+            let result = monitor.sync(&token).await;
+            monitor.check_staleness();
+            result
+        }
+    });
+    
+    let scan_state = state.clone();
+    supervisor.spawn_periodic("process-scan", SCAN_PERIOD, move || {
+        let state = scan_state.clone();
+        async move {
+            let Some(token) = state.credentials.token() else {
+                return Ok(());
+            };
+            let mut monitor = state.monitor.lock().await;
+            monitor.check_staleness();
+            monitor.scan(&token).await
+        }
+    });
+    
+    if settings.update.enabled {
+        let update_state = state.clone();
+        let update_handle = handle.clone();
+        let update_period = Duration::from_secs(settings.update.check_interval_secs);
+        supervisor.spawn_periodic("update", update_period, move || {
+            let state = update_state.clone();
+            let handle = update_handle.clone();
+            async move {
+                let outcome = check_and_install_update(&state).await?;
+                if let UpdateOutcome::Installed { .. } = outcome {
+                    let _ = handle.emit("update-installed", &outcome);
+                    restart_agent(&handle, state.clone());
+                }
+                Ok(())
+            }
+        });
+    }
+    
+    if settings.remote_tasks.enabled {
+        let tasks_state = state.clone();
+        let tasks_handle = handle.clone();
+        let tasks_period = Duration::from_secs(settings.remote_tasks.poll_interval_secs);
+        supervisor.spawn_periodic("remote-tasks", tasks_period, move || {
+            let state = tasks_state.clone();
+            let handle = tasks_handle.clone();
+            async move {
+                let Some(token) = state.credentials.token() else {
+                    return Ok(());
+                };
+                for result in remote_tasks::poll_and_run(&state, &token).await? {
+                    let _ = handle.emit("remote-task-finished", &result);
+                }
+                Ok(())
+            }
+        });
+    }
+    
+    // Server push: one run holds the stream until shutdown
+    let push_state = state.clone();
+    let push_handle = handle.clone();
+    let push_cancel = supervisor.cancellation_token();
+    supervisor.spawn_periodic("push", PUSH_RESTART_PERIOD, move || {
+        let state = push_state.clone();
+        let handle = push_handle.clone();
+        let cancel = push_cancel.clone();
+        async move {
+            let mut channel = PushChannel::new(state.backend(), state.push_status.clone());
+            let token_state = state.clone();
+            channel
+                .run(
+                    move || token_state.credentials.token(),
+                    cancel,
+                    |command| handle_push_command(&handle, &state, command),
+                )
+                .await;
+            Ok(())
+        }
+    });
+    
+    // Host performance: sample often, upload rollups in batches
+    if settings.perf.enabled {
+        let sample_state = state.clone();
+        let sample_period = Duration::from_secs(settings.perf.sample_interval_secs);
+        supervisor.spawn_periodic("perf-sample", sample_period, move || {
+            let state = sample_state.clone();
+            async move {
+                // sysinfo refreshes block; sample on the blocking pool
+                tokio::task::spawn_blocking(move || {
+                    lock(&state.perf).sample();
+                })
+                .await
+                .map_err(|e| AgentError::Io(format!("perf sample panicked: {}", e)))
+            }
+        });
+    
+        let upload_state = state.clone();
+        let upload_period = Duration::from_secs(settings.perf.upload_interval_secs);
+        supervisor.spawn_periodic("perf-upload", upload_period, move || {
+            let state = upload_state.clone();
+            async move {
+                let Some(token) = state.credentials.token() else {
+                    return Ok(());
+                };
+                perf::upload(&state, &token).await.map(|_| ())
+            }
+        });
+    }
+    
+    if settings.compliance.enabled {
+        let compliance_state = state.clone();
+        let compliance_handle = handle.clone();
+        let compliance_period = Duration::from_secs(settings.compliance.interval_secs);
+        supervisor.spawn_periodic("compliance", compliance_period, move || {
+            let state = compliance_state.clone();
+            let handle = compliance_handle.clone();
+            async move {
+                let Some(token) = state.credentials.token() else {
+                    return Ok(());
+                };
+                if let Some(report) = compliance::run_checks(&state, &token).await? {
+                    let _ = handle.emit("compliance-report", &report);
+                }
+                Ok(())
+            }
+        });
+    }
+    
+    // Threshold rules, after perf-sample so they see its readings
+    if settings.thresholds.enabled {
+        let thresholds_state = state.clone();
+        let thresholds_handle = handle;
+        let thresholds_period = Duration::from_secs(settings.thresholds.check_interval_secs);
+        supervisor.spawn_periodic("thresholds", thresholds_period, move || {
+            let state = thresholds_state.clone();
+            let handle = thresholds_handle.clone();
+            async move {
+                let Some(token) = state.credentials.token() else {
+                    return Ok(());
+                };
+                for event in thresholds::check(&state, &token).await? {
+                    let _ = handle.emit("threshold-alert", &event.to_alert(&get_device_id()));
+                }
+                Ok(())
+            }
+        });
+    }
+    
+    // Prometheus endpoint: one run serves scrapes until shutdown
+    if settings.metrics.enabled {
+        let metrics_state = state.clone();
+        let metrics_cancel = supervisor.cancellation_token();
+        supervisor.spawn_periodic("metrics", METRICS_RESTART_PERIOD, move || {
+            let state = metrics_state.clone();
+            let cancel = metrics_cancel.clone();
+            async move {
+                let listener = metrics::bind(&state.settings.metrics).await?;
+                metrics::serve(listener, state, cancel).await
+            }
+        });
+    }
+    
+    supervisor.spawn_periodic("outbox", OUTBOX_PERIOD, move || {
+        let state = state.clone();
+        async move {
+            let Some(token) = state.credentials.token() else {
+                return Ok(());
+            };
+            state.monitor.lock().await.flush_outbox(&token).await
         }
     });
+    
+    supervisor
 }
 
 // ============================================================================
-// Background Thread: Forbidden App Monitoring and Alerting
+// Graceful Shutdown
 // ============================================================================
-// This function spawns a background thread that:
-// 1. Waits for auth token to be set (via set_monitoring_token command)
-// 2. Syncs forbidden app list from API every 5 minutes
-// 3. Scans running processes every 60 seconds
-// 4. Reports violations to backend API
-// 5. Emits events to React frontend for local notifications
+// Tray quit, SIGTERM / Ctrl+C and OS logoff or shutdown all end up in
+// RunEvent::ExitRequested (signals call app.exit). The first request is
+// held back while shutdown_agent runs, then the app exits:
 //
-// Thread Safety:
-// - Clones AUTH_TOKEN Arc (increments reference count, doesn't copy data)
-// - Locks mutex only during token read (minimizes lock time)
-// - Uses tokio runtime for async API calls within sync thread
+// 1. Stop every supervised task (a run in progress finishes first)
+// 2. Close the open usage session and queue it in the outbox, together
+//    with aggregated violations no alert covers yet (aggregate.rs)
+// 3. Flush the outbox, bounded by settings.shutdown.deadline_secs
+// 4. Persist the policy cache and whatever is still queued
+// 5. Send a last heartbeat with lifecycle "stopping"
 //
-// Error Handling:
-// - Falls back to cached list if API fetch fails
-// - Continues monitoring even if reporting fails
-// - Logs errors to console for debugging
+// Nothing waits past the deadline: records that did not make it are
+// uploaded from the outbox on the next start.
 // ============================================================================
-fn start_forbidden_app_monitoring(handle: AppHandle) {
-    let token_arc = AUTH_TOKEN.clone(); // Clone Arc pointer, not the String
-    thread::spawn(move || {
-        let api_url = "https://it-asset-project-production.up.railway.app";
-        let mut forbidden_list: Vec<ForbiddenApp> = Vec::new();
-        let mut last_sync = SystemTime::UNIX_EPOCH;
-        
-        loop {
-            // ================================================================
-            // STEP 1: Read auth token from global state (thread-safe)
-            // ================================================================
-            let auth_token = {
-                let token = token_arc.lock().unwrap(); // Acquire lock
-                token.clone() // Clone the String value
-            }; // Lock automatically released here
-            
-            // Wait for token to be set by React frontend
-            if auth_token.is_empty() {
-                thread::sleep(Duration::from_secs(10));
-                continue;
+const FINAL_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(3);
+
+async fn shutdown_agent(state: &AgentState, mut supervisor: Supervisor) {
+    info!("Agent stopping...");
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(state.settings.shutdown.deadline_secs);
+    
+    // 1. Stop collectors
+    if tokio::time::timeout_at(deadline, supervisor.shutdown()).await.is_err() {
+        warn!("Background tasks still running at shutdown deadline");
+    }
+    
+    // 2-4. Close the usage session, queue unreported alerts, flush and persist
+    match tokio::time::timeout_at(deadline, state.monitor.lock()).await {
+        Ok(mut monitor) => {
+            let ended = lock(&state.usage).finish();
+            if let Some(usage_data) = ended {
+                let mut payload = serde_json::json!(usage_data);
+                payload["device_id"] = serde_json::json!(get_device_id());
+                monitor.enqueue("/api/agent/usage", payload);
             }
+            monitor.queue_unreported_alerts();
             
-            // Sync forbidden list every 5 minutes
-            let time_since_sync = SystemTime::now()
-                .duration_since(last_sync)
-                .unwrap_or(Duration::from_secs(999999));
-            
-            if time_since_sync.as_secs() > 300 {
-                // 5 minutes
-                let runtime = tokio::runtime::Runtime::new().unwrap();
-                match runtime.block_on(sync_forbidden_list(api_url, &auth_token)) {
-                    Ok(apps) => {
-                        forbidden_list = apps;
-                        last_sync = SystemTime::now();
-                        println!("✅ Synced {} forbidden apps", forbidden_list.len());
-                        
-                        // Emit to frontend
-                        let _ = handle.emit("forbidden-list-updated", forbidden_list.len());
-                    }
-                    Err(e) => {
-                        eprintln!("❌ Failed to sync forbidden list: {}", e);
-                    }
+            if let Some(token) = state.credentials.token() {
+                match tokio::time::timeout_at(deadline, monitor.flush_outbox(&token)).await {
+                    Ok(Ok(())) => info!("Outbox flushed"),
+                    Ok(Err(e)) => warn!("{} - kept for next start", e),
+                    Err(_) => warn!("Outbox flush hit shutdown deadline - kept for next start"),
                 }
             }
-            
-            // Scan for violations every 60 seconds
-            if !forbidden_list.is_empty() {
-                let violations = scan_processes(&forbidden_list);
-                
-                if !violations.is_empty() {
-                    println!("🚨 Detected {} violations", violations.len());
-                    
-                    // Report each violation
-                    let runtime = tokio::runtime::Runtime::new().unwrap();
-                    for violation in &violations {
-                        match runtime.block_on(report_violation(api_url, &auth_token, violation)) {
-                            Ok(_) => {
-                                println!("✅ Reported: {}", violation.app_detected);
-                                
-                                // Emit to frontend for local notification
-                                let _ = handle.emit("violation-detected", violation);
-                            }
-                            Err(e) => {
-                                eprintln!("❌ Failed to report violation: {}", e);
-                            }
-                        }
-                    }
-                }
+            monitor.persist();
+        }
+        Err(_) => warn!("Monitor busy at shutdown deadline, skipping outbox flush"),
+    }
+    
+    // 5. Tell the backend this is a clean stop
+    if let Some(token) = state.credentials.token() {
+        let payload = HeartbeatPayload::collect(state.status(), Lifecycle::Stopping);
+        match tokio::time::timeout(FINAL_HEARTBEAT_TIMEOUT, post_heartbeat(state.backend(), &token, &payload)).await {
+            Ok(Ok(_)) => info!("Sent stopping heartbeat"),
+            Ok(Err(e)) => warn!("Failed to send stopping heartbeat: {}", e),
+            Err(_) => warn!("Stopping heartbeat timed out"),
+        }
+    }
+    info!("Agent stopped");
+}
+
+/// Exit request hook: run the shutdown once, then let the app exit
+fn on_exit_requested(app: &AppHandle, api: &tauri::ExitRequestApi) {
+    let Some(state) = app.try_state::<Arc<AgentState>>() else {
+        return;
+    };
+    if state.is_shutdown_done() {
+        return;
+    }
+    api.prevent_exit();
+    shutdown_then(app, state.inner().clone(), false);
+}
+
+/// Shut down gracefully, then start again (after a self-update)
+fn restart_agent(app: &AppHandle, state: Arc<AgentState>) {
+    shutdown_then(app, state, true);
+}
+
+fn shutdown_then(app: &AppHandle, state: Arc<AgentState>, restart: bool) {
+    // Repeated requests while shutting down find the supervisor already taken
+    let Some(supervisor) = state.take_supervisor() else {
+        return;
+    };
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        shutdown_agent(&state, supervisor).await;
+        if let Ok(logging) = state.logging() {
+            logging.flush();
+        }
+        state.mark_shutdown_done();
+        if restart {
+            app.restart();
+        }
+        app.exit(0);
+    });
+}
+
+/// Turn SIGTERM / Ctrl+C (and console logoff / shutdown on Windows) into
+/// an exit request, so they take the graceful path too
+fn listen_for_os_shutdown(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let Ok(mut terminate) = signal(SignalKind::terminate()) else {
+                warn!("Cannot listen for SIGTERM");
+                return;
+            };
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+        }
+        #[cfg(windows)]
+        {
+            use tokio::signal::windows::{ctrl_logoff, ctrl_shutdown};
+            let (Ok(mut logoff), Ok(mut shutdown)) = (ctrl_logoff(), ctrl_shutdown()) else {
+                warn!("Cannot listen for logoff / shutdown");
+                return;
+            };
+            tokio::select! {
+                _ = logoff.recv() => {}
+                _ = shutdown.recv() => {}
+                _ = tokio::signal::ctrl_c() => {}
             }
-            
-            thread::sleep(Duration::from_secs(60)); // Check every 60 seconds
         }
+        info!("Received OS shutdown signal");
+        app.exit(0);
     });
 }
 
@@ -487,29 +790,229 @@ fn start_forbidden_app_monitoring(handle: AppHandle) {
 // Flow:
 // 1. User logs in via React UI
 // 2. React calls: await invoke('set_monitoring_token', { token })
-// 3. This function writes token to global AUTH_TOKEN
-// 4. Background monitoring thread detects non-empty token
+// 3. This function stores the token in AgentState.credentials
+// 4. Background tasks find a token on their next run
 // 5. Monitoring activates and starts scanning processes
 //
 // Security:
-// - Token printed to console is truncated (first 10 chars only)
+// - Token is never logged (log files can be uploaded, see logging.rs)
 // - Full token stored in memory, never written to disk
 // - Token cleared on app restart (requires re-login)
-//
-// Thread Safety:
-// - Locks AUTH_TOKEN mutex during write
-// - Lock released automatically when function returns
 // ============================================================================
 #[tauri::command]
-fn set_monitoring_token(token: String) -> Result<String, String> {
-    let mut auth_token = AUTH_TOKEN.lock().unwrap(); // Acquire write lock
-    *auth_token = token.clone(); // Dereference and assign
-    println!("✅ Monitoring token set: {}...", &token.chars().take(10).collect::<String>());
+fn set_monitoring_token(state: tauri::State<'_, Arc<AgentState>>, token: String) -> Result<String, AgentError> {
+    info!("Monitoring token set");
+    state.credentials.set(token);
     Ok("Token set successfully".to_string())
-} // Lock automatically released here
+}
+
+// ============================================================================
+// Tauri Commands: Violation Justification & Exception Requests
+// ============================================================================
+// get_violation_status:
+//   Fetches this device's alerts (GET /api/alerts/device/:deviceId), applies
+//   any newly approved exceptions and returns the raw alert rows for display.
+//
+// submit_violation_justification:
+//   Sends the user's justification for an alert (PATCH /api/alerts/:id).
+//   With exception_minutes set, it also requests a temporary exception; the
+//   request is remembered locally and its decision is polled on every
+//   policy sync until the admin approves or denies it.
+//
+// get_active_exceptions:
+//   Approved, unexpired exceptions currently applied to local enforcement.
+// ============================================================================
+#[tauri::command]
+async fn get_violation_status(state: tauri::State<'_, Arc<AgentState>>) -> Result<serde_json::Value, AgentError> {
+    let token = state.credentials.require()?;
+    let (alerts, applied) = state.exceptions.refresh(state.backend(), &token, &get_device_id()).await?;
+    Ok(serde_json::json!({
+        "alerts": alerts,
+        "newly_applied": applied,
+        "active_exceptions": state.exceptions.active(),
+    }))
+}
+
+#[tauri::command]
+async fn submit_violation_justification(
+    state: tauri::State<'_, Arc<AgentState>>,
+    alert_id: i64,
+    justification: String,
+    exception_minutes: Option<u64>,
+) -> Result<serde_json::Value, AgentError> {
+    let token = state.credentials.require()?;
+    if justification.trim().is_empty() {
+        return Err(AgentError::Policy("Justification must not be empty".to_string()));
+    }
+
+    let request = JustificationRequest {
+        justification: justification.trim().to_string(),
+        exception_requested: exception_minutes.is_some(),
+        exception_duration_minutes: exception_minutes,
+    };
+    let updated = submit_justification(state.backend(), &token, alert_id, &request).await?;
+
+    if let Some(minutes) = exception_minutes {
+        state.exceptions.record_request(alert_id, minutes * 60);
+    }
+    Ok(updated)
+}
+
+#[tauri::command]
+fn get_active_exceptions(state: tauri::State<'_, Arc<AgentState>>) -> Vec<LocalException> {
+    state.exceptions.active()
+}
+
+/// Health of every supervised background task
+#[tauri::command]
+fn get_task_health(state: tauri::State<'_, Arc<AgentState>>) -> Vec<TaskHealth> {
+    state.health.snapshot()
+}
+
+/// Last compliance report (compliance.rs), None before the first run
+#[tauri::command]
+fn get_compliance_report(state: tauri::State<'_, Arc<AgentState>>) -> Option<ComplianceReport> {
+    state.compliance.lock().unwrap().clone()
+}
+
+// ============================================================================
+// Tauri Commands: Logging
+// ============================================================================
+// get_log_level / set_log_level:
+//   Read or replace the filter directives ("info", "debug",
+//   "info,tauriagent_lib::monitor=trace") until the next start.
+//
+// upload_logs:
+//   Sends the last `window_minutes` (default 60, at most a day) of the log
+//   files to POST /api/agent/logs when support asks for them. Requires the
+//   monitoring token.
+// ============================================================================
+#[tauri::command]
+fn get_log_level(state: tauri::State<'_, Arc<AgentState>>) -> Result<String, AgentError> {
+    Ok(state.logging()?.level())
+}
+
+#[tauri::command]
+fn set_log_level(state: tauri::State<'_, Arc<AgentState>>, level: String) -> Result<String, AgentError> {
+    state.logging()?.set_level(level.trim())?;
+    Ok(level.trim().to_string())
+}
+
+#[tauri::command]
+async fn upload_logs(
+    state: tauri::State<'_, Arc<AgentState>>,
+    window_minutes: Option<u64>,
+) -> Result<serde_json::Value, AgentError> {
+    let token = state.credentials.require()?;
+    let logging = state.logging()?;
+    let minutes = window_minutes
+        .unwrap_or(logging::DEFAULT_UPLOAD_WINDOW_MINUTES)
+        .clamp(1, logging::MAX_UPLOAD_WINDOW_MINUTES);
+    let uploaded =
+        logging::upload_recent_logs(state.backend(), &token, logging.dir(), Duration::from_secs(minutes * 60)).await?;
+    Ok(serde_json::json!({ "uploaded": uploaded, "window_minutes": minutes }))
+}
+
+// ============================================================================
+// Tauri Command: Diagnostics Bundle
+// ============================================================================
+// Builds the support zip (diagnostics.rs) from the running agent, saves it
+// under <config_dir>/tauriagent/diagnostics/ and, with upload = true, sends
+// it to the backend (requires the monitoring token).
+// ============================================================================
+const DIAGNOSTICS_MONITOR_WAIT: Duration = Duration::from_secs(2);
+
+#[tauri::command]
+async fn collect_diagnostics(
+    state: tauri::State<'_, Arc<AgentState>>,
+    upload: Option<bool>,
+) -> Result<DiagnosticsReport, AgentError> {
+    // Write the in-memory policy cache and outbox, the bundle reads the files
+    if let Ok(monitor) = tokio::time::timeout(DIAGNOSTICS_MONITOR_WAIT, state.monitor.lock()).await {
+        monitor.persist();
+    }
+    let input = DiagnosticsInput {
+        settings: state.settings.clone(),
+        token: state.credentials.token(),
+        status: Some(state.status()),
+        log_dir: state
+            .logging()
+            .map(|logging| logging.dir().to_path_buf())
+            .unwrap_or_else(|_| logging::log_dir()),
+    };
+    diagnostics::collect(input, &default_bundle_path(), upload.unwrap_or(false)).await
+}
+
+// ============================================================================
+// Self-Update
+// ============================================================================
+// The "update" task and the check_for_update command install newer signed
+// releases over the running executable (updater.rs) and restart.
+//
+// watch_update_health runs once per start. If this start is a freshly
+// installed version, it either confirms it after
+// settings.update.health_check_secs or puts the previous version back and
+// restarts; a version that keeps crashing before that is rolled back on
+// its MAX_UNCONFIRMED_BOOTS + 1-th start.
+// ============================================================================
+async fn check_and_install_update(state: &AgentState) -> Result<UpdateOutcome, AgentError> {
+    let target = std::env::current_exe()?;
+    let token = state.credentials.token();
+    updater::check_for_update(&state.settings.update, state.backend(), token.as_deref(), &target).await
+}
+
+fn watch_update_health(app: AppHandle, state: Arc<AgentState>) {
+    let Some(update) = updater::record_boot() else {
+        return;
+    };
+    if update.boots > updater::MAX_UNCONFIRMED_BOOTS {
+        warn!(boots = update.boots, "Update never passed its health check");
+        match updater::roll_back(update) {
+            Ok(()) => restart_agent(&app, state),
+            Err(e) => warn!("Rollback failed: {}", e),
+        }
+        return;
+    }
+    
+    let wait = Duration::from_secs(state.settings.update.health_check_secs);
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(wait).await;
+        if !state.is_running() {
+            // Shutting down: judge the next start instead
+            return;
+        }
+        let status = state.status();
+        if updater::is_healthy(&status) {
+            if let Err(e) = updater::confirm(update) {
+                warn!("Failed to confirm update: {}", e);
+            }
+            return;
+        }
+        warn!(summary = %status.summary, "Updated agent is unhealthy");
+        match updater::roll_back(update) {
+            Ok(()) => restart_agent(&app, state),
+            Err(e) => warn!("Rollback failed: {}", e),
+        }
+    });
+}
+
+/// Look for a newer release now instead of waiting for the update task
+#[tauri::command]
+async fn check_for_update(app: AppHandle, state: tauri::State<'_, Arc<AgentState>>) -> Result<UpdateOutcome, AgentError> {
+    let outcome = check_and_install_update(&state).await?;
+    if let UpdateOutcome::Installed { .. } = outcome {
+        let _ = app.emit("update-installed", &outcome);
+        restart_agent(&app, state.inner().clone());
+    }
+    Ok(outcome)
+}
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    let settings = load_settings();
+    let log_control = logging::init(&settings.logging);
+    info!(version = env!("CARGO_PKG_VERSION"), "Agent starting");
+    
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_http::init())
@@ -553,32 +1056,38 @@ pub fn run() {
                         }
                     }
                     "quit" => {
-                        std::process::exit(0);
+                        // Graceful: goes through on_exit_requested
+                        app.exit(0);
                     }
                     _ => {}
                 })
                 .on_tray_icon_event(|tray, event| {
-                    match event {
-                        TrayIconEvent::Click { button, .. } => {
-                            let app = tray.app_handle();
-                            if button == tauri::tray::MouseButton::Left {
-                                if let Some(window) = app.get_webview_window("main") {
-                                    let _ = window.show();
-                                    let _ = window.set_focus();
-                                }
+                    if let TrayIconEvent::Click { button, .. } = event {
+                        let app = tray.app_handle();
+                        if button == tauri::tray::MouseButton::Left {
+                            if let Some(window) = app.get_webview_window("main") {
+                                let _ = window.show();
+                                let _ = window.set_focus();
                             }
                         }
-                        _ => {}
                     }
                 })
                 .build(app)?;
             
-            // Start background process monitoring
-            let handle = app.handle().clone();
-            start_process_monitoring(handle.clone());
-            
-            // Start forbidden app monitoring
-            start_forbidden_app_monitoring(handle);
+            // Start supervised background tasks on Tauri's Tokio runtime
+            let runtime = tauri::async_runtime::block_on(async { tokio::runtime::Handle::current() });
+            let notifier = Notifier::new(app.handle().clone(), settings.notifications.clone());
+            let state = Arc::new(AgentState::new(
+                settings,
+                default_process_source(),
+                Box::new(TauriEvents::new(app.handle().clone(), notifier)),
+            ));
+            let supervisor = start_background_tasks(app.handle().clone(), state.clone(), runtime);
+            state.attach_supervisor(supervisor);
+            state.attach_logging(log_control);
+            watch_update_health(app.handle().clone(), state.clone());
+            app.manage(state);
+            listen_for_os_shutdown(app.handle().clone());
             
             Ok(())
         })
@@ -598,8 +1107,23 @@ pub fn run() {
             send_heartbeat,
             get_system_info,
             collect_and_send_usage,
-            set_monitoring_token
+            set_monitoring_token,
+            get_violation_status,
+            submit_violation_justification,
+            get_active_exceptions,
+            get_task_health,
+            get_compliance_report,
+            get_log_level,
+            set_log_level,
+            upload_logs,
+            collect_diagnostics,
+            check_for_update
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::ExitRequested { api, .. } = &event {
+                on_exit_requested(app, api);
+            }
+        });
 }
@@ -0,0 +1,281 @@
+// ============================================================================
+// Logging Module
+// ============================================================================
+// Diagnostics go through `tracing` instead of println!, so a release build
+// without a console still leaves a trail:
+//
+// - JSON lines in <config_dir>/tauriagent/logs/agent.YYYY-MM-DD.log,
+//   rotated daily, the newest settings.logging.max_files kept
+// - human-readable lines on the console (development builds)
+//
+// Every supervised run is wrapped in a `task` span (supervisor.rs), so each
+// line written by a background task carries `"span": {"task": "..."}`.
+//
+// Level:
+// - settings.logging.level at startup, EnvFilter syntax
+//   ("info", "debug", "info,tauriagent_lib::netpolicy=trace")
+// - ITAM_AGENT_LOG overrides it (local development)
+// - set_log_level changes it at runtime, until the next start
+//
+// Remote upload (upload_logs command, when support asks for it):
+// POST /api/agent/logs
+// { "device_id": "...", "agent_version": "0.1.4", "window_minutes": 60,
+//   "truncated": false, "entries": [ { "timestamp": "...", "level": "WARN",
+//   "fields": { "message": "..." }, "target": "...", "span": {...} } ] }
+// Only the last MAX_UPLOAD_ENTRIES lines of the window are sent.
+// ============================================================================
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+use crate::api::{self, Backend};
+use crate::error::AgentError;
+use crate::forbidden::get_device_id;
+use crate::settings::{agent_config_dir, LoggingSettings};
+
+pub const LOG_LEVEL_ENV: &str = "ITAM_AGENT_LOG";
+const LOG_FILE_PREFIX: &str = "agent";
+const LOG_FILE_SUFFIX: &str = "log";
+const FALLBACK_LEVEL: &str = "info";
+/// Cap on the log lines sent in one upload
+pub const MAX_UPLOAD_ENTRIES: usize = 5_000;
+/// Upload window when none is given, and the longest one accepted
+pub const DEFAULT_UPLOAD_WINDOW_MINUTES: u64 = 60;
+pub const MAX_UPLOAD_WINDOW_MINUTES: u64 = 24 * 60;
+
+/// Log directory, created on first use
+pub fn log_dir() -> PathBuf {
+    let path = agent_config_dir().join("logs");
+    if !path.exists() {
+        let _ = fs::create_dir_all(&path);
+    }
+    path
+}
+
+/// Runtime control over the installed subscriber
+#[derive(Clone)]
+pub struct LogControl {
+    filter: reload::Handle<EnvFilter, Registry>,
+    level: Arc<Mutex<String>>,
+    dir: PathBuf,
+    /// Flushes the file writer when dropped
+    guard: Arc<Mutex<Option<WorkerGuard>>>,
+}
+
+impl LogControl {
+    /// Current filter directives
+    pub fn level(&self) -> String {
+        self.level.lock().unwrap().clone()
+    }
+
+    /// Replace the filter, e.g. "debug" while support is looking
+    pub fn set_level(&self, directives: &str) -> Result<(), AgentError> {
+        let filter = EnvFilter::try_new(directives)
+            .map_err(|e| AgentError::Policy(format!("Invalid log level {:?}: {}", directives, e)))?;
+        self.filter
+            .reload(filter)
+            .map_err(|e| AgentError::Io(format!("Cannot change log level: {}", e)))?;
+        *self.level.lock().unwrap() = directives.to_string();
+        tracing::info!(level = directives, "Log level changed");
+        Ok(())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Write out buffered lines before the process exits; later lines only
+    /// reach the console
+    pub fn flush(&self) {
+        self.guard.lock().unwrap().take();
+    }
+}
+
+/// Install the global subscriber: JSON file + console, reloadable level
+pub fn init(settings: &LoggingSettings) -> LogControl {
+    let dir = log_dir();
+    let level = std::env::var(LOG_LEVEL_ENV).unwrap_or_else(|_| settings.level.clone());
+    let (filter, level) = match EnvFilter::try_new(&level) {
+        Ok(filter) => (filter, level),
+        Err(_) => (EnvFilter::new(FALLBACK_LEVEL), FALLBACK_LEVEL.to_string()),
+    };
+    let (filter, filter_handle) = reload::Layer::new(filter);
+
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(LOG_FILE_PREFIX)
+        .filename_suffix(LOG_FILE_SUFFIX)
+        .max_log_files(settings.max_files.max(1))
+        .build(&dir);
+    let (file_layer, guard, file_error) = match appender {
+        Ok(appender) => {
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let layer = fmt::layer().json().with_current_span(true).with_span_list(false).with_writer(writer);
+            (Some(layer), Some(guard), None)
+        }
+        Err(e) => (None, None, Some(e)),
+    };
+
+    let installed = tracing_subscriber::registry()
+        .with(filter)
+        .with(file_layer)
+        .with(fmt::layer().with_target(false))
+        .try_init();
+    if installed.is_err() {
+        eprintln!("Logging already initialised, keeping the existing subscriber");
+    }
+    if let Some(e) = file_error {
+        tracing::warn!(dir = %dir.display(), error = %e, "Cannot write log files, logging to console only");
+    }
+
+    LogControl {
+        filter: filter_handle,
+        level: Arc::new(Mutex::new(level)),
+        dir,
+        guard: Arc::new(Mutex::new(guard)),
+    }
+}
+
+/// JSON log lines written at or after `since`, oldest first, at most `max`
+/// (the newest win). Returns whether older lines were cut off.
+pub fn recent_entries(dir: &Path, since: DateTime<Utc>, max: usize) -> (Vec<serde_json::Value>, bool) {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| {
+                    p.file_name()
+                        .and_then(|n| n.to_str())
+                        .is_some_and(|n| n.starts_with(&format!("{}.", LOG_FILE_PREFIX)))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    // agent.YYYY-MM-DD.log sorts by date
+    files.sort();
+
+    let mut entries = VecDeque::new();
+    let mut truncated = false;
+    for path in files {
+        let Ok(content) = fs::read_to_string(&path) else {
+            continue;
+        };
+        for line in content.lines() {
+            let Ok(entry) = serde_json::from_str::<serde_json::Value>(line) else {
+                continue;
+            };
+            let Some(written) = entry["timestamp"]
+                .as_str()
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            else {
+                continue;
+            };
+            if written < since {
+                continue;
+            }
+            if entries.len() == max {
+                entries.pop_front();
+                truncated = true;
+            }
+            entries.push_back(entry);
+        }
+    }
+    (entries.into(), truncated)
+}
+
+#[derive(Debug, Serialize)]
+pub struct LogUpload {
+    pub device_id: String,
+    pub agent_version: String,
+    pub window_minutes: u64,
+    pub truncated: bool,
+    pub entries: Vec<serde_json::Value>,
+}
+
+/// Send the last `window` of logs; returns how many lines were uploaded
+pub async fn upload_recent_logs(backend: &Backend, token: &str, dir: &Path, window: Duration) -> Result<usize, AgentError> {
+    let since = Utc::now() - chrono::Duration::from_std(window).unwrap_or_else(|_| chrono::Duration::hours(1));
+    let (entries, truncated) = recent_entries(dir, since, MAX_UPLOAD_ENTRIES);
+    let upload = LogUpload {
+        device_id: get_device_id(),
+        agent_version: env!("CARGO_PKG_VERSION").to_string(),
+        window_minutes: window.as_secs() / 60,
+        truncated,
+        entries,
+    };
+
+    let url = backend.url("/api/agent/logs");
+    let response = api::send(
+        backend.client()
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&upload),
+    )
+    .await?;
+    api::ensure_success(response).await?;
+    tracing::info!(entries = upload.entries.len(), truncated, "Uploaded recent logs");
+    Ok(upload.entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn line(timestamp: &str, message: &str) -> String {
+        json!({ "timestamp": timestamp, "level": "INFO", "fields": { "message": message } }).to_string()
+    }
+
+    #[test]
+    fn recent_entries_keep_the_newest_lines_in_the_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let day1 = [
+            line("2026-01-01T10:00:00.000000Z", "too old"),
+            line("2026-01-01T12:00:00.000000Z", "first"),
+        ];
+        let day2 = [
+            "not json".to_string(),
+            line("2026-01-02T08:00:00.000000Z", "second"),
+            line("2026-01-02T09:00:00.000000Z", "third"),
+        ];
+        fs::write(dir.path().join("agent.2026-01-01.log"), day1.join("\n")).unwrap();
+        fs::write(dir.path().join("agent.2026-01-02.log"), day2.join("\n")).unwrap();
+        fs::write(dir.path().join("other.txt"), line("2026-01-02T10:00:00Z", "ignored")).unwrap();
+
+        let since = DateTime::parse_from_rfc3339("2026-01-01T11:00:00Z").unwrap().with_timezone(&Utc);
+        let messages = |entries: &[serde_json::Value]| -> Vec<String> {
+            entries.iter().map(|e| e["fields"]["message"].as_str().unwrap().to_string()).collect()
+        };
+
+        let (entries, truncated) = recent_entries(dir.path(), since, 10);
+        assert_eq!(messages(&entries), ["first", "second", "third"]);
+        assert!(!truncated);
+
+        let (entries, truncated) = recent_entries(dir.path(), since, 2);
+        assert_eq!(messages(&entries), ["second", "third"]);
+        assert!(truncated);
+    }
+
+    #[test]
+    fn set_level_rejects_invalid_directives() {
+        let (_, handle) = reload::Layer::<EnvFilter, Registry>::new(EnvFilter::new("info"));
+        let control = LogControl {
+            filter: handle,
+            level: Arc::new(Mutex::new("info".to_string())),
+            dir: PathBuf::new(),
+            guard: Arc::new(Mutex::new(None)),
+        };
+        assert!(matches!(control.set_level("info,netpolicy=loud"), Err(AgentError::Policy(_))));
+        assert_eq!(control.level(), "info");
+    }
+}
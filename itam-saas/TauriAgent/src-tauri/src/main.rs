@@ -2,5 +2,11 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 fn main() {
+    // `tauriagent diagnostics ...` builds a support bundle without the UI
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("diagnostics") {
+        std::process::exit(tauriagent_lib::diagnostics::run_cli(&args[2..]));
+    }
+
     tauriagent_lib::run()
 }
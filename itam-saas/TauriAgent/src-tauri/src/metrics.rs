@@ -0,0 +1,397 @@
+// ============================================================================
+// Metrics Module
+// ============================================================================
+// Optional Prometheus endpoint for a node-local scraper or federation:
+//
+//   settings.metrics = { "enabled": true, "listen": "127.0.0.1:9464" }
+//   GET http://127.0.0.1:9464/metrics   (text format 0.0.4)
+//
+// Off by default, and only a loopback address is accepted: the endpoint
+// has no authentication.
+//
+// Agent internals (recorded where they happen, process-wide like the log
+// subscriber):
+//
+// | metric                                   | type      | labels            |
+// |------------------------------------------|-----------|-------------------|
+// | itam_agent_scan_duration_seconds         | histogram |                   |
+// | itam_agent_processes_scanned_total       | counter   |                   |
+// | itam_agent_violations_total              | counter   | severity          |
+// | itam_agent_policy_syncs_total            | counter   | result            |
+// | itam_agent_http_request_duration_seconds | histogram | method, endpoint  |
+// | itam_agent_http_requests_total           | counter   | method, endpoint, |
+// |                                          |           | status            |
+//
+// Read from AgentState at scrape time: itam_agent_info, itam_agent_up
+// (logged in and not expired), outbox depth, active violations, policy
+// rules and age, push connection, per-task runs and failures.
+//
+// Host: itam_host_cpu_usage_percent, memory and swap bytes, load averages,
+// uptime.
+//
+// HTTP requests go through api::send(); `endpoint` is the path with ids
+// replaced by ":id" (/api/agent/tasks/:id/result), so label values stay
+// bounded. status is the HTTP status code, or "error" when no response
+// arrived.
+// ============================================================================
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use sysinfo::System;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+use crate::error::AgentError;
+use crate::settings::MetricsSettings;
+use crate::state::AgentState;
+use crate::status::AuthState;
+use crate::supervisor::lock;
+
+const SCAN_BUCKETS: &[f64] = &[0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+const HTTP_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+/// A scraper that does not finish its request in time is dropped
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: &'static [f64],
+    /// Per bucket, not cumulative; the last one is +Inf
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        let bucket = self.bounds.iter().position(|bound| value <= *bound).unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        let sep = if labels.is_empty() { "" } else { "," };
+        for (i, count) in self.counts.iter().enumerate() {
+            cumulative += count;
+            let le = self.bounds.get(i).map(|b| b.to_string()).unwrap_or_else(|| "+Inf".to_string());
+            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, sep, le, cumulative);
+        }
+        let braces = |labels: &str| if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+        let _ = writeln!(out, "{}_sum{} {}", name, braces(labels), self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, braces(labels), self.count);
+    }
+}
+
+struct Counters {
+    scan_duration: Histogram,
+    processes_scanned: u64,
+    violations: BTreeMap<String, u64>,
+    syncs: BTreeMap<&'static str, u64>,
+    http_duration: BTreeMap<(String, String), Histogram>,
+    http_requests: BTreeMap<(String, String, String), u64>,
+}
+
+/// Process-wide agent counters
+pub struct Registry {
+    counters: Mutex<Counters>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self {
+            counters: Mutex::new(Counters {
+                scan_duration: Histogram::new(SCAN_BUCKETS),
+                processes_scanned: 0,
+                violations: BTreeMap::new(),
+                syncs: BTreeMap::new(),
+                http_duration: BTreeMap::new(),
+                http_requests: BTreeMap::new(),
+            }),
+        }
+    }
+}
+
+static GLOBAL: OnceLock<Registry> = OnceLock::new();
+
+/// The registry api.rs and the monitor record into
+pub fn global() -> &'static Registry {
+    GLOBAL.get_or_init(Registry::default)
+}
+
+impl Registry {
+    pub fn observe_scan(&self, duration: Duration, processes: usize) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.scan_duration.observe(duration.as_secs_f64());
+        counters.processes_scanned += processes as u64;
+    }
+
+    /// One violation alert raised
+    pub fn add_violation(&self, severity: &str) {
+        *self.counters.lock().unwrap().violations.entry(severity.to_string()).or_default() += 1;
+    }
+
+    pub fn observe_sync(&self, succeeded: bool) {
+        let result = if succeeded { "success" } else { "failure" };
+        *self.counters.lock().unwrap().syncs.entry(result).or_default() += 1;
+    }
+
+    /// `status` is None when the request failed without a response
+    pub fn observe_http(&self, method: &str, endpoint: &str, status: Option<u16>, duration: Duration) {
+        let mut counters = self.counters.lock().unwrap();
+        counters
+            .http_duration
+            .entry((method.to_string(), endpoint.to_string()))
+            .or_insert_with(|| Histogram::new(HTTP_BUCKETS))
+            .observe(duration.as_secs_f64());
+        let status = status.map(|s| s.to_string()).unwrap_or_else(|| "error".to_string());
+        *counters
+            .http_requests
+            .entry((method.to_string(), endpoint.to_string(), status))
+            .or_default() += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        let counters = self.counters.lock().unwrap();
+
+        header(out, "itam_agent_scan_duration_seconds", "histogram", "Time to snapshot and match the process table");
+        counters.scan_duration.render(out, "itam_agent_scan_duration_seconds", "");
+        header(out, "itam_agent_processes_scanned_total", "counter", "Processes matched against the policy");
+        let _ = writeln!(out, "itam_agent_processes_scanned_total {}", counters.processes_scanned);
+
+        header(out, "itam_agent_violations_total", "counter", "Violation alerts raised, by rule severity");
+        for (severity, count) in &counters.violations {
+            let _ = writeln!(out, "itam_agent_violations_total{{severity=\"{}\"}} {}", escape(severity), count);
+        }
+        header(out, "itam_agent_policy_syncs_total", "counter", "Forbidden list syncs, by result");
+        for (result, count) in &counters.syncs {
+            let _ = writeln!(out, "itam_agent_policy_syncs_total{{result=\"{}\"}} {}", result, count);
+        }
+
+        header(out, "itam_agent_http_request_duration_seconds", "histogram", "Backend request latency");
+        for ((method, endpoint), histogram) in &counters.http_duration {
+            let labels = format!("method=\"{}\",endpoint=\"{}\"", escape(method), escape(endpoint));
+            histogram.render(out, "itam_agent_http_request_duration_seconds", &labels);
+        }
+        header(out, "itam_agent_http_requests_total", "counter", "Backend requests, by response status");
+        for ((method, endpoint, status), count) in &counters.http_requests {
+            let _ = writeln!(
+                out,
+                "itam_agent_http_requests_total{{method=\"{}\",endpoint=\"{}\",status=\"{}\"}} {}",
+                escape(method),
+                escape(endpoint),
+                status,
+                count
+            );
+        }
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    header(out, name, "gauge", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Path with ids replaced by ":id", so each route is one label value
+pub fn endpoint_label(path: &str) -> String {
+    let mut previous = "";
+    let segments: Vec<&str> = path
+        .split('/')
+        .map(|segment| {
+            let is_id = segment.chars().any(|c| c.is_ascii_digit()) || previous == "device";
+            previous = segment;
+            if is_id && !segment.is_empty() {
+                ":id"
+            } else {
+                segment
+            }
+        })
+        .collect();
+    segments.join("/")
+}
+
+fn render_state(out: &mut String, state: &AgentState) {
+    let status = state.status();
+    header(out, "itam_agent_info", "gauge", "Agent version");
+    let _ = writeln!(
+        out,
+        "itam_agent_info{{version=\"{}\",device_id=\"{}\"}} 1",
+        escape(&status.agent_version),
+        escape(&status.device_id)
+    );
+    gauge(out, "itam_agent_up", "Logged in with an accepted token", u8::from(status.auth == AuthState::Authenticated));
+    gauge(out, "itam_agent_outbox_depth", "Records waiting for upload", status.outbox_depth);
+    gauge(out, "itam_agent_active_violations", "Violations currently running", status.active_violations);
+    gauge(out, "itam_agent_policy_rules", "Forbidden app rules enforced", status.policy.rules);
+    gauge(out, "itam_agent_policy_stale", "Policy older than max_cache_age_secs", u8::from(status.policy.stale));
+    if let Some(age) = status.policy.age_secs {
+        gauge(out, "itam_agent_policy_age_seconds", "Time since the server confirmed the policy", age);
+    }
+    gauge(out, "itam_agent_push_connected", "Server push stream open", u8::from(status.push.connected));
+
+    header(out, "itam_agent_task_runs_total", "counter", "Supervised task runs");
+    for task in &status.tasks {
+        let _ = writeln!(out, "itam_agent_task_runs_total{{task=\"{}\"}} {}", escape(&task.name), task.runs);
+    }
+    header(out, "itam_agent_task_consecutive_failures", "gauge", "Failures since the task last succeeded");
+    for task in &status.tasks {
+        let _ = writeln!(
+            out,
+            "itam_agent_task_consecutive_failures{{task=\"{}\"}} {}",
+            escape(&task.name),
+            task.consecutive_failures
+        );
+    }
+}
+
+fn render_host(out: &mut String, sys: &mut System) {
+    sys.refresh_cpu();
+    sys.refresh_memory();
+    gauge(out, "itam_host_cpu_usage_percent", "CPU usage since the previous scrape", sys.global_cpu_info().cpu_usage());
+    gauge(out, "itam_host_memory_total_bytes", "Physical memory", sys.total_memory());
+    gauge(out, "itam_host_memory_used_bytes", "Physical memory in use", sys.used_memory());
+    gauge(out, "itam_host_swap_total_bytes", "Swap space", sys.total_swap());
+    gauge(out, "itam_host_swap_used_bytes", "Swap space in use", sys.used_swap());
+    let load = System::load_average();
+    header(out, "itam_host_load_average", "gauge", "Load average (0 on Windows)");
+    for (window, value) in [("1m", load.one), ("5m", load.five), ("15m", load.fifteen)] {
+        let _ = writeln!(out, "itam_host_load_average{{window=\"{}\"}} {}", window, value);
+    }
+    gauge(out, "itam_host_uptime_seconds", "Time since boot", System::uptime());
+}
+
+/// The full /metrics page
+pub fn render(state: &AgentState, sys: &mut System) -> String {
+    let mut out = String::new();
+    render_state(&mut out, state);
+    global().render(&mut out);
+    render_host(&mut out, sys);
+    out
+}
+
+/// Bind settings.metrics.listen, refusing anything but loopback
+pub async fn bind(settings: &MetricsSettings) -> Result<TcpListener, AgentError> {
+    let addr: SocketAddr = settings
+        .listen
+        .parse()
+        .map_err(|e| AgentError::Policy(format!("Invalid metrics address {:?}: {}", settings.listen, e)))?;
+    if !addr.ip().is_loopback() {
+        return Err(AgentError::Policy(format!(
+            "Metrics address {} is not loopback; the endpoint has no authentication",
+            addr
+        )));
+    }
+    Ok(TcpListener::bind(addr).await?)
+}
+
+async fn respond(mut stream: TcpStream, state: Arc<AgentState>, sys: Arc<Mutex<System>>) {
+    let mut buf = [0u8; 2048];
+    let n = match tokio::time::timeout(REQUEST_READ_TIMEOUT, stream.read(&mut buf)).await {
+        Ok(Ok(n)) if n > 0 => n,
+        _ => return,
+    };
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let mut request_line = request.lines().next().unwrap_or("").split_whitespace();
+    let (method, path) = (request_line.next().unwrap_or(""), request_line.next().unwrap_or(""));
+
+    let (status, content_type, body) = match (method, path.split('?').next().unwrap_or(path)) {
+        ("GET", "/metrics") => {
+            // Refreshing host stats blocks; keep it off the runtime workers
+            let rendered = tokio::task::spawn_blocking(move || render(&state, &mut lock(&sys))).await;
+            match rendered {
+                Ok(body) => ("200 OK", "text/plain; version=0.0.4; charset=utf-8", body),
+                Err(_) => ("500 Internal Server Error", "text/plain", "Rendering failed\n".to_string()),
+            }
+        }
+        ("GET", _) => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "Method not allowed\n".to_string()),
+    };
+    debug!(method, path, status, "Metrics request");
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Answer scrapes until `cancel`
+pub async fn serve(listener: TcpListener, state: Arc<AgentState>, cancel: CancellationToken) -> Result<(), AgentError> {
+    if let Ok(addr) = listener.local_addr() {
+        info!(%addr, "Serving Prometheus metrics");
+    }
+    let sys = Arc::new(Mutex::new(System::new()));
+    loop {
+        let (stream, _) = tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            accepted = listener.accept() => accepted?,
+        };
+        let state = state.clone();
+        let sys = sys.clone();
+        tokio::spawn(respond(stream, state, sys));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoint_labels_hide_ids() {
+        assert_eq!(endpoint_label("/api/agent/tasks/42/result"), "/api/agent/tasks/:id/result");
+        assert_eq!(endpoint_label("/api/alerts/device/laptop-anna"), "/api/alerts/device/:id");
+        assert_eq!(endpoint_label("/api/forbidden-apps"), "/api/forbidden-apps");
+    }
+
+    #[test]
+    fn registry_renders_cumulative_histograms_and_labelled_counters() {
+        let registry = Registry::default();
+        registry.observe_scan(Duration::from_millis(30), 120);
+        registry.observe_scan(Duration::from_secs(20), 80);
+        registry.add_violation("High");
+        registry.add_violation("High");
+        registry.observe_http("GET", "/api/forbidden-apps", Some(304), Duration::from_millis(80));
+        registry.observe_http("POST", "/api/alerts", None, Duration::from_secs(1));
+
+        let mut out = String::new();
+        registry.render(&mut out);
+        for line in [
+            "itam_agent_scan_duration_seconds_bucket{le=\"0.01\"} 0",
+            "itam_agent_scan_duration_seconds_bucket{le=\"0.05\"} 1",
+            "itam_agent_scan_duration_seconds_bucket{le=\"+Inf\"} 2",
+            "itam_agent_scan_duration_seconds_count 2",
+            "itam_agent_processes_scanned_total 200",
+            "itam_agent_violations_total{severity=\"High\"} 2",
+            "itam_agent_http_request_duration_seconds_bucket{method=\"GET\",endpoint=\"/api/forbidden-apps\",le=\"0.1\"} 1",
+            "itam_agent_http_requests_total{method=\"POST\",endpoint=\"/api/alerts\",status=\"error\"} 1",
+        ] {
+            assert!(out.lines().any(|l| l == line), "missing {:?} in\n{}", line, out);
+        }
+    }
+}
@@ -0,0 +1,517 @@
+// ============================================================================
+// Forbidden App Monitor Module
+// ============================================================================
+// ForbiddenMonitor holds the policy and enforcement state; one tick() is
+// one pass of the monitoring loop:
+// 1. Revalidates forbidden app list with the API (default every 5 minutes,
+//    see settings.policy.sync_interval_secs); unchanged lists cost a 304
+// 2. Syncs network rules and picks up decisions on exception requests
+// 3. Reports "policy stale" when the cached list outlives
+//    settings.policy.max_cache_age_secs (e.g. API unreachable for a day)
+// 4. Scans running processes and, when enabled (settings.network), outbound
+//    connections against network rules
+// 5. Reports violations to backend API, one alert per device, rule and
+//    executable with re-alerts per settings.aggregation (aggregate.rs).
+//    On shutdown, queue_unreported_alerts() moves what no alert covers yet
+//    into the outbox
+// 6. Queues audit-mode matches in the outbox as would-have-violated
+//    telemetry (POST /api/agent/policy-audit) - no alert, no notification
+// 7. Flushes the outbox
+//
+// In the app the steps run as separate supervised tasks (policy-sync,
+// process-scan, outbox; see lib.rs), sharing the monitor behind a mutex.
+// Keeping it free of Tauri types lets the integration tests drive it
+// against a mock backend with recorded process tables.
+//
+// After every step the monitor publishes a MonitorStatus (status.rs) to a
+// shared handle, so the agent status never waits on the monitor's lock
+// while a sync or upload is in flight.
+//
+// Everything the user should see is raised as a MonitorEvent. In the app,
+// TauriEvents turns them into frontend events plus native notifications:
+// - forbidden-list-updated: ForbiddenListDiff (added/removed rules + total)
+// - policy-stale: PolicyStaleness, emitted whenever the stale flag flips
+// - violation-detected: ViolationReport (enforced rules only), raised
+//   whether or not the report reached the backend; alert_id is None when
+//   it did not, and a retried report raises it again
+// - exception-approved: Vec<LocalException> newly applied from the server
+// - policy-audit-match: ViolationReport (audit rules, for diagnostics only)
+// - auth expiry: notification only
+//
+// Error Handling:
+// - Starts from the on-disk cache and keeps it if an API fetch fails
+// - Continues monitoring even if reporting fails
+// - Steps return the typed AgentError (error.rs) so the supervisor can pick
+//   the retry delay; any Unauthorized raises "auth expired"
+// - Logs errors through tracing to the JSON log files (logging.rs)
+// ============================================================================
+
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tauri::{AppHandle, Emitter};
+use tracing::{error, info, warn};
+
+use crate::api::{unix_now, Backend};
+use crate::aggregate::ViolationAggregator;
+use crate::error::AgentError;
+use crate::evidence::EvidenceCollector;
+use crate::exceptions::{Exceptions, LocalException};
+use crate::forbidden::{
+    cache_to_disk, clear_cache, device_policy_context, get_device_id, load_from_cache, policy_staleness,
+    report_violation, sync_forbidden_list, warn_unknown_timezones, ForbiddenAppCache, ForbiddenListDiff,
+    PolicyStaleness, ProcessScanner, ViolationReport,
+};
+use crate::metrics;
+use crate::netpolicy::{resolve_domains, sync_network_rules, NetworkMonitor};
+use crate::notify::Notifier;
+use crate::outbox::Outbox;
+use crate::process_source::ProcessSource;
+use crate::settings::{AgentSettings, DeviceSettings};
+use crate::status::{MonitorStatus, PolicyStatus};
+
+#[derive(Debug, Clone)]
+pub enum MonitorEvent {
+    ForbiddenListUpdated(ForbiddenListDiff),
+    PolicyStale(PolicyStaleness),
+    ViolationDetected(ViolationReport),
+    ExceptionApproved(Vec<LocalException>),
+    PolicyAuditMatch(ViolationReport),
+    AuthExpired,
+}
+
+/// Receives everything the monitor wants the user to know about
+pub trait MonitorEvents: Send {
+    fn raise(&mut self, event: MonitorEvent);
+}
+
+/// Frontend events plus native notifications (notify.rs)
+pub struct TauriEvents {
+    handle: AppHandle,
+    notifier: Notifier,
+}
+
+impl TauriEvents {
+    pub fn new(handle: AppHandle, notifier: Notifier) -> Self {
+        Self { handle, notifier }
+    }
+}
+
+impl MonitorEvents for TauriEvents {
+    fn raise(&mut self, event: MonitorEvent) {
+        match event {
+            MonitorEvent::ForbiddenListUpdated(diff) => {
+                let _ = self.handle.emit("forbidden-list-updated", &diff);
+            }
+            MonitorEvent::PolicyStale(staleness) => {
+                if staleness.stale {
+                    self.notifier.policy_stale(&staleness);
+                }
+                let _ = self.handle.emit("policy-stale", &staleness);
+            }
+            MonitorEvent::ViolationDetected(violation) => {
+                let _ = self.handle.emit("violation-detected", &violation);
+                self.notifier.violation(&violation);
+            }
+            MonitorEvent::ExceptionApproved(applied) => {
+                let _ = self.handle.emit("exception-approved", &applied);
+                self.notifier.exceptions_applied(&applied);
+            }
+            MonitorEvent::PolicyAuditMatch(audit_match) => {
+                let _ = self.handle.emit("policy-audit-match", &audit_match);
+            }
+            MonitorEvent::AuthExpired => self.notifier.auth_expired(),
+        }
+    }
+}
+
+/// Process source and the scanners; snapshots and evidence hashing block,
+/// so a scan runs on Tokio's blocking pool with its own handle to them
+struct Scanners {
+    source: Box<dyn ProcessSource + Send>,
+    processes: ProcessScanner,
+    network: NetworkMonitor,
+    evidence: EvidenceCollector,
+}
+
+pub struct ForbiddenMonitor {
+    backend: Backend,
+    settings: AgentSettings,
+    events: Box<dyn MonitorEvents>,
+    policy: ForbiddenAppCache,
+    outbox: Outbox,
+    exceptions: Exceptions,
+    /// Shared with AgentState; the heartbeat task writes it
+    device: Arc<RwLock<DeviceSettings>>,
+    scanners: Arc<Mutex<Scanners>>,
+    aggregator: ViolationAggregator,
+    policy_stale: bool,
+    last_sync: SystemTime,
+    last_upload: Option<u64>,
+    auth_rejected: bool,
+    status: Arc<Mutex<MonitorStatus>>,
+}
+
+impl ForbiddenMonitor {
+    /// Start from the on-disk policy cache and outbox
+    pub fn new(
+        backend: &Backend,
+        settings: AgentSettings,
+        process_source: Box<dyn ProcessSource + Send>,
+        events: Box<dyn MonitorEvents>,
+    ) -> Self {
+        let policy = load_from_cache().unwrap_or_else(|e| {
+            warn!("Ignoring forbidden list cache: {}", e);
+            ForbiddenAppCache::default()
+        });
+        warn_unknown_timezones(&policy.apps);
+        let exceptions = Exceptions::load();
+        let settings_device = settings.device.clone();
+        let evidence = EvidenceCollector::new(settings.evidence.clone());
+        Self {
+            backend: backend.clone(),
+            aggregator: ViolationAggregator::new(settings.aggregation.clone()),
+            settings,
+            events,
+            policy,
+            outbox: Outbox::load(),
+            scanners: Arc::new(Mutex::new(Scanners {
+                source: process_source,
+                processes: ProcessScanner::new(exceptions.clone()),
+                network: NetworkMonitor::default(),
+                evidence,
+            })),
+            exceptions,
+            device: Arc::new(RwLock::new(settings_device)),
+            policy_stale: false,
+            last_sync: SystemTime::UNIX_EPOCH,
+            last_upload: None,
+            auth_rejected: false,
+            status: Arc::default(),
+        }
+    }
+
+    /// Exception store shared with the scanner
+    pub fn exceptions(&self) -> Exceptions {
+        self.exceptions.clone()
+    }
+
+    /// Device tags and org unit, updated from heartbeat responses
+    pub fn device_handle(&self) -> Arc<RwLock<DeviceSettings>> {
+        self.device.clone()
+    }
+
+    /// A scan that panicked leaves the scanners usable for the next one
+    fn scanners(&self) -> MutexGuard<'_, Scanners> {
+        self.scanners.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Shared handle the monitor publishes its MonitorStatus to
+    pub fn status_handle(&self) -> Arc<Mutex<MonitorStatus>> {
+        self.publish_status();
+        self.status.clone()
+    }
+
+    fn publish_status(&self) {
+        let staleness = policy_staleness(&self.policy, self.settings.policy.max_cache_age_secs);
+        let confirmed = self.policy.last_updated != 0;
+        let last_sync = self
+            .last_sync
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_secs())
+            .filter(|secs| *secs > 0);
+        let scanners = self.scanners();
+        let active_violations = if self.settings.policy.audit_only {
+            0
+        } else {
+            scanners.processes.active_violations() + scanners.network.active_violations()
+        };
+
+        *self.status.lock().unwrap() = MonitorStatus {
+            auth_rejected: self.auth_rejected,
+            last_sync,
+            last_upload: self.last_upload,
+            policy: PolicyStatus {
+                version: self.policy.etag.clone(),
+                rules: self.policy.apps.len(),
+                network_rules: scanners.network.rule_count(),
+                last_updated: confirmed.then_some(self.policy.last_updated),
+                age_secs: confirmed.then_some(staleness.age_secs),
+                stale: staleness.stale,
+            },
+            outbox_depth: self.outbox.len(),
+            active_violations,
+        };
+    }
+
+    /// Queue a record for upload through the outbox
+    pub fn enqueue(&mut self, endpoint: &str, payload: serde_json::Value) {
+        self.outbox.push(endpoint, payload);
+        self.publish_status();
+    }
+
+    /// Queue aggregated violations no alert has covered yet in the outbox,
+    /// so they are not lost when the agent stops (on shutdown)
+    pub fn queue_unreported_alerts(&mut self) {
+        let unreported = self.aggregator.take_unreported(unix_now());
+        if unreported.is_empty() {
+            return;
+        }
+        info!("Queueing {} unreported alerts", unreported.len());
+        for report in unreported {
+            match serde_json::to_value(&report) {
+                Ok(payload) => self.outbox.push("/api/alerts", payload),
+                Err(e) => error!("Failed to queue alert: {}", e),
+            }
+        }
+        self.publish_status();
+    }
+
+    /// Write the policy cache and outbox to disk (on shutdown)
+    pub fn persist(&self) {
+        if self.policy.last_updated != 0 {
+            if let Err(e) = cache_to_disk(&self.policy) {
+                warn!("Failed to persist forbidden list cache: {}", e);
+            }
+        }
+        self.outbox.persist();
+    }
+
+    /// Drop the cached forbidden list (memory and disk); enforcement is off
+    /// until the next sync
+    pub fn clear_policy_cache(&mut self) -> Result<(), AgentError> {
+        self.policy = ForbiddenAppCache::default();
+        self.last_sync = SystemTime::UNIX_EPOCH;
+        let result = clear_cache();
+        self.publish_status();
+        result
+    }
+
+    /// Remember a 401 from the backend and tell the user
+    fn auth_expired(&mut self) {
+        self.auth_rejected = true;
+        self.events.raise(MonitorEvent::AuthExpired);
+    }
+
+    /// One full monitoring pass with the current auth token. The app runs
+    /// the steps as separate supervised tasks (supervisor.rs) instead.
+    pub async fn tick(&mut self, auth_token: &str) {
+        if self.sync_due() {
+            let _ = self.sync(auth_token).await;
+        }
+        self.check_staleness();
+        let _ = self.scan(auth_token).await;
+        let _ = self.flush_outbox(auth_token).await;
+    }
+
+    /// Whether settings.policy.sync_interval_secs passed since the last sync
+    pub fn sync_due(&self) -> bool {
+        let time_since_sync = SystemTime::now()
+            .duration_since(self.last_sync)
+            .unwrap_or(Duration::from_secs(999999));
+        time_since_sync.as_secs() >= self.settings.policy.sync_interval_secs
+    }
+
+    /// Report transitions between fresh and stale policy
+    pub fn check_staleness(&mut self) {
+        let staleness = policy_staleness(&self.policy, self.settings.policy.max_cache_age_secs);
+        if staleness.stale != self.policy_stale {
+            self.policy_stale = staleness.stale;
+            if self.policy_stale {
+                warn!("Policy stale: forbidden list last confirmed {}s ago", staleness.age_secs);
+            } else {
+                info!("Policy fresh again");
+            }
+            self.events.raise(MonitorEvent::PolicyStale(staleness));
+        }
+        self.publish_status();
+    }
+
+    /// Upload queued telemetry (audit matches survive offline periods).
+    /// Fails with the error that paused the upload, so the task retries
+    /// sooner (or after Retry-After when rate limited).
+    pub async fn flush_outbox(&mut self, auth_token: &str) -> Result<(), AgentError> {
+        if self.outbox.is_empty() {
+            return Ok(());
+        }
+        let result = self.outbox.flush(&self.backend, auth_token).await;
+        if result.sent > 0 {
+            self.last_upload = Some(unix_now());
+            info!("Uploaded {} queued records ({} remaining)", result.sent, result.remaining);
+        }
+        if let Some(e) = result.error {
+            if e.needs_reauth() {
+                self.auth_expired();
+            }
+            self.publish_status();
+            warn!("Outbox upload paused: {} records waiting", result.remaining);
+            return Err(e);
+        }
+        self.publish_status();
+        Ok(())
+    }
+
+    /// Revalidate the forbidden list, then network rules and exception
+    /// requests. Fails when the forbidden list could not be synced.
+    pub async fn sync(&mut self, auth_token: &str) -> Result<(), AgentError> {
+        let result = match sync_forbidden_list(&self.backend, auth_token, &self.policy).await {
+            Ok(outcome) => {
+                self.last_sync = SystemTime::now();
+                self.auth_rejected = false;
+                self.policy = outcome.cache;
+
+                if outcome.not_modified {
+                    info!("Forbidden list unchanged ({} apps)", self.policy.apps.len());
+                } else {
+                    info!(
+                        "Synced {} forbidden apps (+{} / -{})",
+                        outcome.diff.total,
+                        outcome.diff.added.len(),
+                        outcome.diff.removed.len()
+                    );
+                    warn_unknown_timezones(&self.policy.apps);
+                }
+
+                // Emit only real changes to frontend
+                if !outcome.diff.is_empty() {
+                    self.events.raise(MonitorEvent::ForbiddenListUpdated(outcome.diff));
+                }
+                Ok(())
+            }
+            Err(e) => {
+                error!("Failed to sync forbidden list: {}", e);
+                if e.needs_reauth() {
+                    self.auth_expired();
+                }
+                Err(e)
+            }
+        };
+
+        if self.settings.network.enabled {
+            match sync_network_rules(&self.backend, auth_token).await {
+                Ok(rules) => {
+                    info!("Synced {} network rules", rules.len());
+                    let resolved = resolve_domains(&rules).await;
+                    self.scanners().network.set_rules(rules, &resolved);
+                }
+                Err(e) => error!("Failed to sync network rules: {}", e),
+            }
+        }
+
+        // Rebuild approved exceptions from the server, revocations included;
+        // on failure the last known set stays in place
+        match self.exceptions.refresh(&self.backend, auth_token, &get_device_id()).await {
+            Ok((_, applied)) if !applied.is_empty() => {
+                info!("Applied {} approved exceptions", applied.len());
+                self.events.raise(MonitorEvent::ExceptionApproved(applied));
+            }
+            Ok(_) => {}
+            Err(e) => error!("Failed to check exception requests: {}", e),
+        }
+
+        metrics::global().observe_sync(result.is_ok());
+        self.publish_status();
+        result
+    }
+
+    /// Scan processes and connections, then report what is due.
+    /// Fails when a violation report could not be delivered.
+    pub async fn scan(&mut self, auth_token: &str) -> Result<(), AgentError> {
+        let result = self.scan_and_report(auth_token).await;
+        self.publish_status();
+        result
+    }
+
+    async fn scan_and_report(&mut self, auth_token: &str) -> Result<(), AgentError> {
+        if self.policy.apps.is_empty() && !self.scanners().network.has_rules() {
+            return Ok(());
+        }
+
+        // Rule schedules and scope are evaluated against the current time
+        let ctx = device_policy_context(&self.device.read().unwrap_or_else(PoisonError::into_inner));
+        let audit_only = self.settings.policy.audit_only;
+        let apps = self.policy.apps.clone();
+        let scanners = self.scanners.clone();
+        let started = Instant::now();
+        let (matches, processes) = tokio::task::spawn_blocking(move || {
+            let mut guard = scanners.lock().unwrap_or_else(PoisonError::into_inner);
+            let Scanners { source, processes, network, evidence } = &mut *guard;
+            let table = source.snapshot();
+            let mut matches = processes.scan(&table, &apps, &ctx, evidence, audit_only);
+            matches.extend(network.scan(&table, &ctx, evidence, audit_only));
+            (matches, table.len())
+        })
+        .await
+        .map_err(|e| AgentError::Io(format!("process scan panicked: {}", e)))?;
+        metrics::global().observe_scan(started.elapsed(), processes);
+        let (violations, audit_matches): (Vec<_>, Vec<_>) =
+            matches.into_iter().partition(|v| v.mode.is_enforce());
+
+        // Audit matches: record only, never alert or notify the user
+        for audit_match in audit_matches {
+            info!("Audit match (not enforced): {}", audit_match.app_detected);
+            match serde_json::to_value(&audit_match) {
+                Ok(payload) => self.outbox.push("/api/agent/policy-audit", payload),
+                Err(e) => error!("Failed to record audit match: {}", e),
+            }
+            self.events.raise(MonitorEvent::PolicyAuditMatch(audit_match));
+        }
+
+        // Repeated PIDs of the same app are folded into one alert
+        let now = unix_now();
+        let detected = violations.len();
+        let violations = self.aggregator.ingest(violations, now);
+        if violations.is_empty() {
+            return Ok(());
+        }
+        for violation in &violations {
+            metrics::global().add_violation(&violation.severity);
+        }
+        warn!("Detected {} violations ({} alerts due)", detected, violations.len());
+
+        // Report each violation
+        let due = violations.len();
+        let mut reported = 0;
+        let mut last_error = None;
+        for mut violation in violations {
+            match report_violation(&self.backend, auth_token, &violation).await {
+                Ok(alert_id) => {
+                    info!("Reported: {}", violation.app_detected);
+                    self.aggregator.mark_reported(&violation, alert_id, now);
+                    self.last_upload = Some(now);
+                    reported += 1;
+
+                    // alert_id lets the user attach a justification
+                    violation.alert_id = alert_id;
+                    self.events.raise(MonitorEvent::ViolationDetected(violation));
+                }
+                Err(e) => {
+                    error!("Failed to report violation: {}", e);
+                    if e.needs_reauth() {
+                        self.auth_expired();
+                    }
+                    // Aggregated groups stay due until mark_reported; a plain
+                    // report is retried by letting the scanners find it again
+                    if violation.aggregate.is_none() {
+                        let mut scanners = self.scanners();
+                        match violation.network {
+                            Some(_) => scanners.network.forget(&violation),
+                            None => scanners.processes.forget(&violation),
+                        }
+                    }
+                    // The user is told even when the backend is unreachable
+                    self.events.raise(MonitorEvent::ViolationDetected(violation));
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match last_error {
+            Some(e) => {
+                warn!("{} of {} due alerts will be retried", due - reported, due);
+                Err(e)
+            }
+            None => Ok(()),
+        }
+    }
+}
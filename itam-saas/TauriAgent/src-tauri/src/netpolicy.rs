@@ -0,0 +1,702 @@
+// ============================================================================
+// Network Destination Policy Module
+// ============================================================================
+// Forbidden apps only match process names, but many policy breaches are web
+// services or P2P traffic. This module attributes outbound TCP connections
+// to processes and matches them against server-provided network rules.
+//
+// Rules: GET /api/network-rules (cached to network_rules_cache.json)
+// [
+//   { "id": 4, "name": "Torrent trackers", "severity": "High",
+//     "cidrs": ["203.0.113.0/24", "198.51.100.7"], "ports": [6881, 6969] },
+//   { "id": 5, "name": "File sharing", "severity": "Medium",
+//     "domains": ["wetransfer.com", "mega.nz"], "mode": "audit",
+//     "schedule": { "timezone": "Europe/Berlin",
+//                   "windows": [{ "days": ["mon","tue","wed","thu","fri"], "start": "09:00", "end": "17:00" }] },
+//     "scope": { "device_tags": ["finance"] }, "exceptions": { "os_users": ["backup"] } }
+// ]
+// schedule, scope and exceptions work exactly as for forbidden apps
+// (policy.rs); the OS user is the owner of the connecting process.
+// A connection matches a rule when its remote address is inside one of the
+// rule's CIDRs or resolves from one of its domains, and (if the rule lists
+// ports) its remote port is one of them. A rule with only ports matches any
+// destination on those ports.
+//
+// Domains are resolved through the system resolver whenever the rules are
+// synced, so CDN rotation is picked up on the next sync. Domains resolve
+// concurrently through tokio::net::lookup_host, bounded by DNS_TIMEOUT, so a
+// slow resolver never blocks a runtime thread and holds the monitor (which
+// syncs under its lock) for DNS_TIMEOUT at most. Wildcards are not expanded -
+// list the concrete host names.
+//
+// Attribution (Linux only; other platforms report nothing):
+// 1. /proc/net/tcp and /proc/net/tcp6 -> socket inode per connection
+//    (ESTABLISHED and SYN_SENT only; listening sockets are ignored)
+// 2. /proc/<pid>/fd/* -> "socket:[inode]" links map inodes to PIDs
+// 3. /proc/<pid>/comm -> process name
+// Without root, sockets of other users' processes cannot be attributed.
+//
+// Hits are turned into ViolationReports (with a `network` section) and go
+// through the same pipeline as forbidden apps: audit/enforce split,
+// /api/alerts, `violation-detected` and native notifications. Each live
+// connection is reported once; a reconnect is a new hit. A sync keeps what
+// was reported for rules that are still served (same id and name), so a
+// long-lived connection is not reported again after every sync.
+// ============================================================================
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::api::{self, Backend};
+use crate::error::AgentError;
+use crate::evidence::EvidenceCollector;
+use crate::forbidden::ViolationReport;
+use crate::policy::{
+    rule_active_for_device, rule_applies_to_user, warn_unknown_timezone, PolicyContext, RuleExceptions, RuleMode,
+    RuleSchedule, RuleScope,
+};
+use crate::process_source::ProcessTable;
+use crate::settings::agent_config_dir;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkRule {
+    #[serde(default)]
+    pub id: Option<i64>,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default = "default_severity")]
+    pub severity: String,
+    #[serde(default)]
+    pub cidrs: Vec<String>,
+    #[serde(default)]
+    pub domains: Vec<String>,
+    #[serde(default)]
+    pub ports: Vec<u16>,
+    #[serde(default)]
+    pub mode: RuleMode,
+    /// Optional qualifiers, see policy.rs. None = always / everywhere
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<RuleSchedule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<RuleScope>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exceptions: Option<RuleExceptions>,
+}
+
+/// Upper bound for resolving one rule domain
+const DNS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Addresses each rule domain resolved to, from resolve_domains()
+pub type ResolvedDomains = HashMap<String, Vec<IpAddr>>;
+
+fn default_severity() -> String {
+    "Medium".to_string()
+}
+
+/// What the connection looked like, attached to the ViolationReport
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkEvidence {
+    pub network_rule_id: Option<i64>,
+    pub rule_name: String,
+    pub remote_ip: String,
+    pub remote_port: u16,
+    /// Rule domain the remote IP was resolved from, if matched by domain
+    pub domain: Option<String>,
+}
+
+/// One row of /proc/net/tcp{,6} (local address omitted, never needed)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketEntry {
+    pub remote: SocketAddr,
+    pub state: u8,
+    pub inode: u64,
+}
+
+struct CompiledRule {
+    rule: NetworkRule,
+    nets: Vec<IpNet>,
+    domain_ips: Vec<(IpAddr, String)>,
+}
+
+#[derive(Default)]
+pub struct NetworkMonitor {
+    rules: Vec<CompiledRule>,
+    /// (pid, rule index, remote address) already reported
+    reported: HashSet<(u32, usize, SocketAddr)>,
+}
+
+fn get_cache_path() -> PathBuf {
+    agent_config_dir().join("network_rules_cache.json")
+}
+
+/// Fetch network rules from the API, falling back to the disk cache
+pub async fn sync_network_rules(backend: &Backend, token: &str) -> Result<Vec<NetworkRule>, AgentError> {
+    let url = backend.url("/api/network-rules");
+
+    let client = backend.client();
+    let fetched: Result<Vec<NetworkRule>, AgentError> = async {
+        let response = api::send(client.get(&url).header("Authorization", format!("Bearer {}", token))).await?;
+        let response = api::ensure_success(response).await?;
+        response
+            .json()
+            .await
+            .map_err(|e| AgentError::Parse(e.to_string()))
+    }
+    .await;
+
+    match fetched {
+        Ok(rules) => {
+            if let Ok(json) = serde_json::to_string_pretty(&rules) {
+                if let Err(e) = fs::write(get_cache_path(), json) {
+                    warn!("Failed to cache network rules: {}", e);
+                }
+            }
+            Ok(rules)
+        }
+        Err(e) => {
+            warn!("Failed to fetch network rules: {}. Loading from cache...", e);
+            let path = get_cache_path();
+            if !path.exists() {
+                return Err(e);
+            }
+            let json = fs::read_to_string(path)?;
+            Ok(serde_json::from_str(&json)?)
+        }
+    }
+}
+
+/// Parse "10.0.0.0/8" or a bare address as a single-host network
+fn parse_net(value: &str) -> Option<IpNet> {
+    let value = value.trim();
+    value
+        .parse::<IpNet>()
+        .ok()
+        .or_else(|| value.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+async fn resolve_domain(domain: &str) -> Vec<IpAddr> {
+    let host = domain.trim().trim_start_matches("*.");
+    match tokio::time::timeout(DNS_TIMEOUT, tokio::net::lookup_host((host, 0))).await {
+        Ok(Ok(addrs)) => addrs.map(|a| a.ip()).collect(),
+        Ok(Err(e)) => {
+            warn!("Could not resolve {}: {}", host, e);
+            Vec::new()
+        }
+        Err(_) => {
+            warn!("Could not resolve {}: timed out", host);
+            Vec::new()
+        }
+    }
+}
+
+/// Resolve every domain named by `rules` concurrently, for
+/// NetworkMonitor::set_rules
+pub async fn resolve_domains(rules: &[NetworkRule]) -> ResolvedDomains {
+    let domains: HashSet<&String> = rules.iter().flat_map(|rule| &rule.domains).collect();
+    let mut lookups = tokio::task::JoinSet::new();
+    for domain in domains {
+        let domain = domain.clone();
+        lookups.spawn(async move {
+            let ips = resolve_domain(&domain).await;
+            (domain, ips)
+        });
+    }
+
+    let mut resolved = ResolvedDomains::new();
+    while let Some(lookup) = lookups.join_next().await {
+        if let Ok((domain, ips)) = lookup {
+            resolved.insert(domain, ips);
+        }
+    }
+    resolved
+}
+
+/// IPv4-mapped IPv6 addresses (::ffff:a.b.c.d) compare as IPv4
+fn normalize_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    }
+}
+
+fn parse_hex_ipv4(hex: &str) -> Option<Ipv4Addr> {
+    let raw = u32::from_str_radix(hex, 16).ok()?;
+    // The kernel prints the raw network-order bytes as a native u32
+    Some(Ipv4Addr::from(raw.to_ne_bytes()))
+}
+
+fn parse_hex_ipv6(hex: &str) -> Option<Ipv6Addr> {
+    if hex.len() != 32 {
+        return None;
+    }
+    let mut bytes = [0u8; 16];
+    for (i, chunk) in bytes.chunks_mut(4).enumerate() {
+        let word = u32::from_str_radix(&hex[i * 8..i * 8 + 8], 16).ok()?;
+        chunk.copy_from_slice(&word.to_ne_bytes());
+    }
+    Some(Ipv6Addr::from(bytes))
+}
+
+fn parse_hex_socket(value: &str, ipv6: bool) -> Option<SocketAddr> {
+    let (ip, port) = value.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let ip = if ipv6 {
+        IpAddr::V6(parse_hex_ipv6(ip)?)
+    } else {
+        IpAddr::V4(parse_hex_ipv4(ip)?)
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+/// Parse the contents of /proc/net/tcp (ipv6 = false) or /proc/net/tcp6
+pub fn parse_proc_net_tcp(content: &str, ipv6: bool) -> Vec<SocketEntry> {
+    content
+        .lines()
+        .skip(1) // header
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 10 {
+                return None;
+            }
+            Some(SocketEntry {
+                remote: parse_hex_socket(fields[2], ipv6)?,
+                state: u8::from_str_radix(fields[3], 16).ok()?,
+                inode: fields[9].parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Outbound connections with the owning PID, read from /proc
+#[cfg(target_os = "linux")]
+fn active_connections() -> Vec<(u32, SocketAddr)> {
+    use std::collections::HashMap;
+
+    const TCP_ESTABLISHED: u8 = 0x01;
+    const TCP_SYN_SENT: u8 = 0x02;
+
+    let mut by_inode: HashMap<u64, SocketAddr> = HashMap::new();
+    for (path, ipv6) in [("/proc/net/tcp", false), ("/proc/net/tcp6", true)] {
+        if let Ok(content) = fs::read_to_string(path) {
+            for entry in parse_proc_net_tcp(&content, ipv6) {
+                let open = entry.state == TCP_ESTABLISHED || entry.state == TCP_SYN_SENT;
+                if open && entry.inode != 0 && !entry.remote.ip().is_unspecified() {
+                    by_inode.insert(entry.inode, entry.remote);
+                }
+            }
+        }
+    }
+    if by_inode.is_empty() {
+        return Vec::new();
+    }
+
+    let mut connections = Vec::new();
+    let Ok(procs) = fs::read_dir("/proc") else {
+        return connections;
+    };
+    for proc_entry in procs.flatten() {
+        let Some(pid) = proc_entry.file_name().to_str().and_then(|n| n.parse::<u32>().ok()) else {
+            continue;
+        };
+        // Permission denied for other users' processes without root
+        let Ok(fds) = fs::read_dir(proc_entry.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            let Ok(target) = fs::read_link(fd.path()) else {
+                continue;
+            };
+            let target = target.to_string_lossy();
+            let inode = target
+                .strip_prefix("socket:[")
+                .and_then(|rest| rest.strip_suffix(']'))
+                .and_then(|inode| inode.parse::<u64>().ok());
+            if let Some(remote) = inode.and_then(|inode| by_inode.get(&inode)) {
+                connections.push((pid, *remote));
+            }
+        }
+    }
+    connections
+}
+
+#[cfg(not(target_os = "linux"))]
+fn active_connections() -> Vec<(u32, SocketAddr)> {
+    Vec::new()
+}
+
+impl NetworkMonitor {
+    pub fn has_rules(&self) -> bool {
+        !self.rules.is_empty()
+    }
+
+    pub fn rule_count(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Open connections currently in violation of an enforced rule
+    pub fn active_violations(&self) -> usize {
+        self.reported
+            .iter()
+            .filter(|(_, index, _)| self.rules[*index].rule.mode.is_enforce())
+            .count()
+    }
+
+    /// Forget a report that could not be delivered, so the next scan
+    /// reports its connection again
+    pub fn forget(&mut self, report: &ViolationReport) {
+        let Some(network) = &report.network else {
+            return;
+        };
+        self.reported.retain(|(pid, _, remote)| {
+            !(*pid == report.process_id
+                && remote.port() == network.remote_port
+                && normalize_ip(remote.ip()).to_string() == network.remote_ip)
+        });
+    }
+
+    /// Replace the rule set, parsing CIDRs; `resolved` comes from
+    /// resolve_domains()
+    pub fn set_rules(&mut self, rules: Vec<NetworkRule>, resolved: &ResolvedDomains) {
+        let compiled = rules
+            .into_iter()
+            .map(|rule| {
+                warn_unknown_timezone(&rule.name, rule.schedule.as_ref());
+                let nets = rule
+                    .cidrs
+                    .iter()
+                    .filter_map(|c| {
+                        let net = parse_net(c);
+                        if net.is_none() {
+                            warn!("Ignoring invalid CIDR {:?} in rule {}", c, rule.name);
+                        }
+                        net
+                    })
+                    .collect();
+                let domain_ips = rule
+                    .domains
+                    .iter()
+                    .flat_map(|d| {
+                        resolved
+                            .get(d)
+                            .into_iter()
+                            .flatten()
+                            .map(move |ip| (normalize_ip(*ip), d.clone()))
+                    })
+                    .collect();
+                CompiledRule { rule, nets, domain_ips }
+            })
+            .collect();
+        let previous = std::mem::replace(&mut self.rules, compiled);
+
+        // Rules may have moved; drop only connections of rules that are gone
+        self.reported = std::mem::take(&mut self.reported)
+            .into_iter()
+            .filter_map(|(pid, index, remote)| {
+                let old = &previous.get(index)?.rule;
+                let index = self
+                    .rules
+                    .iter()
+                    .position(|c| c.rule.id == old.id && c.rule.name == old.name)?;
+                Some((pid, index, remote))
+            })
+            .collect();
+    }
+
+    /// Index of the first matching rule and the matched domain, if any;
+    /// `applies` filters rules by schedule, scope and exceptions
+    fn match_rule(
+        &self,
+        remote: &SocketAddr,
+        applies: impl Fn(usize, &NetworkRule) -> bool,
+    ) -> Option<(usize, Option<String>)> {
+        let ip = normalize_ip(remote.ip());
+        self.rules.iter().enumerate().find_map(|(index, compiled)| {
+            let rule = &compiled.rule;
+            if !applies(index, rule) {
+                return None;
+            }
+            if !rule.ports.is_empty() && !rule.ports.contains(&remote.port()) {
+                return None;
+            }
+            if compiled.nets.is_empty() && rule.domains.is_empty() {
+                // Port-only rule
+                return (!rule.ports.is_empty()).then_some((index, None));
+            }
+            if compiled.nets.iter().any(|net| net.contains(&ip)) {
+                return Some((index, None));
+            }
+            compiled
+                .domain_ips
+                .iter()
+                .find(|(resolved, _)| *resolved == ip)
+                .map(|(_, domain)| (index, Some(domain.clone())))
+        })
+    }
+
+    /// Connections matching a rule that applies here, now and to the
+    /// connection's owner, as (pid, remote, rule index, matched domain)
+    ///
+    /// POLICY:
+    /// - Rules outside their schedule / device scope are skipped up front
+    /// - User scope and exceptions are checked against each process owner
+    fn hits(
+        &self,
+        connections: Vec<(u32, SocketAddr)>,
+        table: &ProcessTable,
+        ctx: &PolicyContext,
+    ) -> Vec<(u32, SocketAddr, usize, Option<String>)> {
+        let active: Vec<bool> = self
+            .rules
+            .iter()
+            .map(|compiled| {
+                let rule = &compiled.rule;
+                rule_active_for_device(rule.schedule.as_ref(), rule.scope.as_ref(), rule.exceptions.as_ref(), ctx)
+            })
+            .collect();
+        if !active.contains(&true) {
+            return Vec::new();
+        }
+
+        connections
+            .into_iter()
+            .filter_map(|(pid, remote)| {
+                let os_user = table.get(pid).and_then(|p| p.os_user.as_deref());
+                self.match_rule(&remote, |index, rule| {
+                    active[index] && rule_applies_to_user(rule.scope.as_ref(), rule.exceptions.as_ref(), os_user)
+                })
+                .map(|(index, domain)| (pid, remote, index, domain))
+            })
+            .collect()
+    }
+
+    /// Scan live connections and return newly detected hits; `table` names
+    /// the owning processes and supplies evidence
+    pub fn scan(
+        &mut self,
+        table: &ProcessTable,
+        ctx: &PolicyContext,
+        evidence: &mut EvidenceCollector,
+        audit_only: bool,
+    ) -> Vec<ViolationReport> {
+        if self.rules.is_empty() {
+            return Vec::new();
+        }
+
+        let hits = self.hits(active_connections(), table, ctx);
+
+        // Forget connections that are gone so a reconnect is reported again
+        let live: HashSet<(u32, usize, SocketAddr)> = hits
+            .iter()
+            .map(|(pid, remote, index, _)| (*pid, *index, *remote))
+            .collect();
+        self.reported.retain(|key| live.contains(key));
+
+        let new_hits: Vec<_> = hits
+            .into_iter()
+            .filter(|(pid, remote, index, _)| self.reported.insert((*pid, *index, *remote)))
+            .collect();
+        if new_hits.is_empty() {
+            return Vec::new();
+        }
+
+        new_hits
+            .into_iter()
+            .map(|(pid, remote, index, domain)| {
+                let rule = &self.rules[index].rule;
+                let process_name = table
+                    .get(pid)
+                    .map(|p| p.name.clone())
+                    .or_else(|| fs::read_to_string(format!("/proc/{}/comm", pid)).ok())
+                    .unwrap_or_else(|| "unknown".to_string())
+                    .trim()
+                    .to_lowercase();
+
+                ViolationReport {
+                    device_id: ctx.device_id.clone(),
+                    app_detected: process_name,
+                    severity: rule.severity.clone(),
+                    process_id: pid,
+                    rule_id: None,
+                    evidence: Some(evidence.collect(table, pid)),
+                    mode: if audit_only { RuleMode::Audit } else { rule.mode },
+                    alert_id: None,
+                    network: Some(NetworkEvidence {
+                        network_rule_id: rule.id,
+                        rule_name: rule.name.clone(),
+                        remote_ip: normalize_ip(remote.ip()).to_string(),
+                        remote_port: remote.port(),
+                        domain,
+                    }),
+                    aggregate: None,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process_source::ProcessInfo;
+    use serde_json::json;
+
+    fn rules(rules: serde_json::Value, resolved: &[(&str, &str)]) -> NetworkMonitor {
+        let resolved: ResolvedDomains = resolved
+            .iter()
+            .map(|(domain, ip)| (domain.to_string(), vec![ip.parse().unwrap()]))
+            .collect();
+        let mut monitor = NetworkMonitor::default();
+        monitor.set_rules(serde_json::from_value(rules).unwrap(), &resolved);
+        monitor
+    }
+
+    fn addr(value: &str) -> SocketAddr {
+        value.parse().unwrap()
+    }
+
+    fn ctx(now: &str) -> PolicyContext {
+        PolicyContext {
+            device_id: "test-device".to_string(),
+            device_tags: vec!["finance".to_string()],
+            org_unit: None,
+            now: chrono::DateTime::parse_from_rfc3339(now).unwrap().with_timezone(&chrono::Utc),
+        }
+    }
+
+    fn owned_by(pid: u32, os_user: &str) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            name: "curl".to_string(),
+            os_user: Some(os_user.to_string()),
+            ..ProcessInfo::default()
+        }
+    }
+
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn hex_addresses_are_in_kernel_byte_order() {
+        assert_eq!(parse_hex_ipv4("0100007F"), Some(Ipv4Addr::new(127, 0, 0, 1)));
+        assert_eq!(parse_hex_ipv4("0571CB00"), Some(Ipv4Addr::new(0, 203, 113, 5)));
+        assert_eq!(parse_hex_ipv4("057100CB"), Some(Ipv4Addr::new(203, 0, 113, 5)));
+        assert_eq!(parse_hex_ipv4("not hex"), None);
+
+        assert_eq!(parse_hex_ipv6("00000000000000000000000001000000"), Some(Ipv6Addr::LOCALHOST));
+        assert_eq!(
+            parse_hex_ipv6("B80D0120000000000000000001000000"),
+            Some("2001:db8::1".parse().unwrap())
+        );
+        assert_eq!(
+            parse_hex_ipv6("0000000000000000FFFF0000057100CB"),
+            Some("::ffff:203.0.113.5".parse().unwrap())
+        );
+        assert_eq!(parse_hex_ipv6("0100007F"), None);
+    }
+
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn proc_net_tcp_rows_are_parsed() {
+        let tcp = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 0100007F:0CEA 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 11111 1 0000000000000000 100 0 0 10 0
+   1: 0F02000A:D2B4 057100CB:1AE1 01 00000000:00000000 02:000A7D3F 00000000  1000        0 22222 2 0000000000000000 20 4 30 10 -1
+   2: garbage
+";
+        let entries = parse_proc_net_tcp(tcp, false);
+        assert_eq!(
+            entries,
+            vec![
+                SocketEntry { remote: addr("0.0.0.0:0"), state: 0x0A, inode: 11111 },
+                SocketEntry { remote: addr("203.0.113.5:6881"), state: 0x01, inode: 22222 },
+            ]
+        );
+
+        let tcp6 = "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000000000000000000001000000:9C40 B80D0120000000000000000001000000:01BB 02 00000000:00000000 00:00000000 00000000  1000        0 33333 1 0000000000000000 100 0 0 10 0
+";
+        assert_eq!(
+            parse_proc_net_tcp(tcp6, true),
+            vec![SocketEntry { remote: addr("[2001:db8::1]:443"), state: 0x02, inode: 33333 }]
+        );
+    }
+
+    #[test]
+    fn rules_match_cidrs_domains_and_ports() {
+        let monitor = rules(
+            json!([
+                { "id": 1, "name": "trackers", "cidrs": ["203.0.113.0/24", "198.51.100.7", "bogus"], "ports": [6881] },
+                { "id": 2, "name": "v6", "cidrs": ["2001:db8::/32"] },
+                { "id": 3, "name": "sharing", "domains": ["mega.nz"] },
+                { "id": 4, "name": "telnet", "ports": [23] }
+            ]),
+            &[("mega.nz", "192.0.2.10")],
+        );
+        let matched = |remote: &str| monitor.match_rule(&addr(remote), |_, _| true);
+
+        assert_eq!(matched("203.0.113.5:6881"), Some((0, None)));
+        assert_eq!(matched("198.51.100.7:6881"), Some((0, None)));
+        assert_eq!(matched("[::ffff:203.0.113.5]:6881"), Some((0, None)));
+        assert_eq!(matched("203.0.113.5:443"), None);
+        assert_eq!(matched("198.51.100.8:6881"), None);
+        assert_eq!(matched("[2001:db8:1::9]:443"), Some((1, None)));
+        assert_eq!(matched("[2001:db9::1]:443"), None);
+        assert_eq!(matched("192.0.2.10:443"), Some((2, Some("mega.nz".to_string()))));
+        assert_eq!(matched("[::ffff:192.0.2.10]:443"), Some((2, Some("mega.nz".to_string()))));
+        assert_eq!(matched("192.0.2.11:23"), Some((3, None)));
+        assert_eq!(matched("192.0.2.11:24"), None);
+        assert_eq!(monitor.rules[0].nets.len(), 2);
+    }
+
+    #[test]
+    fn rules_honour_schedule_scope_and_user_exceptions() {
+        let monitor = rules(
+            json!([
+                { "id": 1, "name": "office hours", "cidrs": ["203.0.113.0/24"],
+                  "schedule": { "timezone": "UTC", "windows": [{ "start": "09:00", "end": "17:00" }] } },
+                { "id": 2, "name": "kiosks only", "cidrs": ["198.51.100.0/24"], "scope": { "device_tags": ["kiosk"] } },
+                { "id": 3, "name": "not for backups", "cidrs": ["192.0.2.0/24"], "exceptions": { "os_users": ["backup"] } }
+            ]),
+            &[],
+        );
+        let table = ProcessTable::new(vec![owned_by(10, "alice"), owned_by(20, "backup")]);
+        let connections = vec![
+            (10, addr("203.0.113.5:443")),
+            (10, addr("198.51.100.5:443")),
+            (10, addr("192.0.2.5:443")),
+            (20, addr("192.0.2.5:443")),
+        ];
+        let rule_ids = |now: &str| -> Vec<(u32, usize)> {
+            monitor
+                .hits(connections.clone(), &table, &ctx(now))
+                .into_iter()
+                .map(|(pid, _, index, _)| (pid, index))
+                .collect()
+        };
+
+        assert_eq!(rule_ids("2024-01-15T10:00:00Z"), vec![(10, 0), (10, 2)]);
+        assert_eq!(rule_ids("2024-01-15T20:00:00Z"), vec![(10, 2)]);
+    }
+
+    #[test]
+    fn resync_keeps_reported_connections_of_remaining_rules() {
+        let trackers = json!({ "id": 1, "name": "trackers", "cidrs": ["203.0.113.0/24"] });
+        let sharing = json!({ "id": 2, "name": "sharing", "cidrs": ["198.51.100.0/24"] });
+        let mut monitor = rules(json!([trackers, sharing]), &[]);
+        monitor.reported.insert((10, 0, addr("203.0.113.5:443")));
+        monitor.reported.insert((10, 1, addr("198.51.100.5:443")));
+
+        monitor.set_rules(serde_json::from_value(json!([trackers, sharing])).unwrap(), &ResolvedDomains::new());
+        assert_eq!(monitor.reported.len(), 2);
+
+        // "trackers" removed: "sharing" moves to index 0 and stays reported
+        monitor.set_rules(serde_json::from_value(json!([sharing])).unwrap(), &ResolvedDomains::new());
+        assert_eq!(
+            monitor.reported.iter().collect::<Vec<_>>(),
+            vec![&(10, 0, addr("198.51.100.5:443"))]
+        );
+    }
+}
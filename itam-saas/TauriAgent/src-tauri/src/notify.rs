@@ -0,0 +1,325 @@
+// ============================================================================
+// Native Desktop Notifications Module
+// ============================================================================
+// The React window usually sits hidden in the tray, so `violation-detected`
+// events alone never reach the user. This module raises OS notifications
+// from the Rust side (freedesktop notifications over D-Bus on Linux, toast
+// on Windows, Notification Center on macOS) for:
+//
+// - Violations (respecting settings.notifications.min_severity)
+// - Auth expiry (the API rejected the monitoring token)
+// - Enforcement changes (approved exceptions, stale policy)
+//
+// Rate limiting (settings.notifications):
+// - The same notification key (e.g. "violation:steam.exe") is shown at
+//   most once per min_interval_secs
+// - At most max_per_hour notifications in total, in a sliding hour.
+//   Critical ones are exempt and do not count against it: a burst of
+//   lesser alerts must not hide a critical violation
+//
+// Click-through: every notification carries the "default" action ("Open").
+// Clicking it (or the notification body) shows the main window and emits
+// `navigate` with the view to open, e.g. { "view": "violations" }.
+//
+// Threads: one worker thread shows every notification, in order. Waiting
+// for the click blocks until the notification is clicked or closed, which
+// some notification servers never report, so at most MAX_CLICK_WAITERS
+// threads wait at a time; beyond that a notification is shown without
+// click-through.
+// ============================================================================
+
+use notify_rust::Notification;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+use tracing::warn;
+
+use crate::exceptions::LocalException;
+use crate::forbidden::{PolicyStaleness, ViolationReport};
+use crate::settings::NotificationSettings;
+
+const APP_NAME: &str = "IT Asset Agent";
+/// Threads blocked waiting for a notification click, at most
+const MAX_CLICK_WAITERS: usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct AgentNotification {
+    /// Rate limiting key, e.g. "violation:steam.exe"
+    pub key: String,
+    pub title: String,
+    pub body: String,
+    pub severity: String,
+    /// Frontend view opened on click
+    pub view: &'static str,
+}
+
+/// Rank of "Critical", the highest severity
+const CRITICAL: u8 = 4;
+
+/// Map "Low" / "Medium" / "High" / "Critical" to a rank; unknown is Medium
+pub fn severity_rank(severity: &str) -> u8 {
+    match severity.to_lowercase().as_str() {
+        "low" => 1,
+        "high" => 3,
+        "critical" => CRITICAL,
+        _ => 2,
+    }
+}
+
+/// Per-key interval plus a sliding hourly cap
+pub struct RateLimiter {
+    min_interval: Duration,
+    max_per_hour: usize,
+    last_by_key: HashMap<String, Instant>,
+    recent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(min_interval: Duration, max_per_hour: usize) -> Self {
+        Self {
+            min_interval,
+            max_per_hour,
+            last_by_key: HashMap::new(),
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Record and allow `key` at `now`, or refuse without recording
+    pub fn allow(&mut self, key: &str, now: Instant) -> bool {
+        self.check(key, now, true)
+    }
+
+    /// Like allow(), but outside the hourly cap
+    pub fn allow_uncapped(&mut self, key: &str, now: Instant) -> bool {
+        self.check(key, now, false)
+    }
+
+    fn check(&mut self, key: &str, now: Instant, capped: bool) -> bool {
+        let hour = Duration::from_secs(3600);
+        while self
+            .recent
+            .front()
+            .map(|t| now.duration_since(*t) >= hour)
+            .unwrap_or(false)
+        {
+            self.recent.pop_front();
+        }
+
+        if let Some(last) = self.last_by_key.get(key) {
+            if now.duration_since(*last) < self.min_interval {
+                return false;
+            }
+        }
+        if capped && self.recent.len() >= self.max_per_hour {
+            return false;
+        }
+
+        self.last_by_key.insert(key.to_string(), now);
+        if capped {
+            self.recent.push_back(now);
+        }
+        true
+    }
+}
+
+pub struct Notifier {
+    settings: NotificationSettings,
+    limiter: RateLimiter,
+    /// Feeds the worker thread that shows notifications
+    queue: mpsc::Sender<AgentNotification>,
+}
+
+impl Notifier {
+    pub fn new(handle: AppHandle, settings: NotificationSettings) -> Self {
+        let limiter = RateLimiter::new(
+            Duration::from_secs(settings.min_interval_secs),
+            settings.max_per_hour,
+        );
+        let (queue, notifications) = mpsc::channel();
+        thread::spawn(move || show_worker(handle, notifications));
+        Self { settings, limiter, queue }
+    }
+
+    pub fn violation(&mut self, violation: &ViolationReport) {
+        if severity_rank(&violation.severity) < severity_rank(&self.settings.min_severity) {
+            return;
+        }
+        self.notify(AgentNotification {
+            key: format!("violation:{}", violation.app_detected),
+            title: format!("{} severity: forbidden app detected", violation.severity),
+            body: format!(
+                "{} is not allowed on this device. Your IT team has been notified. Open the agent to add a justification.",
+                violation.app_detected
+            ),
+            severity: violation.severity.clone(),
+            view: "violations",
+        });
+    }
+
+    pub fn auth_expired(&mut self) {
+        self.notify(AgentNotification {
+            key: "auth-expired".to_string(),
+            title: "Sign-in required".to_string(),
+            body: "Your session has expired, so device monitoring is paused. Open the agent to sign in again.".to_string(),
+            severity: "High".to_string(),
+            view: "login",
+        });
+    }
+
+    pub fn exceptions_applied(&mut self, applied: &[LocalException]) {
+        for exception in applied {
+            let until = chrono::DateTime::from_timestamp(exception.expires_at as i64, 0)
+                .map(|t| t.with_timezone(&chrono::Local).format("%H:%M %b %d").to_string())
+                .unwrap_or_default();
+            self.notify(AgentNotification {
+                key: format!("exception:{}", exception.alert_id),
+                title: "Exception approved".to_string(),
+                body: format!("{} is allowed until {}.", exception.process_name, until),
+                severity: "Low".to_string(),
+                view: "violations",
+            });
+        }
+    }
+
+    pub fn policy_stale(&mut self, staleness: &PolicyStaleness) {
+        self.notify(AgentNotification {
+            key: "policy-stale".to_string(),
+            title: "Security policy out of date".to_string(),
+            body: format!(
+                "The agent could not refresh its policy for {} hours. Check your network connection.",
+                staleness.age_secs / 3600
+            ),
+            severity: "Medium".to_string(),
+            view: "status",
+        });
+    }
+
+    /// Show a notification unless disabled or rate limited
+    pub fn notify(&mut self, notification: AgentNotification) -> bool {
+        if !self.settings.enabled {
+            return false;
+        }
+        let now = Instant::now();
+        let allowed = if severity_rank(&notification.severity) >= CRITICAL {
+            self.limiter.allow_uncapped(&notification.key, now)
+        } else {
+            self.limiter.allow(&notification.key, now)
+        };
+        if !allowed {
+            return false;
+        }
+        self.queue.send(notification).is_ok()
+    }
+}
+
+/// Show queued notifications until the Notifier is dropped
+fn show_worker(handle: AppHandle, notifications: mpsc::Receiver<AgentNotification>) {
+    let waiters = Arc::new(AtomicUsize::new(0));
+    for notification in notifications {
+        let mut native = Notification::new();
+        native
+            .appname(APP_NAME)
+            .summary(&notification.title)
+            .body(&notification.body)
+            .action("default", "Open");
+
+        #[cfg(all(unix, not(target_os = "macos")))]
+        native.urgency(match severity_rank(&notification.severity) {
+            4 => notify_rust::Urgency::Critical,
+            1 => notify_rust::Urgency::Low,
+            _ => notify_rust::Urgency::Normal,
+        });
+
+        let shown = match native.show() {
+            Ok(shown) => shown,
+            Err(e) => {
+                warn!("Failed to show notification: {}", e);
+                continue;
+            }
+        };
+        if waiters.fetch_add(1, Ordering::SeqCst) >= MAX_CLICK_WAITERS {
+            waiters.fetch_sub(1, Ordering::SeqCst);
+            continue;
+        }
+
+        let handle = handle.clone();
+        let waiters = waiters.clone();
+        thread::spawn(move || {
+            shown.wait_for_action(|action| {
+                if action == "default" {
+                    open_view(&handle, notification.view);
+                }
+            });
+            waiters.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Bring the main window forward and tell the frontend which view to show
+fn open_view(handle: &AppHandle, view: &str) {
+    if let Some(window) = handle.get_webview_window("main") {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+    let _ = handle.emit("navigate", serde_json::json!({ "view": view }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_key_waits_for_min_interval() {
+        let mut limiter = RateLimiter::new(Duration::from_secs(60), 100);
+        let start = Instant::now();
+
+        assert!(limiter.allow("violation:steam", start));
+        assert!(!limiter.allow("violation:steam", start + Duration::from_secs(59)));
+        assert!(limiter.allow("violation:discord", start + Duration::from_secs(1)));
+        assert!(limiter.allow("violation:steam", start + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn hourly_cap_slides_and_refusals_are_not_recorded() {
+        let mut limiter = RateLimiter::new(Duration::ZERO, 2);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert!(limiter.allow("a", at(0)));
+        assert!(limiter.allow("b", at(600)));
+        assert!(!limiter.allow("c", at(1200)));
+        assert!(!limiter.allow("c", at(3599)));
+
+        // The first notification left the hour; the refused ones never counted
+        assert!(limiter.allow("c", at(3600)));
+        assert!(!limiter.allow("d", at(3600)));
+        assert!(limiter.allow("d", at(4200)));
+    }
+
+    #[test]
+    fn critical_notifications_bypass_the_hourly_cap() {
+        let mut limiter = RateLimiter::new(Duration::from_secs(60), 1);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert!(limiter.allow("violation:steam", at(0)));
+        assert!(!limiter.allow("violation:discord", at(1)));
+        assert!(limiter.allow_uncapped("violation:mimikatz", at(2)));
+        // Still once per key and interval, and never counted in the cap
+        assert!(!limiter.allow_uncapped("violation:mimikatz", at(30)));
+        assert!(limiter.allow_uncapped("violation:mimikatz", at(62)));
+        assert!(limiter.allow("violation:discord", at(3600)));
+    }
+
+    #[test]
+    fn unknown_severities_rank_as_medium() {
+        assert!(severity_rank("Critical") > severity_rank("high"));
+        assert_eq!(severity_rank("whatever"), severity_rank("Medium"));
+        assert!(severity_rank("LOW") < severity_rank("medium"));
+    }
+}
@@ -0,0 +1,332 @@
+// ============================================================================
+// Outbox Module: Store-and-Forward Uploads
+// ============================================================================
+// Telemetry that must not be lost while the API is unreachable is queued
+// here instead of being POSTed directly:
+//
+//   outbox.push("/api/agent/policy-audit", payload)  -> persisted to disk
+//   outbox.flush(backend, token).await               -> POSTs in FIFO order
+//
+// Location: <config_dir>/tauriagent/outbox.json
+//
+// Delivery rules:
+// - 2xx: item removed
+// - The API itself is unavailable (network, timeout, 429, 502/503/504) or
+//   the token is bad (401): flush stops, items kept for next time (later
+//   items would fail too); FlushResult.error says which
+// - Any other error status (4xx, 500...): the server failed on this record.
+//   It is set aside and the flush carries on with the next one, so one bad
+//   record cannot block the queue; it is dropped after MAX_ATTEMPTS
+// - Remaining items keep their FIFO order
+// - Queue is capped at MAX_ITEMS; the oldest items are dropped first
+// ============================================================================
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::future::Future;
+use std::path::PathBuf;
+use tracing::{error, warn};
+
+use crate::api::{self, unix_now, Backend};
+use crate::error::AgentError;
+use crate::settings::agent_config_dir;
+
+const MAX_ITEMS: usize = 1000;
+const MAX_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxItem {
+    pub id: u64,
+    /// Path relative to the API base URL, e.g. "/api/agent/policy-audit"
+    pub endpoint: String,
+    pub payload: serde_json::Value,
+    pub queued_at: u64,
+    pub attempts: u32,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Outbox {
+    items: Vec<OutboxItem>,
+    next_id: u64,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct FlushResult {
+    pub sent: usize,
+    pub dropped: usize,
+    /// Items that failed on their own and were kept for a later flush
+    pub skipped: usize,
+    pub remaining: usize,
+    /// Why the flush stopped early, if it did
+    pub error: Option<AgentError>,
+}
+
+/// Queue summary for diagnostics (payloads left out)
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct OutboxStats {
+    pub depth: usize,
+    pub capacity: usize,
+    pub by_endpoint: BTreeMap<String, usize>,
+    pub oldest_queued_at: Option<u64>,
+    /// Most delivery attempts of any queued item
+    pub max_attempts: u32,
+}
+
+/// The error means the API is unreachable or refuses every request right
+/// now, rather than failing on one record
+fn pauses_flush(e: &AgentError) -> bool {
+    match e {
+        AgentError::Network(_)
+        | AgentError::Timeout(_)
+        | AgentError::RateLimited { .. }
+        | AgentError::Unauthorized(_) => true,
+        AgentError::Server { status, .. } => matches!(status, 502..=504),
+        _ => false,
+    }
+}
+
+fn get_outbox_path() -> PathBuf {
+    agent_config_dir().join("outbox.json")
+}
+
+impl Outbox {
+    /// Load the persisted outbox, or start empty if there is none
+    pub fn load() -> Self {
+        Self::load_from(get_outbox_path())
+    }
+
+    fn load_from(path: PathBuf) -> Self {
+        let mut outbox = fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| match serde_json::from_str::<Outbox>(&json) {
+                Ok(outbox) => Some(outbox),
+                Err(e) => {
+                    warn!("Discarding unreadable outbox: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        outbox.path = path;
+        outbox
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn stats(&self) -> OutboxStats {
+        let mut by_endpoint = BTreeMap::new();
+        for item in &self.items {
+            *by_endpoint.entry(item.endpoint.clone()).or_insert(0) += 1;
+        }
+        OutboxStats {
+            depth: self.items.len(),
+            capacity: MAX_ITEMS,
+            by_endpoint,
+            oldest_queued_at: self.items.iter().map(|i| i.queued_at).min(),
+            max_attempts: self.items.iter().map(|i| i.attempts).max().unwrap_or(0),
+        }
+    }
+
+    /// Queue a payload for upload and persist the queue
+    pub fn push(&mut self, endpoint: &str, payload: serde_json::Value) {
+        self.next_id += 1;
+        self.items.push(OutboxItem {
+            id: self.next_id,
+            endpoint: endpoint.to_string(),
+            payload,
+            queued_at: unix_now(),
+            attempts: 0,
+        });
+
+        if self.items.len() > MAX_ITEMS {
+            let overflow = self.items.len() - MAX_ITEMS;
+            self.items.drain(..overflow);
+            warn!("Outbox full, dropped {} oldest items", overflow);
+        }
+
+        self.persist();
+    }
+
+    pub fn persist(&self) {
+        let result = serde_json::to_string(self)
+            .map_err(|e| format!("Serialize error: {}", e))
+            .and_then(|json| fs::write(&self.path, json).map_err(|e| format!("File write error: {}", e)));
+        if let Err(e) = result {
+            warn!("Failed to persist outbox: {}", e);
+        }
+    }
+
+    /// POST queued items in order, setting aside records the server fails
+    /// on, until the API becomes unavailable
+    pub async fn flush(&mut self, backend: &Backend, token: &str) -> FlushResult {
+        if self.items.is_empty() {
+            return FlushResult::default();
+        }
+
+        let client = backend.client();
+        self.deliver(|item| {
+            let request = client
+                .post(backend.url(&item.endpoint))
+                .header("Authorization", format!("Bearer {}", token))
+                .json(&item.payload);
+            async move {
+                let response = api::send(request).await?;
+                api::ensure_success(response).await.map(|_| ())
+            }
+        })
+        .await
+    }
+
+    /// Flush with `send` delivering one item
+    async fn deliver<F, Fut>(&mut self, mut send: F) -> FlushResult
+    where
+        F: FnMut(&OutboxItem) -> Fut,
+        Fut: Future<Output = Result<(), AgentError>>,
+    {
+        let mut result = FlushResult::default();
+
+        let mut index = 0;
+        while let Some(item) = self.items.get_mut(index) {
+            item.attempts += 1;
+
+            match send(item).await {
+                Ok(()) => {
+                    self.items.remove(index);
+                    result.sent += 1;
+                }
+                Err(e) if pauses_flush(&e) => {
+                    error!("Outbox flush paused: {} failed: {}", item.endpoint, e);
+                    result.error = Some(e);
+                    break;
+                }
+                Err(e) if item.attempts >= MAX_ATTEMPTS => {
+                    error!("Dropping outbox item {} ({}): {}", item.id, item.endpoint, e);
+                    self.items.remove(index);
+                    result.dropped += 1;
+                }
+                Err(e) => {
+                    warn!("Outbox item {} ({}) failed, keeping it for later: {}", item.id, item.endpoint, e);
+                    index += 1;
+                    result.skipped += 1;
+                }
+            }
+        }
+
+        result.remaining = self.items.len();
+        self.persist();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn outbox(dir: &tempfile::TempDir, endpoints: &[&str]) -> Outbox {
+        let mut outbox = Outbox::load_from(dir.path().join("outbox.json"));
+        for endpoint in endpoints {
+            outbox.push(endpoint, serde_json::json!({ "endpoint": endpoint }));
+        }
+        outbox
+    }
+
+    fn server_error(status: u16) -> AgentError {
+        AgentError::Server {
+            status,
+            message: String::new(),
+        }
+    }
+
+    fn endpoints(outbox: &Outbox) -> Vec<&str> {
+        outbox.items.iter().map(|i| i.endpoint.as_str()).collect()
+    }
+
+    /// Deliver with a canned outcome per endpoint (success if not listed),
+    /// returning the endpoints in the order they were sent
+    async fn deliver(outbox: &mut Outbox, outcomes: &HashMap<&str, AgentError>) -> (FlushResult, Vec<String>) {
+        let mut sent = Vec::new();
+        let result = outbox
+            .deliver(|item| {
+                sent.push(item.endpoint.clone());
+                let outcome = outcomes.get(item.endpoint.as_str()).cloned().map_or(Ok(()), Err);
+                async move { outcome }
+            })
+            .await;
+        (result, sent)
+    }
+
+    #[tokio::test]
+    async fn failing_records_are_set_aside_and_the_rest_drain_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut outbox = outbox(&dir, &["/a", "/bad-request", "/b", "/server-error", "/c"]);
+        let outcomes = HashMap::from([("/bad-request", server_error(400)), ("/server-error", server_error(500))]);
+
+        let (result, sent) = deliver(&mut outbox, &outcomes).await;
+
+        assert_eq!(sent, ["/a", "/bad-request", "/b", "/server-error", "/c"]);
+        assert_eq!((result.sent, result.skipped, result.dropped, result.remaining), (3, 2, 0, 2));
+        assert!(result.error.is_none());
+        assert_eq!(endpoints(&outbox), ["/bad-request", "/server-error"]);
+    }
+
+    #[tokio::test]
+    async fn records_are_dropped_after_max_attempts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut outbox = outbox(&dir, &["/poison", "/a"]);
+        let outcomes = HashMap::from([("/poison", server_error(500))]);
+
+        for _ in 1..MAX_ATTEMPTS {
+            let (result, _) = deliver(&mut outbox, &outcomes).await;
+            assert_eq!(result.dropped, 0);
+        }
+        assert_eq!(endpoints(&outbox), ["/poison"]);
+
+        let (result, _) = deliver(&mut outbox, &outcomes).await;
+        assert_eq!(result.dropped, 1);
+        assert!(outbox.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unavailable_api_pauses_the_flush_and_keeps_everything() {
+        for error in [
+            AgentError::Network("refused".to_string()),
+            AgentError::Unauthorized(String::new()),
+            AgentError::RateLimited { retry_after_secs: Some(30) },
+            server_error(503),
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let mut outbox = outbox(&dir, &["/a", "/down", "/b"]);
+            let outcomes = HashMap::from([("/down", error.clone())]);
+
+            let (result, sent) = deliver(&mut outbox, &outcomes).await;
+
+            assert_eq!(sent, ["/a", "/down"]);
+            assert_eq!(result.error, Some(error));
+            assert_eq!(endpoints(&outbox), ["/down", "/b"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn queue_and_attempts_survive_a_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut outbox = outbox(&dir, &["/a", "/b"]);
+        deliver(&mut outbox, &HashMap::from([("/a", server_error(500))])).await;
+
+        let mut reloaded = Outbox::load_from(dir.path().join("outbox.json"));
+        assert_eq!(endpoints(&reloaded), ["/a"]);
+        assert_eq!(reloaded.items[0].attempts, 1);
+        assert_eq!(reloaded.items[0].payload["endpoint"], "/a");
+
+        reloaded.push("/c", serde_json::Value::Null);
+        assert_eq!(reloaded.items[1].id, 3);
+    }
+}
@@ -0,0 +1,461 @@
+// ============================================================================
+// Performance Module: Host Time-Series
+// ============================================================================
+// Device health for the dashboard's charts. The "perf-sample" task takes a
+// PerfSample every settings.perf.sample_interval_secs:
+//
+// - CPU usage, memory and swap, load average (0 on Windows)
+// - disk I/O (bytes read/written by all processes) and network throughput,
+//   per second since the previous sample
+// - top processes by CPU
+//
+// Samples are folded into minute and hour rollups (min / avg / max of each
+// series, peak usage of the top processes) as they arrive; raw samples are
+// not kept. The "perf-upload" task sends the finished rollups through the
+// outbox (outbox.rs), at most MAX_BATCH per record:
+//
+//   POST /api/agent/perf
+//   { "device_id": "...", "rollups": [
+//       { "resolution": "minute", "start": 1718000040, "samples": 4,
+//         "cpu_percent": { "min": 3.1, "avg": 12.5, "max": 40.2 }, ...,
+//         "top_processes": [ { "name": "chrome", "cpu_percent": 35.0,
+//                              "memory_bytes": 812000000 } ] } ] }
+//
+// A rollup is finished when a sample of the next minute / hour arrives.
+// While logged out, finished rollups wait in memory, up to MAX_PENDING
+// (oldest dropped first).
+// ============================================================================
+
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Instant;
+use sysinfo::{Networks, System};
+use tracing::{debug, warn};
+
+use crate::api::unix_now;
+use crate::error::AgentError;
+use crate::forbidden::get_device_id;
+use crate::settings::PerfSettings;
+use crate::state::AgentState;
+use crate::supervisor::lock;
+
+pub const PERF_ENDPOINT: &str = "/api/agent/perf";
+/// A day of minute rollups, plus the hours
+const MAX_PENDING: usize = 24 * 60 + 24;
+/// Rollups per outbox record
+const MAX_BATCH: usize = 240;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProcessUsage {
+    pub name: String,
+    pub cpu_percent: f32,
+    pub memory_bytes: u64,
+}
+
+/// One reading of the host
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PerfSample {
+    /// Seconds since UNIX epoch
+    pub timestamp: u64,
+    pub cpu_percent: f64,
+    pub memory_used_bytes: u64,
+    pub memory_total_bytes: u64,
+    pub swap_used_bytes: u64,
+    pub swap_total_bytes: u64,
+    pub load_1: f64,
+    pub load_5: f64,
+    pub load_15: f64,
+    pub disk_read_bytes_per_sec: f64,
+    pub disk_write_bytes_per_sec: f64,
+    pub net_rx_bytes_per_sec: f64,
+    pub net_tx_bytes_per_sec: f64,
+    pub top_processes: Vec<ProcessUsage>,
+}
+
+/// Reads the host through sysinfo; rates are since the previous call
+pub struct PerfSampler {
+    sys: System,
+    networks: Networks,
+    last: Option<Instant>,
+}
+
+impl Default for PerfSampler {
+    fn default() -> Self {
+        Self {
+            sys: System::new(),
+            networks: Networks::new_with_refreshed_list(),
+            last: None,
+        }
+    }
+}
+
+impl PerfSampler {
+    /// None on the first call: CPU usage and rates need a previous reading
+    pub fn sample(&mut self, top_n: usize) -> Option<PerfSample> {
+        self.sys.refresh_cpu();
+        self.sys.refresh_memory();
+        self.sys.refresh_processes();
+        self.networks.refresh();
+        let now = Instant::now();
+        let elapsed = self.last.replace(now).map(|last| now.duration_since(last).as_secs_f64())?;
+        let per_sec = |bytes: u64| if elapsed > 0.0 { bytes as f64 / elapsed } else { 0.0 };
+
+        let (mut disk_read, mut disk_write) = (0, 0);
+        let mut processes: Vec<ProcessUsage> = Vec::new();
+        for process in self.sys.processes().values() {
+            let usage = process.disk_usage();
+            disk_read += usage.read_bytes;
+            disk_write += usage.written_bytes;
+            processes.push(ProcessUsage {
+                name: process.name().to_string(),
+                cpu_percent: process.cpu_usage(),
+                memory_bytes: process.memory(),
+            });
+        }
+        processes.sort_by(|a, b| b.cpu_percent.total_cmp(&a.cpu_percent));
+        processes.truncate(top_n);
+
+        let (mut rx, mut tx) = (0, 0);
+        for (_, data) in &self.networks {
+            rx += data.received();
+            tx += data.transmitted();
+        }
+        let load = System::load_average();
+        Some(PerfSample {
+            timestamp: unix_now(),
+            cpu_percent: self.sys.global_cpu_info().cpu_usage() as f64,
+            memory_used_bytes: self.sys.used_memory(),
+            memory_total_bytes: self.sys.total_memory(),
+            swap_used_bytes: self.sys.used_swap(),
+            swap_total_bytes: self.sys.total_swap(),
+            load_1: load.one,
+            load_5: load.five,
+            load_15: load.fifteen,
+            disk_read_bytes_per_sec: per_sec(disk_read),
+            disk_write_bytes_per_sec: per_sec(disk_write),
+            net_rx_bytes_per_sec: per_sec(rx),
+            net_tx_bytes_per_sec: per_sec(tx),
+            top_processes: processes,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    Minute,
+    Hour,
+}
+
+impl Resolution {
+    fn secs(self) -> u64 {
+        match self {
+            Resolution::Minute => 60,
+            Resolution::Hour => 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Stat {
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Acc {
+    min: f64,
+    max: f64,
+    sum: f64,
+    count: u32,
+}
+
+impl Default for Acc {
+    fn default() -> Self {
+        Self {
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            sum: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl Acc {
+    fn add(&mut self, value: f64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn stat(&self) -> Stat {
+        Stat {
+            min: self.min,
+            avg: self.sum / self.count.max(1) as f64,
+            max: self.max,
+        }
+    }
+}
+
+/// Summary of the samples in one minute or hour
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Rollup {
+    pub resolution: Resolution,
+    /// Start of the bucket, seconds since UNIX epoch
+    pub start: u64,
+    pub samples: u32,
+    pub cpu_percent: Stat,
+    pub memory_used_bytes: Stat,
+    pub memory_total_bytes: u64,
+    pub swap_used_bytes: Stat,
+    pub swap_total_bytes: u64,
+    pub load_1: Stat,
+    pub disk_read_bytes_per_sec: Stat,
+    pub disk_write_bytes_per_sec: Stat,
+    pub net_rx_bytes_per_sec: Stat,
+    pub net_tx_bytes_per_sec: Stat,
+    /// Peak CPU and memory per process name, busiest first
+    pub top_processes: Vec<ProcessUsage>,
+}
+
+#[derive(Debug)]
+struct RollupBuilder {
+    resolution: Resolution,
+    start: u64,
+    // cpu, memory, swap, load, disk read, disk write, rx, tx
+    series: [Acc; 8],
+    memory_total_bytes: u64,
+    swap_total_bytes: u64,
+    processes: BTreeMap<String, ProcessUsage>,
+}
+
+impl RollupBuilder {
+    fn new(resolution: Resolution, start: u64) -> Self {
+        Self {
+            resolution,
+            start,
+            series: [Acc::default(); 8],
+            memory_total_bytes: 0,
+            swap_total_bytes: 0,
+            processes: BTreeMap::new(),
+        }
+    }
+
+    fn add(&mut self, sample: &PerfSample) {
+        let values = [
+            sample.cpu_percent,
+            sample.memory_used_bytes as f64,
+            sample.swap_used_bytes as f64,
+            sample.load_1,
+            sample.disk_read_bytes_per_sec,
+            sample.disk_write_bytes_per_sec,
+            sample.net_rx_bytes_per_sec,
+            sample.net_tx_bytes_per_sec,
+        ];
+        for (acc, value) in self.series.iter_mut().zip(values) {
+            acc.add(value);
+        }
+        self.memory_total_bytes = sample.memory_total_bytes;
+        self.swap_total_bytes = sample.swap_total_bytes;
+        for process in &sample.top_processes {
+            let peak = self.processes.entry(process.name.clone()).or_insert_with(|| ProcessUsage {
+                name: process.name.clone(),
+                cpu_percent: 0.0,
+                memory_bytes: 0,
+            });
+            peak.cpu_percent = peak.cpu_percent.max(process.cpu_percent);
+            peak.memory_bytes = peak.memory_bytes.max(process.memory_bytes);
+        }
+    }
+
+    fn finish(self, top_n: usize) -> Rollup {
+        let [cpu, memory, swap, load, disk_read, disk_write, rx, tx] = self.series;
+        let mut top_processes: Vec<ProcessUsage> = self.processes.into_values().collect();
+        top_processes.sort_by(|a, b| b.cpu_percent.total_cmp(&a.cpu_percent));
+        top_processes.truncate(top_n);
+        Rollup {
+            resolution: self.resolution,
+            start: self.start,
+            samples: cpu.count,
+            cpu_percent: cpu.stat(),
+            memory_used_bytes: memory.stat(),
+            memory_total_bytes: self.memory_total_bytes,
+            swap_used_bytes: swap.stat(),
+            swap_total_bytes: self.swap_total_bytes,
+            load_1: load.stat(),
+            disk_read_bytes_per_sec: disk_read.stat(),
+            disk_write_bytes_per_sec: disk_write.stat(),
+            net_rx_bytes_per_sec: rx.stat(),
+            net_tx_bytes_per_sec: tx.stat(),
+            top_processes,
+        }
+    }
+}
+
+/// Minute and hour rollups of the samples so far
+#[derive(Debug)]
+pub struct PerfSeries {
+    top_n: usize,
+    open: Vec<RollupBuilder>,
+    finished: VecDeque<Rollup>,
+}
+
+impl PerfSeries {
+    pub fn new(top_n: usize) -> Self {
+        Self {
+            top_n,
+            open: Vec::new(),
+            finished: VecDeque::new(),
+        }
+    }
+
+    pub fn ingest(&mut self, sample: &PerfSample) {
+        for resolution in [Resolution::Minute, Resolution::Hour] {
+            let start = sample.timestamp - sample.timestamp % resolution.secs();
+            let index = match self.open.iter().position(|b| b.resolution == resolution) {
+                Some(i) if self.open[i].start == start => i,
+                Some(i) => {
+                    let done = std::mem::replace(&mut self.open[i], RollupBuilder::new(resolution, start));
+                    self.push_finished(done.finish(self.top_n));
+                    i
+                }
+                None => {
+                    self.open.push(RollupBuilder::new(resolution, start));
+                    self.open.len() - 1
+                }
+            };
+            self.open[index].add(sample);
+        }
+    }
+
+    fn push_finished(&mut self, rollup: Rollup) {
+        if self.finished.len() >= MAX_PENDING {
+            self.finished.pop_front();
+        }
+        self.finished.push_back(rollup);
+    }
+
+    /// Finished rollups not yet handed out, oldest first
+    pub fn take_finished(&mut self) -> Vec<Rollup> {
+        self.finished.drain(..).collect()
+    }
+
+    pub fn pending(&self) -> usize {
+        self.finished.len()
+    }
+}
+
+/// Sampler, rollups and the latest sample, shared by the perf tasks
+pub struct PerfCollector {
+    sampler: PerfSampler,
+    series: PerfSeries,
+    latest: Option<PerfSample>,
+    top_n: usize,
+}
+
+impl PerfCollector {
+    pub fn new(settings: &PerfSettings) -> Self {
+        Self {
+            sampler: PerfSampler::default(),
+            series: PerfSeries::new(settings.top_processes),
+            latest: None,
+            top_n: settings.top_processes,
+        }
+    }
+
+    /// Take a sample and fold it into the rollups
+    pub fn sample(&mut self) -> Option<&PerfSample> {
+        let sample = self.sampler.sample(self.top_n)?;
+        self.ingest(sample);
+        self.latest.as_ref()
+    }
+
+    pub fn ingest(&mut self, sample: PerfSample) {
+        self.series.ingest(&sample);
+        self.latest = Some(sample);
+    }
+
+    pub fn latest(&self) -> Option<&PerfSample> {
+        self.latest.as_ref()
+    }
+
+    pub fn take_finished(&mut self) -> Vec<Rollup> {
+        self.series.take_finished()
+    }
+}
+
+/// Queue the finished rollups and flush the outbox; returns how many went
+/// into the outbox
+pub async fn upload(state: &AgentState, token: &str) -> Result<usize, AgentError> {
+    let rollups = lock(&state.perf).take_finished();
+    if rollups.is_empty() {
+        return Ok(0);
+    }
+    debug!(rollups = rollups.len(), "Queueing performance rollups");
+    let device_id = get_device_id();
+    let mut monitor = state.monitor.lock().await;
+    for batch in rollups.chunks(MAX_BATCH) {
+        monitor.enqueue(PERF_ENDPOINT, json!({ "device_id": device_id, "rollups": batch }));
+    }
+    if let Err(e) = monitor.flush_outbox(token).await {
+        warn!("Performance rollups queued, upload failed: {}", e);
+        return Err(e);
+    }
+    Ok(rollups.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp: u64, cpu: f64, process: (&str, f32)) -> PerfSample {
+        PerfSample {
+            timestamp,
+            cpu_percent: cpu,
+            memory_used_bytes: 4_000,
+            memory_total_bytes: 16_000,
+            swap_used_bytes: 0,
+            swap_total_bytes: 2_000,
+            load_1: cpu / 10.0,
+            load_5: 0.0,
+            load_15: 0.0,
+            disk_read_bytes_per_sec: 100.0,
+            disk_write_bytes_per_sec: 50.0,
+            net_rx_bytes_per_sec: 0.0,
+            net_tx_bytes_per_sec: 0.0,
+            top_processes: vec![ProcessUsage {
+                name: process.0.to_string(),
+                cpu_percent: process.1,
+                memory_bytes: 1_000,
+            }],
+        }
+    }
+
+    #[test]
+    fn samples_roll_up_by_minute_and_hour() {
+        let mut series = PerfSeries::new(1);
+        series.ingest(&sample(3_600, 10.0, ("chrome", 8.0)));
+        series.ingest(&sample(3_630, 30.0, ("code", 20.0)));
+        assert_eq!(series.pending(), 0);
+
+        series.ingest(&sample(3_660, 50.0, ("chrome", 40.0)));
+        let minutes = series.take_finished();
+        assert_eq!(minutes.len(), 1);
+        let minute = &minutes[0];
+        assert_eq!((minute.resolution, minute.start, minute.samples), (Resolution::Minute, 3_600, 2));
+        assert_eq!(minute.cpu_percent, Stat { min: 10.0, avg: 20.0, max: 30.0 });
+        assert_eq!(minute.memory_total_bytes, 16_000);
+        assert_eq!(minute.top_processes[0].name, "code");
+
+        series.ingest(&sample(7_200, 0.0, ("idle", 0.0)));
+        let finished = series.take_finished();
+        let hour = finished.iter().find(|r| r.resolution == Resolution::Hour).unwrap();
+        assert_eq!((hour.start, hour.samples), (3_600, 3));
+        assert_eq!(hour.cpu_percent.max, 50.0);
+        assert_eq!(hour.top_processes[0].name, "chrome");
+        assert_eq!(finished.iter().filter(|r| r.resolution == Resolution::Minute).count(), 1);
+    }
+}
@@ -0,0 +1,347 @@
+// ============================================================================
+// Forbidden App Policy Evaluation Module
+// ============================================================================
+// A forbidden app rule may carry three optional qualifiers:
+//
+// 1. schedule   - only enforced inside time windows (e.g. working hours)
+// 2. scope      - only enforced on matching devices / OS users
+// 3. exceptions - never enforced for listed devices / OS users
+//
+// A rule without any of them is enforced everywhere, always (the original
+// behaviour). Evaluation happens entirely on the device with the embedded
+// IANA time zone database, so schedules keep working while offline.
+//
+// Each rule also has a mode: "enforce" (default) or "audit". Audit matches are uploaded as
+// would-have-violated telemetry instead of alerts, so admins can measure
+// false positives before enforcing a new rule. Audit-only builds
+// (ITAM_AGENT_AUDIT_ONLY, settings.rs) put every rule in audit mode.
+//
+// Example rule as served by GET /api/forbidden-apps:
+// {
+//   "id": 12, "process_name": "steam", "severity": "Medium",
+//   "schedule": {
+//     "timezone": "Europe/Berlin",
+//     "windows": [{ "days": ["mon","tue","wed","thu","fri"], "start": "09:00", "end": "17:00" }]
+//   },
+//   "scope": { "device_tags": ["kiosk", "finance"] },
+//   "exceptions": { "os_users": ["qa-bot"] }
+// }
+//
+// Matching rules:
+// - Lists are OR within a field and AND across fields
+// - An empty list does not restrict anything
+// - String comparison is case-insensitive
+// - A window whose end is before its start spans midnight; its days refer
+//   to the day the window opens
+// - An unknown time zone falls back to device local time; it is logged once
+//   per rule when the rules are loaded or synced, not on every scan
+// ============================================================================
+
+use chrono::{DateTime, Datelike, Local, NaiveTime, TimeZone, Timelike, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleMode {
+    #[default]
+    Enforce,
+    Audit,
+}
+
+impl RuleMode {
+    pub fn is_enforce(&self) -> bool {
+        *self == RuleMode::Enforce
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuleSchedule {
+    /// IANA name such as "America/New_York"; None means device local time
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// Rule is active inside any of these windows; empty means always
+    #[serde(default)]
+    pub windows: Vec<TimeWindow>,
+}
+
+impl RuleSchedule {
+    /// The configured time zone, if it is not a known IANA name
+    pub fn unknown_timezone(&self) -> Option<&str> {
+        self.timezone.as_deref().filter(|name| name.parse::<Tz>().is_err())
+    }
+}
+
+/// Log a rule whose schedule names an unknown time zone. Called when rules
+/// are loaded or synced, so the warning is not repeated on every scan.
+pub fn warn_unknown_timezone(rule: &str, schedule: Option<&RuleSchedule>) {
+    if let Some(name) = schedule.and_then(RuleSchedule::unknown_timezone) {
+        warn!("Rule {}: unknown timezone {:?}, using local time", rule, name);
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TimeWindow {
+    /// "mon".."sun" (or full names); empty means every day
+    #[serde(default)]
+    pub days: Vec<String>,
+    /// "HH:MM", defaults to start of day
+    #[serde(default)]
+    pub start: Option<String>,
+    /// "HH:MM" (exclusive), defaults to end of day
+    #[serde(default)]
+    pub end: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuleScope {
+    #[serde(default)]
+    pub device_ids: Vec<String>,
+    #[serde(default)]
+    pub device_tags: Vec<String>,
+    #[serde(default)]
+    pub org_units: Vec<String>,
+    #[serde(default)]
+    pub os_users: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuleExceptions {
+    #[serde(default)]
+    pub device_ids: Vec<String>,
+    #[serde(default)]
+    pub os_users: Vec<String>,
+}
+
+/// Device facts a rule is evaluated against
+#[derive(Debug, Clone)]
+pub struct PolicyContext {
+    pub device_id: String,
+    pub device_tags: Vec<String>,
+    pub org_unit: Option<String>,
+    pub now: DateTime<Utc>,
+}
+
+fn contains_ignore_case(list: &[String], value: &str) -> bool {
+    list.iter().any(|item| item.eq_ignore_ascii_case(value))
+}
+
+fn parse_minutes(value: &str) -> Option<u32> {
+    let time = NaiveTime::parse_from_str(value.trim(), "%H:%M").ok()?;
+    Some(time.hour() * 60 + time.minute())
+}
+
+/// Unknown day names never match, so a typo disables the window instead of
+/// widening it to every day
+fn day_matches(days: &[String], day: Weekday) -> bool {
+    days.is_empty()
+        || days
+            .iter()
+            .any(|d| d.trim().parse::<Weekday>().map(|w| w == day).unwrap_or(false))
+}
+
+fn window_active(window: &TimeWindow, day: Weekday, minute: u32) -> bool {
+    let start = match window.start.as_deref() {
+        Some(s) => match parse_minutes(s) {
+            Some(m) => m,
+            None => return false,
+        },
+        None => 0,
+    };
+    let end = match window.end.as_deref() {
+        Some(s) => match parse_minutes(s) {
+            Some(m) => m,
+            None => return false,
+        },
+        None => 24 * 60,
+    };
+
+    if start <= end {
+        day_matches(&window.days, day) && minute >= start && minute < end
+    } else {
+        // Overnight window, e.g. 22:00 - 06:00
+        (minute >= start && day_matches(&window.days, day))
+            || (minute < end && day_matches(&window.days, day.pred()))
+    }
+}
+
+/// Local weekday and minute-of-day in the schedule's time zone
+fn local_time(timezone: Option<&str>, now: DateTime<Utc>) -> (Weekday, u32) {
+    fn parts<T: TimeZone>(t: DateTime<T>) -> (Weekday, u32) {
+        (t.weekday(), t.hour() * 60 + t.minute())
+    }
+
+    // Unknown names were logged by warn_unknown_timezone when synced
+    match timezone.and_then(|name| name.parse::<Tz>().ok()) {
+        Some(tz) => parts(now.with_timezone(&tz)),
+        None => parts(now.with_timezone(&Local)),
+    }
+}
+
+/// Whether a schedule is active at `now`
+pub fn schedule_active(schedule: &RuleSchedule, now: DateTime<Utc>) -> bool {
+    if schedule.windows.is_empty() {
+        return true;
+    }
+    let (day, minute) = local_time(schedule.timezone.as_deref(), now);
+    schedule
+        .windows
+        .iter()
+        .any(|window| window_active(window, day, minute))
+}
+
+/// Device-level check: schedule, device scope and device exceptions.
+/// Evaluated once per rule per scan.
+pub fn rule_active_for_device(
+    schedule: Option<&RuleSchedule>,
+    scope: Option<&RuleScope>,
+    exceptions: Option<&RuleExceptions>,
+    ctx: &PolicyContext,
+) -> bool {
+    if let Some(schedule) = schedule {
+        if !schedule_active(schedule, ctx.now) {
+            return false;
+        }
+    }
+
+    if let Some(scope) = scope {
+        if !scope.device_ids.is_empty() && !contains_ignore_case(&scope.device_ids, &ctx.device_id) {
+            return false;
+        }
+        if !scope.device_tags.is_empty()
+            && !ctx.device_tags.iter().any(|tag| contains_ignore_case(&scope.device_tags, tag))
+        {
+            return false;
+        }
+        if !scope.org_units.is_empty()
+            && !ctx
+                .org_unit
+                .as_deref()
+                .map(|ou| contains_ignore_case(&scope.org_units, ou))
+                .unwrap_or(false)
+        {
+            return false;
+        }
+    }
+
+    if let Some(exceptions) = exceptions {
+        if contains_ignore_case(&exceptions.device_ids, &ctx.device_id) {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Process-level check against the owning OS user.
+/// An unknown owner never matches a user scope nor a user exception.
+pub fn rule_applies_to_user(
+    scope: Option<&RuleScope>,
+    exceptions: Option<&RuleExceptions>,
+    os_user: Option<&str>,
+) -> bool {
+    if let Some(scope) = scope {
+        if !scope.os_users.is_empty()
+            && !os_user
+                .map(|user| contains_ignore_case(&scope.os_users, user))
+                .unwrap_or(false)
+        {
+            return false;
+        }
+    }
+
+    if let (Some(exceptions), Some(user)) = (exceptions, os_user) {
+        if contains_ignore_case(&exceptions.os_users, user) {
+            return false;
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(days: &[&str], start: Option<&str>, end: Option<&str>) -> TimeWindow {
+        TimeWindow {
+            days: days.iter().map(|d| d.to_string()).collect(),
+            start: start.map(String::from),
+            end: end.map(String::from),
+        }
+    }
+
+    fn schedule(timezone: &str, window: TimeWindow) -> RuleSchedule {
+        RuleSchedule {
+            timezone: Some(timezone.to_string()),
+            windows: vec![window],
+        }
+    }
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Utc)
+    }
+
+    const WEEKDAYS: &[&str] = &["mon", "tue", "wed", "thu", "fri"];
+
+    #[test]
+    fn schedule_windows_follow_the_rule_timezone() {
+        let office = || window(WEEKDAYS, Some("09:00"), Some("17:00"));
+        // 2024-01-15 and 2024-07-15 are Mondays
+        let cases = [
+            ("Berlin winter, opening minute", schedule("Europe/Berlin", office()), "2024-01-15T08:00:00Z", true),
+            ("Berlin winter, before opening", schedule("Europe/Berlin", office()), "2024-01-15T07:59:00Z", false),
+            ("Berlin winter, end is exclusive", schedule("Europe/Berlin", office()), "2024-01-15T16:00:00Z", false),
+            ("Berlin summer time", schedule("Europe/Berlin", office()), "2024-07-15T07:00:00Z", true),
+            ("Berlin summer, before opening", schedule("Europe/Berlin", office()), "2024-07-15T06:59:00Z", false),
+            ("Friday afternoon", schedule("Europe/Berlin", office()), "2024-01-19T15:59:00Z", true),
+            ("Saturday", schedule("Europe/Berlin", office()), "2024-01-20T10:00:00Z", false),
+            ("UTC Tuesday is Monday evening in New York", schedule("America/New_York", office()), "2024-01-16T01:00:00Z", false),
+            ("UTC Sunday is Monday morning in Tokyo", schedule("Asia/Tokyo", window(&["mon"], None, Some("12:00"))), "2024-01-14T16:00:00Z", true),
+            ("full names and case", schedule("UTC", window(&["Monday"], None, None)), "2024-01-15T23:59:00Z", true),
+            ("whole day ends at midnight", schedule("UTC", window(&["mon"], None, None)), "2024-01-16T00:00:00Z", false),
+            ("unknown day never matches", schedule("UTC", window(&["mon", "funday"], None, None)), "2024-01-17T12:00:00Z", false),
+            ("malformed time disables the window", schedule("UTC", window(&[], Some("9am"), None)), "2024-01-15T12:00:00Z", false),
+        ];
+
+        for (label, schedule, now, expected) in cases {
+            assert_eq!(schedule_active(&schedule, at(now)), expected, "{}", label);
+        }
+    }
+
+    #[test]
+    fn overnight_windows_belong_to_the_day_they_open() {
+        let friday_night = schedule("UTC", window(&["fri"], Some("22:00"), Some("06:00")));
+        let sunday_night = schedule("UTC", window(&["sun"], Some("22:00"), Some("06:00")));
+        // 2024-01-19 is a Friday
+        let cases = [
+            (&friday_night, "2024-01-19T21:59:00Z", false),
+            (&friday_night, "2024-01-19T22:00:00Z", true),
+            (&friday_night, "2024-01-19T23:59:00Z", true),
+            (&friday_night, "2024-01-20T00:00:00Z", true),
+            (&friday_night, "2024-01-20T05:59:00Z", true),
+            (&friday_night, "2024-01-20T06:00:00Z", false),
+            (&friday_night, "2024-01-19T03:00:00Z", false),
+            (&friday_night, "2024-01-20T23:00:00Z", false),
+            (&sunday_night, "2024-01-21T22:30:00Z", true),
+            (&sunday_night, "2024-01-22T02:00:00Z", true),
+            (&sunday_night, "2024-01-15T02:00:00Z", true),
+            (&sunday_night, "2024-01-22T22:30:00Z", false),
+        ];
+
+        for (schedule, now, expected) in cases {
+            assert_eq!(schedule_active(schedule, at(now)), expected, "{}", now);
+        }
+    }
+
+    #[test]
+    fn empty_schedules_are_always_active_and_unknown_zones_are_reported() {
+        assert!(schedule_active(&RuleSchedule::default(), at("2024-01-20T03:00:00Z")));
+
+        let typo = schedule("Europe/Berln", window(&[], None, None));
+        assert_eq!(typo.unknown_timezone(), Some("Europe/Berln"));
+        assert_eq!(schedule("Europe/Berlin", window(&[], None, None)).unknown_timezone(), None);
+        assert_eq!(RuleSchedule::default().unknown_timezone(), None);
+    }
+}
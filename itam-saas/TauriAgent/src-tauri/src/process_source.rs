@@ -0,0 +1,248 @@
+// ============================================================================
+// Process Source Module
+// ============================================================================
+// Everything that looks at running processes (forbidden app scan, network
+// attribution, evidence, usage tracking) reads a ProcessTable from a
+// ProcessSource instead of calling sysinfo directly:
+//
+// - SysinfoSource: the live process table (what the agent normally uses)
+// - FixtureSource: replays process tables recorded as JSON, one snapshot per
+//   call, repeating the last one once the recording runs out
+//
+// This keeps the scan logic deterministic under test, and lets QA check a
+// policy against a recorded machine. QA builds enable the non-default
+// `process-fixture` cargo feature; ITAM_AGENT_PROCESS_FIXTURE then names a
+// fixture file the agent scans instead of the live table. Release builds
+// leave the feature off, so the variable cannot fake the process table.
+//
+// Fixture format (tests/fixtures/*.json):
+// {
+//   "snapshots": [
+//     [ { "pid": 100, "name": "steam", "exe_path": "/usr/bin/steam",
+//         "cmd": ["steam", "-silent"], "os_user": "alice",
+//         "parent_pid": 1, "start_time": 1700000000 } ],
+//     [ ... next scan ... ]
+//   ]
+// }
+// Only pid and name are required.
+// ============================================================================
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use sysinfo::{System, Users};
+
+use crate::error::AgentError;
+
+/// Environment variable pointing a `process-fixture` build at a fixture file
+#[cfg(feature = "process-fixture")]
+pub const FIXTURE_ENV: &str = "ITAM_AGENT_PROCESS_FIXTURE";
+
+/// The facts the agent reads about one process
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub name: String,
+    #[serde(default)]
+    pub exe_path: Option<String>,
+    #[serde(default)]
+    pub cmd: Vec<String>,
+    #[serde(default)]
+    pub os_user: Option<String>,
+    #[serde(default)]
+    pub parent_pid: Option<u32>,
+    /// Seconds since UNIX epoch; with the PID it identifies a process
+    #[serde(default)]
+    pub start_time: u64,
+    #[serde(default)]
+    pub cpu_usage: f32,
+}
+
+/// One snapshot of the process table, ordered by PID
+#[derive(Debug, Clone, Default)]
+pub struct ProcessTable {
+    processes: BTreeMap<u32, ProcessInfo>,
+}
+
+impl ProcessTable {
+    pub fn new(processes: Vec<ProcessInfo>) -> Self {
+        Self {
+            processes: processes.into_iter().map(|p| (p.pid, p)).collect(),
+        }
+    }
+
+    pub fn get(&self, pid: u32) -> Option<&ProcessInfo> {
+        self.processes.get(&pid)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ProcessInfo> {
+        self.processes.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.processes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processes.is_empty()
+    }
+}
+
+pub trait ProcessSource {
+    /// Refresh and return the current process table
+    fn snapshot(&mut self) -> ProcessTable;
+}
+
+/// Live process table via sysinfo v0.30
+pub struct SysinfoSource {
+    sys: System,
+    users: Users,
+}
+
+impl SysinfoSource {
+    pub fn new() -> Self {
+        Self {
+            sys: System::new_all(),
+            users: Users::new_with_refreshed_list(),
+        }
+    }
+}
+
+impl Default for SysinfoSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProcessSource for SysinfoSource {
+    fn snapshot(&mut self) -> ProcessTable {
+        self.sys.refresh_processes();
+        self.users.refresh_list();
+
+        let processes = self
+            .sys
+            .processes()
+            .iter()
+            .map(|(pid, process)| ProcessInfo {
+                pid: pid.as_u32(),
+                name: process.name().to_string(),
+                exe_path: process.exe().map(|p| p.display().to_string()),
+                cmd: process.cmd().to_vec(),
+                os_user: process
+                    .user_id()
+                    .and_then(|uid| self.users.get_user_by_id(uid))
+                    .map(|user| user.name().to_string()),
+                parent_pid: process.parent().map(|p| p.as_u32()),
+                start_time: process.start_time(),
+                cpu_usage: process.cpu_usage(),
+            })
+            .collect();
+
+        ProcessTable::new(processes)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Recording {
+    snapshots: Vec<Vec<ProcessInfo>>,
+}
+
+/// Replays recorded process tables in order
+pub struct FixtureSource {
+    snapshots: Vec<Vec<ProcessInfo>>,
+    next: usize,
+}
+
+impl FixtureSource {
+    pub fn from_json(json: &str) -> Result<Self, AgentError> {
+        let recording: Recording = serde_json::from_str(json)?;
+        Ok(Self {
+            snapshots: recording.snapshots,
+            next: 0,
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, AgentError> {
+        let json = fs::read_to_string(path)?;
+        Self::from_json(&json)
+    }
+}
+
+impl ProcessSource for FixtureSource {
+    fn snapshot(&mut self) -> ProcessTable {
+        let Some(last) = self.snapshots.len().checked_sub(1) else {
+            return ProcessTable::default();
+        };
+        let index = self.next.min(last);
+        self.next += 1;
+        ProcessTable::new(self.snapshots[index].clone())
+    }
+}
+
+/// The fixture named by ITAM_AGENT_PROCESS_FIXTURE (`process-fixture`
+/// builds only), else the live table
+pub fn default_process_source() -> Box<dyn ProcessSource + Send> {
+    #[cfg(feature = "process-fixture")]
+    if let Ok(path) = std::env::var(FIXTURE_ENV) {
+        use tracing::{error, warn};
+
+        match FixtureSource::from_file(Path::new(&path)) {
+            Ok(source) => {
+                warn!("Scanning recorded processes from {} instead of the live table", path);
+                return Box::new(source);
+            }
+            Err(e) => error!("Ignoring {}={}: {}", FIXTURE_ENV, path, e),
+        }
+    }
+    Box::new(SysinfoSource::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(pid: u32, name: &str) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            name: name.to_string(),
+            ..ProcessInfo::default()
+        }
+    }
+
+    #[test]
+    fn fixture_replays_snapshots_then_repeats_the_last() {
+        let mut source = FixtureSource::from_json(
+            r#"{ "snapshots": [ [ { "pid": 1, "name": "init" } ],
+                                [ { "pid": 1, "name": "init" }, { "pid": 7, "name": "steam" } ] ] }"#,
+        )
+        .unwrap();
+
+        assert_eq!(source.snapshot().iter().count(), 1);
+        assert_eq!(source.snapshot().get(7).map(|p| p.name.as_str()), Some("steam"));
+        assert_eq!(source.snapshot().iter().count(), 2);
+    }
+
+    #[test]
+    fn empty_fixture_yields_empty_tables() {
+        let mut source = FixtureSource::from_json(r#"{ "snapshots": [] }"#).unwrap();
+        assert_eq!(source.snapshot().iter().count(), 0);
+    }
+
+    #[test]
+    fn malformed_fixture_is_a_parse_error() {
+        let err = FixtureSource::from_json(r#"{ "snapshots": [ [ { "name": "no pid" } ] ] }"#)
+            .err()
+            .unwrap();
+        assert!(matches!(err, AgentError::Parse(_)));
+        assert!(err.to_string().starts_with("Parse error"));
+    }
+
+    #[test]
+    fn table_is_ordered_by_pid_and_last_duplicate_wins() {
+        let table = ProcessTable::new(vec![process(30, "c"), process(10, "a"), process(30, "d")]);
+        let pids: Vec<u32> = table.iter().map(|p| p.pid).collect();
+        assert_eq!(pids, vec![10, 30]);
+        assert_eq!(table.get(30).unwrap().name, "d");
+    }
+}
@@ -0,0 +1,330 @@
+// ============================================================================
+// Push Channel Module
+// ============================================================================
+// A long-lived Server-Sent Events stream from the backend, so policy
+// changes and requests reach the agent in seconds instead of at the next
+// poll:
+//
+//   GET /api/agent/stream?device_id=<id>
+//   Authorization: Bearer <token>, Accept: text/event-stream
+//   Last-Event-ID: <id of the last event seen, after a reconnect>
+//
+// Events (`data` is JSON, may be empty):
+//
+// | event               | agent does                                      |
+// |---------------------|-------------------------------------------------|
+// | policy-updated      | runs the policy-sync task now                   |
+// | heartbeat-requested | runs the heartbeat task now                     |
+// | inventory-requested | runs process-scan and heartbeat now             |
+// | task-queued         | runs remote-tasks now (remote_tasks.rs)         |
+// | device-revoked      | forgets the monitoring token, monitoring stops  |
+// |                     | until the next login; data: { "reason": "..." } |
+//
+// Anything else (and `:` comment lines) only counts as a keepalive. The
+// server should send one at least every 30s; IDLE_TIMEOUT without a byte
+// means the connection is dead.
+//
+// Reconnects: after a stream that was established ends, right away; after
+// failures, exponential backoff up to MAX_RECONNECT_DELAY with jitter; an
+// older backend without the endpoint (404) is retried every
+// UNSUPPORTED_RETRY. Polling (policy-sync, heartbeat) never stops, so a
+// missing stream only costs latency.
+// ============================================================================
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::api::{self, unix_now, Backend};
+use crate::error::AgentError;
+use crate::forbidden::get_device_id;
+use crate::supervisor::backoff;
+
+pub const STREAM_PATH: &str = "/api/agent/stream";
+const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(300);
+const UNSUPPORTED_RETRY: Duration = Duration::from_secs(30 * 60);
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// How often a logged-out agent checks for a token
+const NO_TOKEN_POLL: Duration = Duration::from_secs(5);
+
+/// One Server-Sent Event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    pub event: String,
+    pub data: String,
+    pub id: Option<String>,
+}
+
+/// Incremental text/event-stream parser; chunks may split lines anywhere
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+    id: Option<String>,
+}
+
+impl SseParser {
+    /// Feed a chunk, get the events it completed
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                if !self.data.is_empty() || self.event.is_some() {
+                    events.push(SseEvent {
+                        event: self.event.take().unwrap_or_else(|| "message".to_string()),
+                        data: self.data.join("\n"),
+                        id: self.id.clone(),
+                    });
+                    self.data.clear();
+                }
+                continue;
+            }
+            if line.starts_with(':') {
+                continue;
+            }
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => self.event = Some(value.to_string()),
+                "data" => self.data.push(value.to_string()),
+                "id" => self.id = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        events
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PushCommand {
+    PolicyUpdated,
+    HeartbeatRequested,
+    InventoryRequested,
+    TaskQueued,
+    DeviceRevoked { reason: Option<String> },
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RevokeData {
+    reason: Option<String>,
+}
+
+impl PushCommand {
+    /// The command an event carries, None for keepalives and unknown events
+    pub fn from_event(event: &SseEvent) -> Option<Self> {
+        match event.event.as_str() {
+            "policy-updated" => Some(PushCommand::PolicyUpdated),
+            "heartbeat-requested" => Some(PushCommand::HeartbeatRequested),
+            "inventory-requested" => Some(PushCommand::InventoryRequested),
+            "task-queued" => Some(PushCommand::TaskQueued),
+            "device-revoked" => {
+                let data: RevokeData = serde_json::from_str(&event.data).unwrap_or_default();
+                Some(PushCommand::DeviceRevoked { reason: data.reason })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Push connection state, reported in AgentStatus
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PushStatus {
+    pub connected: bool,
+    /// Seconds since UNIX epoch
+    pub last_connected: Option<u64>,
+    pub last_event: Option<u64>,
+    pub reconnects: u32,
+    /// The backend answered 404: no push endpoint, polling only
+    pub unsupported: bool,
+    pub last_error: Option<String>,
+}
+
+/// Up to a fifth of `delay` extra, so agents do not reconnect in lockstep
+fn with_jitter(delay: Duration) -> Duration {
+    let spread = delay.as_millis() as u64 / 5;
+    if spread == 0 {
+        return delay;
+    }
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos() as u64;
+    delay + Duration::from_millis(nanos % spread)
+}
+
+pub struct PushChannel {
+    backend: Backend,
+    status: Arc<Mutex<PushStatus>>,
+    last_event_id: Option<String>,
+}
+
+impl PushChannel {
+    pub fn new(backend: &Backend, status: Arc<Mutex<PushStatus>>) -> Self {
+        Self {
+            backend: backend.clone(),
+            status,
+            last_event_id: None,
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut PushStatus)) {
+        f(&mut self.status.lock().unwrap());
+    }
+
+    /// Hold one stream until the server closes it (Ok) or it fails; every
+    /// command is passed to `on_command`. Returns whether the stream was
+    /// established at all.
+    pub async fn connect(
+        &mut self,
+        token: &str,
+        on_command: &mut (dyn FnMut(PushCommand) + Send),
+    ) -> (bool, Result<(), AgentError>) {
+        let url = format!("{}?device_id={}", self.backend.url(STREAM_PATH), get_device_id());
+        let mut request = self.backend.streaming_client()
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Accept", "text/event-stream");
+        if let Some(id) = &self.last_event_id {
+            request = request.header("Last-Event-ID", id.as_str());
+        }
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(e) => return (false, Err(e.into())),
+        };
+        let mut response = match api::ensure_success(response).await {
+            Ok(response) => response,
+            Err(e) => return (false, Err(e)),
+        };
+        self.update(|s| {
+            s.connected = true;
+            s.unsupported = false;
+            s.last_connected = Some(unix_now());
+        });
+        info!("Push channel connected");
+
+        let mut parser = SseParser::default();
+        let result = loop {
+            let chunk = match tokio::time::timeout(IDLE_TIMEOUT, response.chunk()).await {
+                Err(_) => break Err(AgentError::Timeout("no keepalive on the push channel".to_string())),
+                Ok(Err(e)) => break Err(e.into()),
+                Ok(Ok(None)) => break Ok(()),
+                Ok(Ok(Some(chunk))) => chunk,
+            };
+            for event in parser.feed(&chunk) {
+                if event.id.is_some() {
+                    self.last_event_id = event.id.clone();
+                }
+                self.update(|s| s.last_event = Some(unix_now()));
+                match PushCommand::from_event(&event) {
+                    Some(command) => on_command(command),
+                    None => debug!(event = %event.event, "Push keepalive"),
+                }
+            }
+        };
+        self.update(|s| s.connected = false);
+        (true, result)
+    }
+
+    /// Keep a stream open whenever there is a token, until `cancel`
+    pub async fn run(
+        &mut self,
+        token: impl Fn() -> Option<String>,
+        cancel: CancellationToken,
+        mut on_command: impl FnMut(PushCommand) + Send,
+    ) {
+        let mut failures = 0;
+        loop {
+            let delay = match token() {
+                None => NO_TOKEN_POLL,
+                Some(token) => {
+                    let (established, result) = tokio::select! {
+                        _ = cancel.cancelled() => return,
+                        outcome = self.connect(&token, &mut on_command) => outcome,
+                    };
+                    if established {
+                        failures = 0;
+                    }
+                    match result {
+                        Ok(()) => RECONNECT_DELAY,
+                        Err(AgentError::Server { status: 404, .. }) => {
+                            warn!("Backend has no push endpoint, relying on polling");
+                            self.update(|s| s.unsupported = true);
+                            UNSUPPORTED_RETRY
+                        }
+                        Err(e) => {
+                            failures += 1;
+                            warn!(failures, "Push channel lost: {}", e);
+                            let message = e.to_string();
+                            self.update(|s| s.last_error = Some(message));
+                            with_jitter(backoff(failures, MAX_RECONNECT_DELAY))
+                        }
+                    }
+                }
+            };
+            self.update(|s| s.reconnects += 1);
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = tokio::time::sleep(delay) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parser_handles_split_chunks_comments_and_multiline_data() {
+        let mut parser = SseParser::default();
+        assert!(parser.feed(b": keepalive\n\nevent: policy-upd").is_empty());
+        let events = parser.feed(b"ated\r\nid: 7\r\ndata: {\"version\":\n\r\ndata: a\ndata: b\n\n");
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    event: "policy-updated".to_string(),
+                    data: "{\"version\":".to_string(),
+                    id: Some("7".to_string()),
+                },
+                SseEvent {
+                    event: "message".to_string(),
+                    data: "a\nb".to_string(),
+                    id: Some("7".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn events_map_to_commands() {
+        let event = |name: &str, data: &str| SseEvent {
+            event: name.to_string(),
+            data: data.to_string(),
+            id: None,
+        };
+        assert_eq!(PushCommand::from_event(&event("policy-updated", "")), Some(PushCommand::PolicyUpdated));
+        assert_eq!(
+            PushCommand::from_event(&event("device-revoked", r#"{"reason":"stolen"}"#)),
+            Some(PushCommand::DeviceRevoked {
+                reason: Some("stolen".to_string())
+            })
+        );
+        assert_eq!(
+            PushCommand::from_event(&event("device-revoked", "")),
+            Some(PushCommand::DeviceRevoked { reason: None })
+        );
+        assert_eq!(PushCommand::from_event(&event("ping", "")), None);
+    }
+}
@@ -0,0 +1,431 @@
+// ============================================================================
+// Remote Tasks Module
+// ============================================================================
+// Actions an admin queues for this device from the dashboard:
+//
+//   GET  /api/agent/tasks?device_id=<id>
+//   [ { "id": 42, "type": "upload_logs", "params": { "window_minutes": 30 },
+//       "timeout_secs": 60 } ]
+//
+//   POST /api/agent/tasks/<id>/result
+//   { "device_id": "...", "task_id": "42", "type": "upload_logs",
+//     "status": "succeeded", "started_at": 1700000000,
+//     "finished_at": 1700000002, "duration_ms": 1830,
+//     "output": { "uploaded": 120, "window_minutes": 30 }, "error": null }
+//
+// The "remote-tasks" task polls the queue every
+// settings.remote_tasks.poll_interval_secs; a "task-queued" push event
+// (push.rs) runs it right away.
+//
+// Catalog:
+//
+// | type               | params               | does                          |
+// |--------------------|----------------------|-------------------------------|
+// | rescan             | -                    | process scan, report alerts   |
+// | collect_inventory  | -                    | OS, hardware, disks; sends a  |
+// |                    |                      | heartbeat                     |
+// | upload_logs        | window_minutes (60)  | upload_logs, as from the UI   |
+// | clear_policy_cache | -                    | drop the cached forbidden     |
+// |                    |                      | list, sync it from scratch    |
+// | run_script         | script, signature    | signed script in a sandbox    |
+// |                    |                      | (scripts.rs)                  |
+//
+// status:
+// - rejected:  unknown type, bad params, or not in
+//              settings.remote_tasks.allowed - nothing was run
+// - timed_out: still running after timeout_secs (default
+//              default_timeout_secs, at most max_timeout_secs); abandoned
+// - failed:    ran and returned an error (a script: non-zero exit)
+// - succeeded: ran; `output` depends on the type
+//
+// run_script output is { "exit_code", "timed_out", "stdout", "stderr",
+// "truncated", "duration_ms" } for every status but rejected. There is no
+// unsigned shell task: the catalog is all the agent will do.
+//
+// Results go through the outbox (outbox.rs), so they survive offline
+// periods. Finished task ids are kept in finished_tasks.json next to the
+// outbox, so a task served again before its result is delivered does not
+// run twice, not even after a restart.
+// ============================================================================
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use sysinfo::Disks;
+use tracing::{info, warn};
+
+use crate::api::{self, unix_now, Backend};
+use crate::diagnostics::system_section;
+use crate::error::AgentError;
+use crate::forbidden::get_device_id;
+use crate::logging::{self, DEFAULT_UPLOAD_WINDOW_MINUTES, MAX_UPLOAD_WINDOW_MINUTES};
+use crate::scripts::{self, AuditRecord, VerifiedScript};
+use crate::settings::{agent_config_dir, AgentSettings, DeviceSettings};
+use crate::state::AgentState;
+use crate::supervisor::lock;
+
+/// Finished task ids kept for de-duplication
+const MAX_FINISHED: usize = 256;
+
+/// A queued task as served by the backend
+#[derive(Debug, Clone, Deserialize)]
+pub struct RemoteTask {
+    #[serde(deserialize_with = "id_string")]
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub params: Value,
+    pub timeout_secs: Option<u64>,
+}
+
+/// Task and rule ids are serial numbers in Postgres but opaque to the agent
+pub(crate) fn id_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::String(id) => Ok(id),
+        Value::Number(id) => Ok(id.to_string()),
+        other => Err(serde::de::Error::custom(format!("invalid task id {}", other))),
+    }
+}
+
+/// The catalog, with typed parameters
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TaskKind {
+    Rescan,
+    CollectInventory,
+    UploadLogs { window_minutes: u64 },
+    ClearPolicyCache,
+    RunScript(VerifiedScript),
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UploadLogsParams {
+    window_minutes: Option<u64>,
+}
+
+impl TaskKind {
+    pub fn parse(kind: &str, params: &Value, settings: &AgentSettings, device: &DeviceSettings) -> Result<Self, AgentError> {
+        let no_params = || match params {
+            Value::Null => Ok(()),
+            Value::Object(map) if map.is_empty() => Ok(()),
+            _ => Err(AgentError::Policy(format!("{} takes no params", kind))),
+        };
+        match kind {
+            "rescan" => no_params().map(|_| TaskKind::Rescan),
+            "collect_inventory" => no_params().map(|_| TaskKind::CollectInventory),
+            "clear_policy_cache" => no_params().map(|_| TaskKind::ClearPolicyCache),
+            "upload_logs" => {
+                let params: UploadLogsParams = match params {
+                    Value::Null => UploadLogsParams::default(),
+                    params => serde_json::from_value(params.clone())
+                        .map_err(|e| AgentError::Policy(format!("Invalid upload_logs params: {}", e)))?,
+                };
+                Ok(TaskKind::UploadLogs {
+                    window_minutes: params
+                        .window_minutes
+                        .unwrap_or(DEFAULT_UPLOAD_WINDOW_MINUTES)
+                        .clamp(1, MAX_UPLOAD_WINDOW_MINUTES),
+                })
+            }
+            "run_script" => scripts::verify(params, &settings.scripts, device, unix_now()).map(TaskKind::RunScript),
+            other => Err(AgentError::Policy(format!("Unknown task type {:?}", other))),
+        }
+    }
+}
+
+/// Parse a task and check it against the allowlist; returns what to run
+/// and for how long at most
+pub fn admit(
+    task: &RemoteTask,
+    settings: &AgentSettings,
+    device: &DeviceSettings,
+) -> Result<(TaskKind, Duration), AgentError> {
+    let kind = TaskKind::parse(&task.kind, &task.params, settings, device)?;
+    let settings = &settings.remote_tasks;
+    if !settings.allowed.contains(&task.kind) {
+        return Err(AgentError::Policy(format!("Task type {:?} is not allowed on this device", task.kind)));
+    }
+    let timeout = task
+        .timeout_secs
+        .unwrap_or(settings.default_timeout_secs)
+        .clamp(1, settings.max_timeout_secs.max(1));
+    Ok((kind, Duration::from_secs(timeout)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Succeeded,
+    Failed,
+    Rejected,
+    TimedOut,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskResult {
+    pub device_id: String,
+    pub task_id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub status: TaskStatus,
+    /// Seconds since UNIX epoch
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub duration_ms: u64,
+    pub output: Option<Value>,
+    pub error: Option<String>,
+}
+
+fn get_finished_path() -> PathBuf {
+    agent_config_dir().join("finished_tasks.json")
+}
+
+/// Ids of tasks this agent already ran, oldest dropped first
+pub struct FinishedTasks {
+    path: PathBuf,
+    ids: Mutex<VecDeque<String>>,
+}
+
+impl FinishedTasks {
+    /// Load the persisted ids, or start empty if there are none
+    pub fn load() -> Self {
+        let path = get_finished_path();
+        let ids = fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Self { path, ids: Mutex::new(ids) }
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        lock(&self.ids).iter().any(|finished| finished == id)
+    }
+
+    /// Remember `id` and persist the list before the result is queued
+    fn insert(&self, id: &str) {
+        let mut ids = lock(&self.ids);
+        if ids.len() == MAX_FINISHED {
+            ids.pop_front();
+        }
+        ids.push_back(id.to_string());
+
+        let result = serde_json::to_string(&*ids)
+            .map_err(|e| format!("Serialize error: {}", e))
+            .and_then(|json| fs::write(&self.path, json).map_err(|e| format!("File write error: {}", e)));
+        if let Err(e) = result {
+            warn!("Failed to persist finished tasks: {}", e);
+        }
+    }
+}
+
+pub async fn fetch_tasks(backend: &Backend, token: &str) -> Result<Vec<RemoteTask>, AgentError> {
+    let url = format!("{}?device_id={}", backend.url("/api/agent/tasks"), get_device_id());
+    let response = api::send(backend.client().get(&url).header("Authorization", format!("Bearer {}", token))).await?;
+    let response = api::ensure_success(response).await?;
+    response.json().await.map_err(|e| AgentError::Parse(e.to_string()))
+}
+
+fn inventory() -> Value {
+    let disks: Vec<Value> = Disks::new_with_refreshed_list()
+        .iter()
+        .map(|disk| {
+            json!({
+                "mount_point": disk.mount_point().display().to_string(),
+                "file_system": disk.file_system().to_string_lossy(),
+                "total_bytes": disk.total_space(),
+                "available_bytes": disk.available_space(),
+                "removable": disk.is_removable(),
+            })
+        })
+        .collect();
+    let mut inventory = system_section();
+    inventory["disks"] = Value::Array(disks);
+    inventory
+}
+
+async fn execute(state: &AgentState, token: &str, kind: &TaskKind) -> Result<Value, AgentError> {
+    match kind {
+        TaskKind::Rescan => {
+            let mut monitor = state.monitor.lock().await;
+            monitor.check_staleness();
+            monitor.scan(token).await?;
+            drop(monitor);
+            let status = lock(&state.monitor_status).clone();
+            Ok(json!({ "rules": status.policy.rules, "active_violations": status.active_violations }))
+        }
+        TaskKind::CollectInventory => {
+            // The dashboard's device list reads the heartbeat
+            state.triggers.trigger("heartbeat");
+            Ok(inventory())
+        }
+        TaskKind::UploadLogs { window_minutes } => {
+            let logging = state.logging()?;
+            let window = Duration::from_secs(window_minutes * 60);
+            let uploaded = logging::upload_recent_logs(state.backend(), token, logging.dir(), window).await?;
+            Ok(json!({ "uploaded": uploaded, "window_minutes": window_minutes }))
+        }
+        TaskKind::ClearPolicyCache => {
+            let mut monitor = state.monitor.lock().await;
+            monitor.clear_policy_cache()?;
+            monitor.sync(token).await?;
+            drop(monitor);
+            let rules = lock(&state.monitor_status).policy.rules;
+            Ok(json!({ "rules": rules }))
+        }
+        TaskKind::RunScript(_) => unreachable!("scripts run through run_script"),
+    }
+}
+
+/// Keep the audit record locally and queue it for upload
+async fn audit(state: &AgentState, record: AuditRecord) {
+    scripts::append_audit(&record);
+    match serde_json::to_value(&record) {
+        Ok(payload) => state.monitor.lock().await.enqueue(scripts::AUDIT_ENDPOINT, payload),
+        Err(e) => warn!("Cannot queue script audit record: {}", e),
+    }
+}
+
+async fn run_script(
+    state: &AgentState,
+    task: &RemoteTask,
+    script: &VerifiedScript,
+    timeout: Duration,
+) -> (TaskStatus, Option<Value>, Option<String>) {
+    info!(task_id = %task.id, script = %script.id, sha256 = %script.sha256, "Running signed script");
+    let result = scripts::run(script, &state.settings.scripts, timeout).await;
+    audit(state, AuditRecord::ran(&task.id, script, &result)).await;
+    match result {
+        Err(e) => (TaskStatus::Failed, None, Some(e.to_string())),
+        Ok(output) => {
+            let (status, error) = match (output.timed_out, output.exit_code) {
+                (true, _) => (TaskStatus::TimedOut, Some(format!("Still running after {}s", timeout.as_secs()))),
+                (false, Some(0)) => (TaskStatus::Succeeded, None),
+                (false, Some(code)) => (TaskStatus::Failed, Some(format!("Exited with code {}", code))),
+                (false, None) => (TaskStatus::Failed, Some("Killed by a signal".to_string())),
+            };
+            (status, serde_json::to_value(&output).ok(), error)
+        }
+    }
+}
+
+/// Admit and run one task within its timeout
+pub async fn run_task(state: &AgentState, token: &str, task: &RemoteTask) -> TaskResult {
+    let started_at = unix_now();
+    let started = Instant::now();
+    let (status, output, error) = match admit(task, &state.settings, &state.device()) {
+        Err(e) => {
+            if task.kind == "run_script" {
+                audit(state, AuditRecord::rejected(&task.id, &task.params, &e)).await;
+            }
+            (TaskStatus::Rejected, None, Some(e.to_string()))
+        }
+        // Scripts enforce their timeout themselves, killing the whole run
+        Ok((TaskKind::RunScript(script), timeout)) => run_script(state, task, &script, timeout).await,
+        Ok((kind, timeout)) => match tokio::time::timeout(timeout, execute(state, token, &kind)).await {
+            Err(_) => (
+                TaskStatus::TimedOut,
+                None,
+                Some(format!("Still running after {}s", timeout.as_secs())),
+            ),
+            Ok(Err(e)) => (TaskStatus::Failed, None, Some(e.to_string())),
+            Ok(Ok(output)) => (TaskStatus::Succeeded, Some(output), None),
+        },
+    };
+    TaskResult {
+        device_id: get_device_id(),
+        task_id: task.id.clone(),
+        kind: task.kind.clone(),
+        status,
+        started_at,
+        finished_at: unix_now(),
+        duration_ms: started.elapsed().as_millis() as u64,
+        output,
+        error,
+    }
+}
+
+/// Fetch the queue, run what is new, queue the results for upload.
+/// Returns the results of the tasks run this time.
+pub async fn poll_and_run(state: &AgentState, token: &str) -> Result<Vec<TaskResult>, AgentError> {
+    let tasks = match fetch_tasks(state.backend(), token).await {
+        Ok(tasks) => tasks,
+        // A backend without the task queue
+        Err(AgentError::Server { status: 404, .. }) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut results = Vec::new();
+    for task in tasks {
+        if state.finished_tasks.contains(&task.id) {
+            continue;
+        }
+        info!(task_id = %task.id, kind = %task.kind, "Running remote task");
+        let result = run_task(state, token, &task).await;
+        match result.status {
+            TaskStatus::Succeeded => info!(task_id = %task.id, "Remote task succeeded"),
+            status => warn!(
+                task_id = %task.id,
+                status = ?status,
+                "Remote task did not succeed: {}",
+                result.error.as_deref().unwrap_or("unknown error")
+            ),
+        }
+        state.finished_tasks.insert(&task.id);
+
+        let endpoint = format!("/api/agent/tasks/{}/result", task.id);
+        state.monitor.lock().await.enqueue(&endpoint, serde_json::to_value(&result)?);
+        results.push(result);
+    }
+
+    if !results.is_empty() {
+        state.monitor.lock().await.flush_outbox(token).await?;
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(kind: &str, params: Value, timeout_secs: Option<u64>) -> RemoteTask {
+        serde_json::from_value(json!({ "id": 7, "type": kind, "params": params, "timeout_secs": timeout_secs }))
+            .unwrap()
+    }
+
+    #[test]
+    fn catalog_types_are_parsed_with_typed_params() {
+        let settings = AgentSettings::default();
+        let device = DeviceSettings::default();
+        let (kind, timeout) = admit(&task("upload_logs", json!({ "window_minutes": 99999 }), Some(30)), &settings, &device).unwrap();
+        assert_eq!(kind, TaskKind::UploadLogs { window_minutes: MAX_UPLOAD_WINDOW_MINUTES });
+        assert_eq!(timeout, Duration::from_secs(30));
+
+        let (kind, timeout) = admit(&task("rescan", Value::Null, Some(86_400)), &settings, &device).unwrap();
+        assert_eq!(kind, TaskKind::Rescan);
+        assert_eq!(timeout, Duration::from_secs(settings.remote_tasks.max_timeout_secs));
+        assert_eq!(task("rescan", Value::Null, None).id, "7");
+    }
+
+    #[test]
+    fn unknown_bad_or_disallowed_tasks_are_rejected() {
+        let mut settings = AgentSettings::default();
+        settings.remote_tasks.allowed = vec!["rescan".to_string(), "upload_logs".to_string()];
+        let device = DeviceSettings::default();
+        for rejected in [
+            task("shell", json!({ "command": "rm -rf /" }), None),
+            task("rescan", json!({ "force": true }), None),
+            task("upload_logs", json!({ "window_minutes": "all" }), None),
+            task("clear_policy_cache", Value::Null, None),
+            // No signing key configured
+            task("run_script", json!({ "script": "e30=", "signature": "AA==" }), None),
+        ] {
+            assert!(matches!(admit(&rejected, &settings, &device), Err(AgentError::Policy(_))), "{:?}", rejected);
+        }
+    }
+}
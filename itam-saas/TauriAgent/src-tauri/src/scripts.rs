@@ -0,0 +1,624 @@
+// ============================================================================
+// Signed Scripts Module
+// ============================================================================
+// Remediation scripts IT runs on a fleet through the "run_script" remote
+// task (remote_tasks.rs). A script only runs if the organisation signed it:
+//
+//   params: { "script": "<base64 of the script JSON>",
+//             "signature": "<base64 ed25519 signature of those bytes>" }
+//
+//   script JSON:
+//   { "id": "flush-dns", "interpreter": "sh", "body": "resolvectl flush-caches",
+//     "expires_at": 1700086400, "timeout_secs": 60,
+//     "targets": { "device_tags": ["linux-fleet"], "os": ["linux"] } }
+//
+// The signature covers the exact bytes, so nothing depends on how the
+// JSON is formatted: it is verified before anything is parsed. Then:
+// - expires_at must be in the future, so a leaked script cannot be
+//   replayed forever
+// - targets: device_ids / device_tags / org_units match like a rule scope
+//   (policy.rs), os against "linux" / "windows" / "macos"; a script
+//   naming none of the first three needs "all_devices": true
+// - interpreter: sh, bash, python3 or powershell
+// The key is built in from ITAM_AGENT_SCRIPT_PUBKEY (settings.rs); without
+// it every script is rejected.
+//
+// Sandbox:
+// - the body is written to a fresh directory
+//   <config_dir>/tauriagent/scripts/<run>/, the working directory of the
+//   run, removed afterwards
+// - environment cleared except PATH (and SystemRoot on Windows), stdin
+//   closed
+// - Unix: own process group, killed as a whole on timeout; rlimits on CPU
+//   seconds, address space, open files and file size (settings.scripts)
+// - Windows: timeout and kill only
+// - timeout: the lower of the task's and the script's timeout_secs
+// - stdout and stderr kept up to settings.scripts.max_output_bytes each
+//
+// Audit: every run and every rejected script is appended to
+// <config_dir>/tauriagent/script_audit.jsonl and queued for
+// POST /api/agent/script-audit:
+// { "timestamp": 1700000000, "device_id": "...", "task_id": "9",
+//   "script_id": "flush-dns", "sha256": "<of the signed bytes>",
+//   "decision": "ran", "reason": null, "exit_code": 0, "timed_out": false,
+//   "duration_ms": 412 }
+// ============================================================================
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tracing::{info, warn};
+
+use crate::api::unix_now;
+use crate::error::AgentError;
+use crate::forbidden::{device_policy_context, get_device_id};
+use crate::policy::{rule_active_for_device, RuleScope};
+use crate::settings::{agent_config_dir, DeviceSettings, ScriptSettings};
+
+pub const AUDIT_ENDPOINT: &str = "/api/agent/script-audit";
+/// How long output is still read after the script exited or was killed
+const OUTPUT_GRACE: Duration = Duration::from_secs(2);
+
+/// params of a run_script task
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptParams {
+    pub script: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ScriptTargets {
+    #[serde(flatten)]
+    pub scope: RuleScope,
+    #[serde(default)]
+    pub os: Vec<String>,
+    #[serde(default)]
+    pub all_devices: bool,
+}
+
+/// The signed script JSON
+#[derive(Debug, Clone, Deserialize)]
+pub struct SignedScript {
+    pub id: String,
+    pub interpreter: String,
+    pub body: String,
+    /// Seconds since UNIX epoch
+    pub expires_at: u64,
+    pub timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub targets: ScriptTargets,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpreter {
+    Sh,
+    Bash,
+    Python3,
+    Powershell,
+}
+
+impl Interpreter {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "sh" => Some(Interpreter::Sh),
+            "bash" => Some(Interpreter::Bash),
+            "python3" => Some(Interpreter::Python3),
+            "powershell" => Some(Interpreter::Powershell),
+            _ => None,
+        }
+    }
+
+    fn file_name(self) -> &'static str {
+        match self {
+            Interpreter::Sh | Interpreter::Bash => "script.sh",
+            Interpreter::Python3 => "script.py",
+            Interpreter::Powershell => "script.ps1",
+        }
+    }
+
+    fn command(self, file: &Path) -> Command {
+        let (program, args): (&str, &[&str]) = match self {
+            Interpreter::Sh => ("sh", &[]),
+            Interpreter::Bash => ("bash", &[]),
+            Interpreter::Python3 => ("python3", &[]),
+            Interpreter::Powershell => (
+                "powershell",
+                &["-NoProfile", "-NonInteractive", "-ExecutionPolicy", "Bypass", "-File"],
+            ),
+        };
+        let mut command = Command::new(program);
+        command.args(args).arg(file);
+        command
+    }
+}
+
+/// A script that passed every check, ready to run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VerifiedScript {
+    pub id: String,
+    pub interpreter: Interpreter,
+    #[serde(skip)]
+    pub body: String,
+    /// Of the signed bytes, for the audit trail
+    pub sha256: String,
+    pub timeout_secs: Option<u64>,
+}
+
+fn rejected(reason: impl Into<String>) -> AgentError {
+    AgentError::Policy(reason.into())
+}
+
+fn public_key(settings: &ScriptSettings) -> Result<VerifyingKey, AgentError> {
+    let encoded = settings
+        .public_key
+        .as_deref()
+        .ok_or_else(|| rejected("No script signing key configured"))?;
+    let invalid = |e: String| rejected(format!("Invalid script signing key: {}", e));
+    let bytes: [u8; 32] = BASE64
+        .decode(encoded.trim())
+        .map_err(|e| invalid(e.to_string()))?
+        .try_into()
+        .map_err(|_| invalid("expected 32 bytes".to_string()))?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| invalid(e.to_string()))
+}
+
+/// sha256 of the signed bytes in `params`, for auditing scripts that were
+/// rejected (None if there are no readable bytes)
+pub fn script_digest(params: &Value) -> Option<String> {
+    let encoded = params["script"].as_str()?;
+    let bytes = BASE64.decode(encoded.trim()).ok()?;
+    Some(hex::encode(Sha256::digest(bytes)))
+}
+
+fn os_matches(targets: &[String]) -> bool {
+    targets.is_empty() || targets.iter().any(|os| os.eq_ignore_ascii_case(std::env::consts::OS))
+}
+
+/// Signature, expiry, target scope and interpreter of a run_script task
+pub fn verify(
+    params: &Value,
+    settings: &ScriptSettings,
+    device: &DeviceSettings,
+    now: u64,
+) -> Result<VerifiedScript, AgentError> {
+    let params: ScriptParams = serde_json::from_value(params.clone())
+        .map_err(|e| rejected(format!("Invalid run_script params: {}", e)))?;
+    let key = public_key(settings)?;
+
+    let bytes = BASE64
+        .decode(params.script.trim())
+        .map_err(|_| rejected("Script is not valid base64"))?;
+    let signature = BASE64
+        .decode(params.signature.trim())
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or_else(|| rejected("Script signature is malformed"))?;
+    key.verify(&bytes, &signature)
+        .map_err(|_| rejected("Script signature is invalid"))?;
+
+    let script: SignedScript =
+        serde_json::from_slice(&bytes).map_err(|e| rejected(format!("Invalid script: {}", e)))?;
+    if script.expires_at <= now {
+        return Err(rejected(format!("Script {} expired", script.id)));
+    }
+
+    let scope = &script.targets.scope;
+    let names_devices = !scope.device_ids.is_empty() || !scope.device_tags.is_empty() || !scope.org_units.is_empty();
+    if !names_devices && !script.targets.all_devices {
+        return Err(rejected(format!("Script {} has no target scope", script.id)));
+    }
+    let ctx = device_policy_context(device);
+    if !rule_active_for_device(None, Some(scope), None, &ctx) || !os_matches(&script.targets.os) {
+        return Err(rejected(format!("Script {} does not target this device", script.id)));
+    }
+
+    let interpreter = Interpreter::parse(&script.interpreter)
+        .ok_or_else(|| rejected(format!("Unsupported interpreter {:?}", script.interpreter)))?;
+
+    Ok(VerifiedScript {
+        id: script.id,
+        interpreter,
+        body: script.body,
+        sha256: hex::encode(Sha256::digest(&bytes)),
+        timeout_secs: script.timeout_secs,
+    })
+}
+
+/// What a script run produced
+#[derive(Debug, Clone, Serialize)]
+pub struct ScriptOutput {
+    /// None when killed (timeout, signal)
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    pub stdout: String,
+    pub stderr: String,
+    /// stdout or stderr was cut at max_output_bytes
+    pub truncated: bool,
+    pub duration_ms: u64,
+}
+
+/// Read a pipe to the end, keeping the first `max` bytes
+async fn read_capped(mut pipe: impl AsyncRead + Unpin, max: usize) -> (Vec<u8>, bool) {
+    let mut kept = Vec::new();
+    let mut truncated = false;
+    let mut chunk = [0u8; 8192];
+    loop {
+        match pipe.read(&mut chunk).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                let room = max.saturating_sub(kept.len());
+                kept.extend_from_slice(&chunk[..n.min(room)]);
+                truncated |= n > room;
+            }
+        }
+    }
+    (kept, truncated)
+}
+
+fn run_dir(base: &Path, script: &VerifiedScript) -> PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    base.join(format!("{}-{}", millis, &script.sha256[..12]))
+}
+
+#[cfg(unix)]
+fn confine(command: &mut Command, settings: &ScriptSettings) {
+    let limits = [
+        (libc::RLIMIT_CPU, settings.max_cpu_secs),
+        (libc::RLIMIT_AS, settings.max_memory_mb * 1024 * 1024),
+        (libc::RLIMIT_NOFILE, settings.max_open_files),
+        (libc::RLIMIT_FSIZE, settings.max_file_size_mb * 1024 * 1024),
+    ];
+    command.process_group(0);
+    // SAFETY: only async-signal-safe setrlimit calls between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            for (resource, value) in limits {
+                let limit = libc::rlimit {
+                    rlim_cur: value as libc::rlim_t,
+                    rlim_max: value as libc::rlim_t,
+                };
+                if libc::setrlimit(resource, &limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn confine(_command: &mut Command, _settings: &ScriptSettings) {}
+
+/// Stop whatever is left in the process group confine() created for the
+/// script; `pid` is the script's, taken before it was waited for
+fn kill_group(pid: Option<u32>) {
+    #[cfg(unix)]
+    if let Some(pid) = pid {
+        // SAFETY: signals the process group created by confine()
+        unsafe {
+            libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = pid;
+}
+
+/// Run a verified script in the sandbox, at most `timeout`
+pub async fn run(script: &VerifiedScript, settings: &ScriptSettings, timeout: Duration) -> Result<ScriptOutput, AgentError> {
+    run_in(&agent_config_dir().join("scripts"), script, settings, timeout).await
+}
+
+async fn run_in(
+    base: &Path,
+    script: &VerifiedScript,
+    settings: &ScriptSettings,
+    timeout: Duration,
+) -> Result<ScriptOutput, AgentError> {
+    let timeout = match script.timeout_secs {
+        Some(secs) => timeout.min(Duration::from_secs(secs.max(1))),
+        None => timeout,
+    };
+    let dir = run_dir(base, script);
+    fs::create_dir_all(&dir)?;
+    let file = dir.join(script.interpreter.file_name());
+    fs::write(&file, &script.body)?;
+
+    let mut command = script.interpreter.command(&file);
+    command
+        .current_dir(&dir)
+        .env_clear()
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(path) = std::env::var_os("PATH") {
+        command.env("PATH", path);
+    }
+    if let Some(root) = std::env::var_os("SystemRoot") {
+        command.env("SystemRoot", root);
+    }
+    confine(&mut command, settings);
+
+    let started = Instant::now();
+    let spawned = command.spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            let _ = fs::remove_dir_all(&dir);
+            return Err(AgentError::Io(format!("Cannot start {:?}: {}", script.interpreter, e)));
+        }
+    };
+
+    let max = settings.max_output_bytes;
+    let stdout = tokio::spawn(read_capped(child.stdout.take().expect("stdout is piped"), max));
+    let stderr = tokio::spawn(read_capped(child.stderr.take().expect("stderr is piped"), max));
+
+    let group = child.id();
+    let waited = tokio::time::timeout(timeout, child.wait()).await;
+    if waited.is_err() {
+        warn!(script = %script.id, "Script timed out after {}s, killing it", timeout.as_secs());
+    }
+    // Also after a normal exit: background jobs must not outlive the script
+    kill_group(group);
+    let (exit_code, timed_out) = match waited {
+        Ok(status) => (status?.code(), false),
+        Err(_) => {
+            let _ = child.kill().await;
+            (None, true)
+        }
+    };
+    let duration_ms = started.elapsed().as_millis() as u64;
+
+    // A process that escaped the group could keep the pipes open
+    let collect = |reader: tokio::task::JoinHandle<(Vec<u8>, bool)>| async move {
+        match tokio::time::timeout(OUTPUT_GRACE, reader).await {
+            Ok(Ok(output)) => output,
+            _ => (Vec::new(), true),
+        }
+    };
+    let (stdout, stdout_truncated) = collect(stdout).await;
+    let (stderr, stderr_truncated) = collect(stderr).await;
+    let _ = fs::remove_dir_all(&dir);
+
+    Ok(ScriptOutput {
+        exit_code,
+        timed_out,
+        stdout: String::from_utf8_lossy(&stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        truncated: stdout_truncated || stderr_truncated,
+        duration_ms,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditDecision {
+    Ran,
+    Rejected,
+}
+
+/// One line of script_audit.jsonl
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    pub timestamp: u64,
+    pub device_id: String,
+    pub task_id: String,
+    pub script_id: Option<String>,
+    pub sha256: Option<String>,
+    pub decision: AuditDecision,
+    pub reason: Option<String>,
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    pub duration_ms: Option<u64>,
+}
+
+impl AuditRecord {
+    pub fn rejected(task_id: &str, params: &Value, reason: &AgentError) -> Self {
+        Self {
+            timestamp: unix_now(),
+            device_id: get_device_id(),
+            task_id: task_id.to_string(),
+            script_id: None,
+            sha256: script_digest(params),
+            decision: AuditDecision::Rejected,
+            reason: Some(reason.to_string()),
+            exit_code: None,
+            timed_out: false,
+            duration_ms: None,
+        }
+    }
+
+    pub fn ran(task_id: &str, script: &VerifiedScript, result: &Result<ScriptOutput, AgentError>) -> Self {
+        let output = result.as_ref().ok();
+        Self {
+            timestamp: unix_now(),
+            device_id: get_device_id(),
+            task_id: task_id.to_string(),
+            script_id: Some(script.id.clone()),
+            sha256: Some(script.sha256.clone()),
+            decision: AuditDecision::Ran,
+            reason: result.as_ref().err().map(|e| e.to_string()),
+            exit_code: output.and_then(|o| o.exit_code),
+            timed_out: output.is_some_and(|o| o.timed_out),
+            duration_ms: output.map(|o| o.duration_ms),
+        }
+    }
+}
+
+pub fn audit_log_path() -> PathBuf {
+    agent_config_dir().join("script_audit.jsonl")
+}
+
+/// Append to the local audit log; the caller queues the upload
+pub fn append_audit(record: &AuditRecord) {
+    info!(
+        task_id = %record.task_id,
+        script = record.script_id.as_deref().unwrap_or("-"),
+        decision = ?record.decision,
+        "Script audit"
+    );
+    let line = match serde_json::to_string(record) {
+        Ok(line) => line,
+        Err(e) => return warn!("Cannot serialize script audit record: {}", e),
+    };
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(audit_log_path())
+        .and_then(|mut file| writeln!(file, "{}", line));
+    if let Err(e) = written {
+        warn!("Cannot write script audit log: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use serde_json::json;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn signed(key: &SigningKey, script: Value) -> Value {
+        let bytes = script.to_string().into_bytes();
+        json!({
+            "script": BASE64.encode(&bytes),
+            "signature": BASE64.encode(key.sign(&bytes).to_bytes()),
+        })
+    }
+
+    fn settings(key: &SigningKey) -> ScriptSettings {
+        ScriptSettings {
+            public_key: Some(BASE64.encode(key.verifying_key().to_bytes())),
+            ..ScriptSettings::default()
+        }
+    }
+
+    fn script(targets: Value) -> Value {
+        json!({ "id": "fix", "interpreter": "sh", "body": "echo ok", "expires_at": NOW + 60, "targets": targets })
+    }
+
+    #[test]
+    fn verify_checks_signature_expiry_and_scope() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let settings = settings(&key);
+        let device = DeviceSettings {
+            tags: vec!["kiosk".to_string()],
+            org_unit: None,
+        };
+        let verify = |params: &Value| verify(params, &settings, &device, NOW);
+
+        let ok = verify(&signed(&key, script(json!({ "device_tags": ["KIOSK"] })))).unwrap();
+        assert_eq!((ok.id.as_str(), ok.interpreter, ok.body.as_str()), ("fix", Interpreter::Sh, "echo ok"));
+        assert!(verify(&signed(&key, script(json!({ "all_devices": true })))).is_ok());
+
+        let mut tampered = signed(&key, script(json!({ "all_devices": true })));
+        tampered["script"] = json!(BASE64.encode(script(json!({ "all_devices": true, "os": ["plan9"] })).to_string()));
+        let foreign = signed(&SigningKey::from_bytes(&[8; 32]), script(json!({ "all_devices": true })));
+        let mut expired = script(json!({ "all_devices": true }));
+        expired["expires_at"] = json!(NOW);
+        let mut perl = script(json!({ "all_devices": true }));
+        perl["interpreter"] = json!("perl");
+
+        for (params, reason) in [
+            (tampered, "signature is invalid"),
+            (foreign, "signature is invalid"),
+            (signed(&key, expired), "expired"),
+            (signed(&key, script(json!({}))), "no target scope"),
+            (signed(&key, script(json!({ "device_tags": ["finance"] }))), "does not target"),
+            (signed(&key, script(json!({ "all_devices": true, "os": ["plan9"] }))), "does not target"),
+            (signed(&key, perl), "Unsupported interpreter"),
+        ] {
+            let error = verify(&params).unwrap_err().to_string();
+            assert!(error.contains(reason), "{} should contain {}", error, reason);
+        }
+
+        let unsigned = ScriptSettings::default();
+        let params = signed(&key, script(json!({ "all_devices": true })));
+        assert!(super::verify(&params, &unsigned, &device, NOW).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_captures_output_and_kills_on_timeout() {
+        let script = |body: &str| VerifiedScript {
+            id: "test".to_string(),
+            interpreter: Interpreter::Sh,
+            body: body.to_string(),
+            sha256: "0123456789abcdef".to_string(),
+            timeout_secs: None,
+        };
+        let settings = ScriptSettings {
+            max_output_bytes: 8,
+            ..ScriptSettings::default()
+        };
+        let base = tempfile::tempdir().unwrap();
+
+        let output = run_in(base.path(), &script("echo hello; echo oops >&2; exit 3"), &settings, Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(output.exit_code, Some(3));
+        assert_eq!((output.stdout.as_str(), output.stderr.as_str()), ("hello\n", "oops\n"));
+        assert!(!output.truncated && !output.timed_out);
+
+        let output = run_in(base.path(), &script("echo 0123456789; sleep 30 & wait"), &settings, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert!(output.timed_out && output.truncated);
+        assert_eq!((output.exit_code, output.stdout.as_str()), (None, "01234567"));
+        assert!(output.duration_ms < 5_000);
+        // The run directory is removed
+        assert_eq!(fs::read_dir(base.path()).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn background_jobs_are_killed_when_the_script_exits() {
+        let script = VerifiedScript {
+            id: "test".to_string(),
+            interpreter: Interpreter::Sh,
+            body: "sleep 30 & echo $!".to_string(),
+            sha256: "0123456789abcdef".to_string(),
+            timeout_secs: None,
+        };
+        let base = tempfile::tempdir().unwrap();
+
+        let started = Instant::now();
+        let output = run_in(base.path(), &script, &ScriptSettings::default(), Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(output.exit_code, Some(0));
+        // The pipes closed with the group, so the output was not cut short
+        assert!(!output.timed_out && !output.truncated);
+        assert!(started.elapsed() < OUTPUT_GRACE);
+
+        // Gone, or a zombie waiting for init to reap it
+        let sleeper = output.stdout.trim();
+        let mut alive = true;
+        for _ in 0..100 {
+            let ps = std::process::Command::new("ps").args(["-o", "stat=", "-p", sleeper]).output().unwrap();
+            let stat = String::from_utf8_lossy(&ps.stdout);
+            alive = !stat.trim().is_empty() && !stat.trim().starts_with('Z');
+            if !alive {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!alive, "background job {} outlived the script", sleeper);
+    }
+}
@@ -0,0 +1,557 @@
+// ============================================================================
+// Agent Settings Module
+// ============================================================================
+// Local overrides for agent behaviour that would otherwise be hardcoded.
+//
+// Location: <config_dir>/tauriagent/settings.json
+// (same directory as forbidden_cache.json)
+//
+// Environment overrides (local development and integration tests):
+// - ITAM_AGENT_CONFIG_DIR replaces <config_dir>/tauriagent for every file
+// - ITAM_AGENT_API_URL replaces api.base_url, e.g. a local mock backend;
+//   only in process-fixture (QA) builds, release builds ignore it
+//
+// Every section and field is optional: a missing file, a missing key or a
+// file that fails to parse falls back to the defaults below, so a fresh
+// install behaves exactly like an agent without a settings file.
+//
+// Enforcement switches and trust anchors are NOT read from this file, since
+// any local user can edit it. They come from the build (option_env!) and are
+// ignored here:
+// - api.base_url              ITAM_AGENT_API_URL (release backend otherwise)
+// - device                    the backend, with every heartbeat (heartbeat.rs)
+// - network.enabled           ITAM_AGENT_NETWORK_POLICY=0 turns it off
+// - policy.audit_only          ITAM_AGENT_AUDIT_ONLY=1 (pilot builds)
+// - update.public_key          ITAM_AGENT_UPDATE_PUBKEY (updater.rs)
+// - update.manifest_url        ITAM_AGENT_UPDATE_URL (updater.rs)
+// - scripts.public_key         ITAM_AGENT_SCRIPT_PUBKEY
+// - remote_tasks.allowed       the built-in allowlist below
+//
+// Example:
+// {
+//   "api": { "request_timeout_secs": 30 },
+//   "policy": { "sync_interval_secs": 300, "max_cache_age_secs": 86400 },
+//   "evidence": { "capture_command_line": true, "redact_args": ["password", "token"] },
+//   "notifications": { "enabled": true, "min_severity": "High" },
+//   "aggregation": { "window_secs": 900, "realert_after_secs": 3600 },
+//   "heartbeat": { "interval_secs": 120 },
+//   "shutdown": { "deadline_secs": 10 },
+//   "logging": { "level": "info", "max_files": 7 },
+//   "update": { "enabled": true, "channel": "stable", "check_interval_secs": 21600 },
+//   "remote_tasks": { "enabled": true, "poll_interval_secs": 300 },
+//   "scripts": { "max_memory_mb": 512 },
+//   "metrics": { "enabled": true, "listen": "127.0.0.1:9464" },
+//   "perf": { "sample_interval_secs": 15, "upload_interval_secs": 300 },
+//   "thresholds": { "check_interval_secs": 30, "rules_refresh_secs": 300 },
+//   "compliance": { "interval_secs": 21600, "probe_timeout_secs": 30 }
+// }
+// ============================================================================
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AgentSettings {
+    pub api: ApiSettings,
+    pub policy: PolicySettings,
+    pub evidence: EvidenceSettings,
+    /// Assigned by the backend (heartbeat.rs), never read from settings.json
+    #[serde(skip)]
+    pub device: DeviceSettings,
+    pub notifications: NotificationSettings,
+    pub network: NetworkSettings,
+    pub aggregation: AggregationSettings,
+    pub heartbeat: HeartbeatSettings,
+    pub shutdown: ShutdownSettings,
+    pub logging: LoggingSettings,
+    pub update: UpdateSettings,
+    pub remote_tasks: RemoteTaskSettings,
+    pub scripts: ScriptSettings,
+    pub metrics: MetricsSettings,
+    pub perf: PerfSettings,
+    pub thresholds: ThresholdSettings,
+    pub compliance: ComplianceSettings,
+}
+
+pub const DEFAULT_API_URL: &str = "https://it-asset-project-production.up.railway.app";
+pub const CONFIG_DIR_ENV: &str = "ITAM_AGENT_CONFIG_DIR";
+pub const API_URL_ENV: &str = "ITAM_AGENT_API_URL";
+/// Backend of this build; DEFAULT_API_URL when not set
+const BUILT_IN_API_URL: Option<&str> = option_env!("ITAM_AGENT_API_URL");
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiSettings {
+    /// Backend base URL, without a trailing slash. Set by the build, never
+    /// by settings.json: the agent sends its token there and trusts the
+    /// policy it serves
+    #[serde(skip)]
+    pub base_url: String,
+    /// Upper bound for a single API request, connect to last byte
+    pub request_timeout_secs: u64,
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        Self {
+            base_url: BUILT_IN_API_URL.unwrap_or(DEFAULT_API_URL).to_string(),
+            request_timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicySettings {
+    /// How often the forbidden list is re-validated against the server
+    pub sync_interval_secs: u64,
+    /// Cached policy older than this is reported as "policy stale"
+    pub max_cache_age_secs: u64,
+    /// Treat every rule as audit-only: record matches, never alert or enforce.
+    /// Set by the build (ITAM_AGENT_AUDIT_ONLY), never by settings.json
+    #[serde(skip)]
+    pub audit_only: bool,
+}
+
+/// Audit-only pilot builds, see PolicySettings::audit_only
+const BUILT_IN_AUDIT_ONLY: Option<&str> = option_env!("ITAM_AGENT_AUDIT_ONLY");
+/// Script signing key of the organisation, embedded at build time
+const BUILT_IN_SCRIPT_PUBLIC_KEY: Option<&str> = option_env!("ITAM_AGENT_SCRIPT_PUBKEY");
+
+impl Default for PolicySettings {
+    fn default() -> Self {
+        Self {
+            sync_interval_secs: 300,    // 5 minutes
+            max_cache_age_secs: 86_400, // 24 hours
+            audit_only: matches!(BUILT_IN_AUDIT_ONLY, Some("1" | "true")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EvidenceSettings {
+    /// Include the (redacted) command line in violation reports
+    pub capture_command_line: bool,
+    /// Argument names whose values are masked, case-insensitive substring match
+    pub redact_args: Vec<String>,
+    /// Include the executable's SHA-256 in violation reports
+    pub hash_executables: bool,
+    /// Executables larger than this are not hashed
+    pub max_hash_size_bytes: u64,
+    /// How many parent processes to record
+    pub parent_chain_depth: usize,
+}
+
+impl Default for EvidenceSettings {
+    fn default() -> Self {
+        Self {
+            capture_command_line: true,
+            redact_args: [
+                "password", "passwd", "pwd", "secret", "token",
+                "apikey", "api-key", "api_key", "authorization", "credential",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            hash_executables: true,
+            max_hash_size_bytes: 512 * 1024 * 1024, // 512 MB
+            parent_chain_depth: 5,
+        }
+    }
+}
+
+/// Device facts used to evaluate rule scopes and script targets, as the
+/// backend assigned them (e.g. { "tags": ["finance"], "org_unit": "EMEA/Finance" })
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceSettings {
+    pub tags: Vec<String>,
+    pub org_unit: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationSettings {
+    /// Show native desktop notifications at all
+    pub enabled: bool,
+    /// Violations below this severity ("Low".."Critical") stay silent
+    pub min_severity: String,
+    /// Minimum gap between two notifications about the same thing
+    pub min_interval_secs: u64,
+    /// Hard cap on notifications per sliding hour
+    pub max_per_hour: usize,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_severity: "Medium".to_string(),
+            min_interval_secs: 300, // 5 minutes
+            max_per_hour: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkSettings {
+    /// Match outbound connections against server network rules. Set by the
+    /// build (ITAM_AGENT_NETWORK_POLICY), never by settings.json; the
+    /// server turns rules off by serving none
+    #[serde(skip)]
+    pub enabled: bool,
+}
+
+/// "0" or "false" builds an agent without network rules
+const BUILT_IN_NETWORK_POLICY: Option<&str> = option_env!("ITAM_AGENT_NETWORK_POLICY");
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        Self {
+            enabled: !matches!(BUILT_IN_NETWORK_POLICY, Some("0" | "false")),
+        }
+    }
+}
+
+/// Grouping of repeated violations and re-alert policy, see aggregate.rs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AggregationSettings {
+    /// Report one alert per device, rule and executable instead of per PID
+    pub enabled: bool,
+    /// A group closes after this long without a new PID
+    pub window_secs: u64,
+    /// Re-alert an open group this long after its last alert (0 = never)
+    pub realert_after_secs: u64,
+    /// Re-alert an open group after this many new PIDs (0 = never)
+    pub realert_after_count: u32,
+    /// Raise the severity one step on every re-alert
+    pub escalate_severity: bool,
+    /// Cap on the PIDs listed in one report
+    pub max_pids: usize,
+}
+
+impl Default for AggregationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window_secs: 900,         // 15 minutes
+            realert_after_secs: 3600, // 1 hour
+            realert_after_count: 25,
+            escalate_severity: false,
+            max_pids: 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeartbeatSettings {
+    /// How often POST /api/agent/heartbeat is sent while logged in
+    pub interval_secs: u64,
+}
+
+impl Default for HeartbeatSettings {
+    fn default() -> Self {
+        Self { interval_secs: 120 } // 2 minutes
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShutdownSettings {
+    /// How long quitting may wait for running tasks and the outbox upload
+    /// before giving up (unsent records stay queued on disk)
+    pub deadline_secs: u64,
+}
+
+impl Default for ShutdownSettings {
+    fn default() -> Self {
+        Self { deadline_secs: 10 }
+    }
+}
+
+/// Log files and verbosity, see logging.rs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingSettings {
+    /// Filter directives at startup, e.g. "info" or "info,tauriagent_lib::monitor=debug"
+    pub level: String,
+    /// Daily log files kept before the oldest is deleted
+    pub max_files: usize,
+}
+
+impl Default for LoggingSettings {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            max_files: 7, // one week
+        }
+    }
+}
+
+/// Self-update, see updater.rs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpdateSettings {
+    pub enabled: bool,
+    /// "stable" or "beta"
+    pub channel: String,
+    pub check_interval_secs: u64,
+    /// Release manifest, "{channel}" is replaced; None = the URL built into
+    /// the agent, else <api>/api/agent/releases/{channel}. Never read from
+    /// settings.json
+    #[serde(skip)]
+    pub manifest_url: Option<String>,
+    /// Base64 ed25519 release key; None = the key built into the agent.
+    /// Never read from settings.json
+    #[serde(skip)]
+    pub public_key: Option<String>,
+    /// How long a new version runs before it is confirmed or rolled back
+    pub health_check_secs: u64,
+}
+
+impl Default for UpdateSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            channel: "stable".to_string(),
+            check_interval_secs: 21_600, // 6 hours
+            manifest_url: None,
+            public_key: None,
+            health_check_secs: 300, // 5 minutes
+        }
+    }
+}
+
+/// Admin-queued tasks, see remote_tasks.rs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RemoteTaskSettings {
+    pub enabled: bool,
+    /// How often the task queue is polled (a push event runs it sooner)
+    pub poll_interval_secs: u64,
+    /// Task types this device accepts; anything else is rejected.
+    /// Built in, never read from settings.json
+    #[serde(skip)]
+    pub allowed: Vec<String>,
+    /// Limit for tasks that do not set timeout_secs
+    pub default_timeout_secs: u64,
+    /// Upper bound on any task's timeout_secs
+    pub max_timeout_secs: u64,
+}
+
+impl Default for RemoteTaskSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            poll_interval_secs: 300, // 5 minutes
+            allowed: ["rescan", "collect_inventory", "upload_logs", "clear_policy_cache", "run_script"]
+                .map(String::from)
+                .to_vec(),
+            default_timeout_secs: 120,
+            max_timeout_secs: 900,
+        }
+    }
+}
+
+/// Signed remediation scripts, see scripts.rs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScriptSettings {
+    /// Base64 ed25519 key of the organisation, built in from
+    /// ITAM_AGENT_SCRIPT_PUBKEY; None = no script ever runs.
+    /// Never read from settings.json
+    #[serde(skip)]
+    pub public_key: Option<String>,
+    /// CPU time, address space, open files and largest written file of
+    /// the script process (Unix)
+    pub max_cpu_secs: u64,
+    pub max_memory_mb: u64,
+    pub max_open_files: u64,
+    pub max_file_size_mb: u64,
+    /// stdout and stderr kept in the task result, each
+    pub max_output_bytes: usize,
+}
+
+impl Default for ScriptSettings {
+    fn default() -> Self {
+        Self {
+            public_key: BUILT_IN_SCRIPT_PUBLIC_KEY.map(String::from),
+            max_cpu_secs: 300,
+            max_memory_mb: 1024,
+            max_open_files: 256,
+            max_file_size_mb: 100,
+            max_output_bytes: 64 * 1024,
+        }
+    }
+}
+
+/// Prometheus endpoint, see metrics.rs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsSettings {
+    pub enabled: bool,
+    /// Loopback address only, e.g. "127.0.0.1:9464" or "[::1]:9464"
+    pub listen: String,
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: "127.0.0.1:9464".to_string(),
+        }
+    }
+}
+
+/// Host performance time-series, see perf.rs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PerfSettings {
+    pub enabled: bool,
+    pub sample_interval_secs: u64,
+    /// How often finished rollups are uploaded
+    pub upload_interval_secs: u64,
+    /// Processes kept per sample and per rollup
+    pub top_processes: usize,
+}
+
+impl Default for PerfSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sample_interval_secs: 15,
+            upload_interval_secs: 300, // 5 minutes
+            top_processes: 5,
+        }
+    }
+}
+
+/// Threshold alert rules, see thresholds.rs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThresholdSettings {
+    pub enabled: bool,
+    /// How often the rules are evaluated; durations are only as
+    /// precise as this interval
+    pub check_interval_secs: u64,
+    pub rules_refresh_secs: u64,
+}
+
+impl Default for ThresholdSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            check_interval_secs: 30,
+            rules_refresh_secs: 300, // 5 minutes
+        }
+    }
+}
+
+/// Compliance checks, see compliance.rs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ComplianceSettings {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    /// Per probe command; a command that takes longer is an error
+    pub probe_timeout_secs: u64,
+}
+
+impl Default for ComplianceSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 21600, // 6 hours
+            probe_timeout_secs: 30,
+        }
+    }
+}
+
+/// Agent config directory, created on first use
+pub fn agent_config_dir() -> PathBuf {
+    let path = match std::env::var_os(CONFIG_DIR_ENV) {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            let mut path = dirs::config_dir().unwrap_or_else(|| PathBuf::from("."));
+            path.push("tauriagent");
+            path
+        }
+    };
+    if !path.exists() {
+        let _ = fs::create_dir_all(&path);
+    }
+    path
+}
+
+fn get_settings_path() -> PathBuf {
+    agent_config_dir().join("settings.json")
+}
+
+/// Load settings from disk, falling back to defaults
+pub fn load_settings() -> AgentSettings {
+    let mut settings = read_settings_file();
+    #[cfg(any(test, feature = "process-fixture"))]
+    if let Ok(url) = std::env::var(API_URL_ENV) {
+        settings.api.base_url = url;
+    }
+    settings.api.base_url = settings.api.base_url.trim_end_matches('/').to_string();
+    settings
+}
+
+fn read_settings_file() -> AgentSettings {
+    let path = get_settings_path();
+
+    if !path.exists() {
+        return AgentSettings::default();
+    }
+
+    match fs::read_to_string(&path)
+        .map_err(|e| format!("File read error: {}", e))
+        .and_then(|json| serde_json::from_str(&json).map_err(|e| format!("Parse error: {}", e)))
+    {
+        Ok(settings) => settings,
+        Err(e) => {
+            // Settings are read before logging is set up (logging.rs)
+            eprintln!("⚠️ Ignoring {}: {}", path.display(), e);
+            AgentSettings::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_file_cannot_set_trust_anchors_or_enforcement() {
+        let settings: AgentSettings = serde_json::from_str(
+            r#"{ "api": { "base_url": "http://evil", "request_timeout_secs": 5 },
+                 "device": { "tags": ["finance"], "org_unit": "EMEA/Finance" },
+                 "policy": { "audit_only": true, "sync_interval_secs": 60 },
+                 "network": { "enabled": false },
+                 "update": { "public_key": "attacker", "manifest_url": "http://evil/{channel}" },
+                 "scripts": { "public_key": "attacker" },
+                 "remote_tasks": { "allowed": ["run_script"], "poll_interval_secs": 60 } }"#,
+        )
+        .unwrap();
+        let defaults = AgentSettings::default();
+
+        assert_eq!(settings.api.base_url, defaults.api.base_url);
+        assert_eq!(settings.api.request_timeout_secs, 5);
+        assert_eq!(settings.device, DeviceSettings::default());
+        assert_eq!(settings.policy.sync_interval_secs, 60);
+        assert_eq!(settings.policy.audit_only, defaults.policy.audit_only);
+        assert_eq!(settings.network.enabled, defaults.network.enabled);
+        assert_eq!(settings.update.public_key, defaults.update.public_key);
+        assert_eq!(settings.update.manifest_url, None);
+        assert_eq!(settings.scripts.public_key, defaults.scripts.public_key);
+        assert_eq!(settings.remote_tasks.poll_interval_secs, 60);
+        assert_eq!(settings.remote_tasks.allowed, defaults.remote_tasks.allowed);
+    }
+}
@@ -0,0 +1,209 @@
+// ============================================================================
+// Agent State Module
+// ============================================================================
+// Everything the agent shares between commands and background tasks lives
+// in one AgentState, registered with Tauri as managed state:
+//
+//   app.manage(Arc::new(AgentState::new(settings, source, events)));
+//   fn my_command(state: tauri::State<'_, Arc<AgentState>>) { ... }
+//
+// Background tasks hold a clone of the same Arc.
+//
+// - settings:       settings.json, read once at startup (read-only)
+// - credentials:    monitoring token set by the frontend (RwLock)
+// - monitor:        policy, scanners, aggregator, outbox (monitor.rs);
+//                   tokio Mutex, held across API calls
+// - monitor_status: MonitorStatus published after every monitor step
+//                   (status.rs), so status reads never wait on the monitor
+// - exceptions:     approved exceptions and pending requests
+//                   (exceptions.rs), shared with the monitor's scanner
+// - device:         tags and org unit assigned by the backend
+//                   (heartbeat.rs), shared with the monitor
+// - usage:          current usage session (usage.rs)
+// - health:         per-task health of the supervisor (supervisor.rs)
+// - triggers:       run a supervised task now (supervisor.rs), used by
+//                   server push commands (push.rs)
+// - push_status:    push channel connection state (push.rs)
+// - finished_tasks: remote tasks already run (remote_tasks.rs)
+// - perf:           host sampler and rollups (perf.rs)
+// - thresholds:     threshold rules and their firing state (thresholds.rs)
+// - compliance:     last compliance report (compliance.rs)
+// - logging:        log level and files (logging.rs), attached by run()
+//
+// The backend (api.rs: base URL and request timeout) is built from
+// settings.api here and passed to the monitor and every backend call.
+//
+// Tests can build as many independent agents as they like (the on-disk
+// files still follow ITAM_AGENT_CONFIG_DIR). Only the Prometheus registry
+// (metrics::global(), which api::send() records into) is deliberately
+// process-wide and shared by every AgentState in the process; tests must
+// not assume exact values in it.
+// ============================================================================
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::info;
+
+use crate::api::Backend;
+use crate::compliance::ComplianceReport;
+use crate::error::AgentError;
+use crate::exceptions::Exceptions;
+use crate::logging::LogControl;
+use crate::monitor::{ForbiddenMonitor, MonitorEvents};
+use crate::perf::PerfCollector;
+use crate::process_source::ProcessSource;
+use crate::push::PushStatus;
+use crate::remote_tasks::FinishedTasks;
+use crate::settings::{AgentSettings, DeviceSettings};
+use crate::status::{AgentStatus, MonitorStatus};
+use crate::supervisor::{HealthRegistry, Supervisor, TaskTriggers};
+use crate::thresholds::ThresholdEngine;
+use crate::usage::UsageTracker;
+
+/// Monitoring token set by the frontend after login
+#[derive(Default)]
+pub struct Credentials {
+    token: RwLock<String>,
+}
+
+impl Credentials {
+    pub fn set(&self, token: String) {
+        *self.token.write().unwrap() = token;
+    }
+
+    /// Current monitoring token, or None while the user is not logged in
+    pub fn token(&self) -> Option<String> {
+        let token = self.token.read().unwrap();
+        (!token.is_empty()).then(|| token.clone())
+    }
+
+    /// Current monitoring token, or an error if the user is not logged in
+    pub fn require(&self) -> Result<String, AgentError> {
+        self.token()
+            .ok_or_else(|| AgentError::Unauthorized("monitoring token not set".to_string()))
+    }
+
+    pub fn is_set(&self) -> bool {
+        !self.token.read().unwrap().is_empty()
+    }
+
+    /// Forget the token, as if the user logged out
+    pub fn clear(&self) {
+        self.token.write().unwrap().clear();
+    }
+}
+
+pub struct AgentState {
+    pub settings: AgentSettings,
+    backend: Backend,
+    pub credentials: Credentials,
+    pub monitor: tokio::sync::Mutex<ForbiddenMonitor>,
+    pub monitor_status: Arc<Mutex<MonitorStatus>>,
+    pub exceptions: Exceptions,
+    pub device: Arc<RwLock<DeviceSettings>>,
+    pub usage: Mutex<UsageTracker>,
+    pub health: HealthRegistry,
+    pub triggers: TaskTriggers,
+    pub push_status: Arc<Mutex<PushStatus>>,
+    pub finished_tasks: FinishedTasks,
+    pub perf: Mutex<PerfCollector>,
+    pub thresholds: Mutex<ThresholdEngine>,
+    pub compliance: Mutex<Option<ComplianceReport>>,
+    /// Taken by the first shutdown
+    supervisor: Mutex<Option<Supervisor>>,
+    logging: Mutex<Option<LogControl>>,
+    shutdown_done: AtomicBool,
+}
+
+impl AgentState {
+    pub fn new(
+        settings: AgentSettings,
+        process_source: Box<dyn ProcessSource + Send>,
+        events: Box<dyn MonitorEvents>,
+    ) -> Self {
+        let backend = Backend::new(&settings.api);
+        let monitor = ForbiddenMonitor::new(&backend, settings.clone(), process_source, events);
+        Self {
+            backend,
+            monitor_status: monitor.status_handle(),
+            exceptions: monitor.exceptions(),
+            device: monitor.device_handle(),
+            monitor: tokio::sync::Mutex::new(monitor),
+            perf: Mutex::new(PerfCollector::new(&settings.perf)),
+            settings,
+            credentials: Credentials::default(),
+            usage: Mutex::new(UsageTracker::new()),
+            health: HealthRegistry::default(),
+            triggers: TaskTriggers::default(),
+            push_status: Arc::new(Mutex::new(PushStatus::default())),
+            finished_tasks: FinishedTasks::load(),
+            thresholds: Mutex::new(ThresholdEngine::default()),
+            compliance: Mutex::new(None),
+            supervisor: Mutex::new(None),
+            logging: Mutex::new(None),
+            shutdown_done: AtomicBool::new(false),
+        }
+    }
+
+    /// Backend base URL and HTTP clients
+    pub fn backend(&self) -> &Backend {
+        &self.backend
+    }
+
+    /// Device tags and org unit as last assigned by the backend
+    pub fn device(&self) -> DeviceSettings {
+        self.device.read().unwrap().clone()
+    }
+
+    /// Apply the tags and org unit from a heartbeat response
+    pub fn set_device(&self, device: DeviceSettings) {
+        let mut current = self.device.write().unwrap();
+        if *current != device {
+            info!(tags = ?device.tags, org_unit = ?device.org_unit, "Device assignment changed");
+            *current = device;
+        }
+    }
+
+    /// Structured status (status.rs); never waits on the monitor
+    pub fn status(&self) -> AgentStatus {
+        let monitor = self.monitor_status.lock().unwrap().clone();
+        let push = self.push_status.lock().unwrap().clone();
+        AgentStatus::build(self.credentials.is_set(), monitor, push, self.health.snapshot())
+    }
+
+    /// Keep the supervisor running the background tasks until shutdown
+    pub fn attach_supervisor(&self, supervisor: Supervisor) {
+        *self.supervisor.lock().unwrap() = Some(supervisor);
+    }
+
+    /// The supervisor, once: later callers are already shutting down
+    pub fn take_supervisor(&self) -> Option<Supervisor> {
+        self.supervisor.lock().unwrap().take()
+    }
+
+    pub fn attach_logging(&self, logging: LogControl) {
+        *self.logging.lock().unwrap() = Some(logging);
+    }
+
+    /// Log control, or an error when logging was never set up (tests)
+    pub fn logging(&self) -> Result<LogControl, AgentError> {
+        self.logging
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| AgentError::Io("logging is not initialised".to_string()))
+    }
+
+    /// Background tasks are running: not shutting down or shut down
+    pub fn is_running(&self) -> bool {
+        self.supervisor.lock().unwrap().is_some()
+    }
+
+    pub fn mark_shutdown_done(&self) {
+        self.shutdown_done.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutdown_done(&self) -> bool {
+        self.shutdown_done.load(Ordering::SeqCst)
+    }
+}
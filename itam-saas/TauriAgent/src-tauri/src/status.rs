@@ -0,0 +1,206 @@
+// ============================================================================
+// Agent Status Module
+// ============================================================================
+// One structured answer to "is this agent actually working?", built from:
+// - the monitoring token (logged in or not)
+// - MonitorStatus, published by ForbiddenMonitor after every step
+//   (auth rejections, last sync/upload, policy version and age, outbox
+//   depth, active violations)
+// - the supervisor's health registry (per-task runs, failures, errors;
+//   the "heartbeat" task's last success is the last heartbeat)
+// - PushStatus of the server push channel (push.rs)
+//
+// Exposed as JSON through:
+// - get_agent_status command
+// - agent-status event, every STATUS_PERIOD (lib.rs)
+// - the heartbeat payload (heartbeat.rs)
+//
+// overall:
+// - logged_out:   no monitoring token, nothing is synced or reported
+// - auth_expired: the backend rejected the token (401) since the last
+//                 successful sync
+// - degraded:     stale policy, or a task failing / in backoff
+//                 (a disconnected push channel is not: polling covers it)
+// - healthy:      everything else
+// ============================================================================
+
+use serde::Serialize;
+
+use crate::api::unix_now;
+use crate::forbidden::get_device_id;
+use crate::push::PushStatus;
+use crate::supervisor::TaskHealth;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverallStatus {
+    Healthy,
+    Degraded,
+    AuthExpired,
+    LoggedOut,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthState {
+    LoggedOut,
+    Authenticated,
+    Expired,
+}
+
+/// Forbidden list the agent enforces right now
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PolicyStatus {
+    /// ETag of the cached forbidden list (None before the first sync)
+    pub version: Option<String>,
+    pub rules: usize,
+    pub network_rules: usize,
+    /// Last time the server confirmed the list (seconds since UNIX epoch)
+    pub last_updated: Option<u64>,
+    pub age_secs: Option<u64>,
+    pub stale: bool,
+}
+
+/// Monitoring state, published by ForbiddenMonitor after every step
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MonitorStatus {
+    /// The backend answered 401 and no sync has succeeded since
+    pub auth_rejected: bool,
+    pub last_sync: Option<u64>,
+    /// Last time queued telemetry or an alert was delivered
+    pub last_upload: Option<u64>,
+    pub policy: PolicyStatus,
+    pub outbox_depth: usize,
+    pub active_violations: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentStatus {
+    pub overall: OverallStatus,
+    /// Human-readable one-liner for the tray and dashboard
+    pub summary: String,
+    pub auth: AuthState,
+    pub device_id: String,
+    pub agent_version: String,
+    pub timestamp: u64,
+    pub last_sync: Option<u64>,
+    pub last_heartbeat: Option<u64>,
+    pub last_upload: Option<u64>,
+    pub policy: PolicyStatus,
+    pub outbox_depth: usize,
+    pub active_violations: usize,
+    pub push: PushStatus,
+    pub tasks: Vec<TaskHealth>,
+    /// "<task>: <last error>" for every task that is currently failing
+    pub errors: Vec<String>,
+}
+
+impl AgentStatus {
+    pub fn build(logged_in: bool, monitor: MonitorStatus, push: PushStatus, tasks: Vec<TaskHealth>) -> Self {
+        let auth = match (logged_in, monitor.auth_rejected) {
+            (false, _) => AuthState::LoggedOut,
+            (true, true) => AuthState::Expired,
+            (true, false) => AuthState::Authenticated,
+        };
+
+        let failing: Vec<&TaskHealth> = tasks.iter().filter(|t| t.consecutive_failures > 0).collect();
+        let errors: Vec<String> = failing
+            .iter()
+            .map(|t| format!("{}: {}", t.name, t.last_error.as_deref().unwrap_or("unknown error")))
+            .collect();
+
+        let (overall, summary) = match auth {
+            AuthState::LoggedOut => (OverallStatus::LoggedOut, "Not logged in".to_string()),
+            AuthState::Expired => (OverallStatus::AuthExpired, "Session expired - log in again".to_string()),
+            AuthState::Authenticated if !failing.is_empty() => {
+                let names: Vec<&str> = failing.iter().map(|t| t.name.as_str()).collect();
+                (OverallStatus::Degraded, format!("Degraded: {} failing", names.join(", ")))
+            }
+            AuthState::Authenticated if monitor.policy.stale => {
+                (OverallStatus::Degraded, "Degraded: policy out of date".to_string())
+            }
+            AuthState::Authenticated => (OverallStatus::Healthy, "Monitoring active".to_string()),
+        };
+
+        let last_heartbeat = tasks
+            .iter()
+            .find(|t| t.name == "heartbeat")
+            .and_then(|t| t.last_success);
+
+        Self {
+            overall,
+            summary,
+            auth,
+            device_id: get_device_id(),
+            agent_version: env!("CARGO_PKG_VERSION").to_string(),
+            timestamp: unix_now(),
+            last_sync: monitor.last_sync,
+            last_heartbeat,
+            last_upload: monitor.last_upload,
+            policy: monitor.policy,
+            outbox_depth: monitor.outbox_depth,
+            active_violations: monitor.active_violations,
+            push,
+            tasks,
+            errors,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::supervisor::TaskState;
+
+    fn task(name: &str, consecutive_failures: u32, last_error: Option<&str>) -> TaskHealth {
+        TaskHealth {
+            name: name.to_string(),
+            state: if consecutive_failures > 0 { TaskState::Backoff } else { TaskState::Idle },
+            period_secs: 60,
+            runs: 3,
+            consecutive_failures,
+            panics: 0,
+            last_success: Some(1_700_000_000),
+            last_error: last_error.map(str::to_string),
+            last_error_kind: last_error.map(|_| "network".to_string()),
+            last_error_at: None,
+        }
+    }
+
+    #[test]
+    fn healthy_when_logged_in_and_nothing_fails() {
+        let status = AgentStatus::build(
+            true,
+            MonitorStatus::default(),
+            PushStatus::default(),
+            vec![task("heartbeat", 0, None)],
+        );
+        assert_eq!(status.overall, OverallStatus::Healthy);
+        assert_eq!(status.auth, AuthState::Authenticated);
+        assert_eq!(status.last_heartbeat, Some(1_700_000_000));
+        assert!(status.errors.is_empty());
+    }
+
+    #[test]
+    fn failing_task_degrades_and_is_listed() {
+        let tasks = vec![
+            task("heartbeat", 0, None),
+            task("policy-sync", 2, Some("Network error: connection refused")),
+        ];
+        let status = AgentStatus::build(true, MonitorStatus::default(), PushStatus::default(), tasks);
+        assert_eq!(status.overall, OverallStatus::Degraded);
+        assert_eq!(status.summary, "Degraded: policy-sync failing");
+        assert_eq!(status.errors, vec!["policy-sync: Network error: connection refused"]);
+    }
+
+    #[test]
+    fn auth_state_wins_over_task_failures() {
+        let monitor = MonitorStatus {
+            auth_rejected: true,
+            ..MonitorStatus::default()
+        };
+        let tasks = vec![task("policy-sync", 1, Some("API error: 401 Unauthorized"))];
+        assert_eq!(AgentStatus::build(true, monitor.clone(), PushStatus::default(), tasks.clone()).overall, OverallStatus::AuthExpired);
+        assert_eq!(AgentStatus::build(false, monitor, PushStatus::default(), tasks).auth, AuthState::LoggedOut);
+    }
+}
@@ -0,0 +1,420 @@
+// ============================================================================
+// Task Supervisor Module
+// ============================================================================
+// All background work (usage tracking, heartbeats, policy sync, process
+// scanning, outbox uploads) runs as periodic tasks on one Tokio runtime,
+// Tauri's own, instead of std::threads that build a runtime per call.
+//
+//   supervisor.spawn_periodic("heartbeat", Duration::from_secs(120), move || {
+//       let state = state.clone();
+//       async move { send_heartbeat(&state).await }
+//   });
+//
+// Each run of a task is spawned separately, so:
+// - Ok(())      -> recorded as success, next run after `period`
+// - Err(error)  -> recorded as failure; the AgentError (error.rs) picks
+//                  the retry delay:
+//                  retryable      exponential backoff (5s, 10s, 20s ...
+//                                 capped at `period`)
+//                  rate limited   the server's Retry-After, if longer
+//                  anything else  `period` (retrying sooner cannot help,
+//                                 e.g. 401 waits for a new login)
+// - panic       -> caught (the runtime and the other tasks keep going),
+//                  recorded with its message, task restarted after backoff
+//
+// The health registry keeps one TaskHealth per task (state, runs, failures,
+// panics, last success, last error). It is what the UI and the status
+// report read to tell "running" from "silently dead".
+//
+// TaskTriggers.trigger(name) starts the next run of a task right away
+// instead of after its delay (push.rs uses it for server requests); a
+// trigger during a run queues exactly one more run.
+//
+// Task bodies take shared std mutexes through lock(), which ignores
+// poisoning: a run that panicked while holding one must not turn every
+// later run into another panic.
+//
+// shutdown() cancels every task through a shared CancellationToken: a task
+// sleeping between runs stops immediately, a running one finishes its
+// current run first.
+// ============================================================================
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{error, Instrument};
+use tokio_util::sync::CancellationToken;
+
+use crate::api::unix_now;
+use crate::error::AgentError;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    /// Executing a run right now
+    Running,
+    /// Last run succeeded, waiting for the next one
+    Idle,
+    /// Last run failed or panicked, waiting to retry
+    Backoff,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskHealth {
+    pub name: String,
+    pub state: TaskState,
+    pub period_secs: u64,
+    pub runs: u64,
+    /// Failed or panicked runs since the last success
+    pub consecutive_failures: u32,
+    /// Runs that panicked, over the lifetime of the agent
+    pub panics: u32,
+    /// Seconds since UNIX epoch
+    pub last_success: Option<u64>,
+    pub last_error: Option<String>,
+    /// AgentError kind of the last error, or "panic"
+    pub last_error_kind: Option<String>,
+    pub last_error_at: Option<u64>,
+}
+
+/// Shared view of every supervised task, keyed by task name
+#[derive(Clone, Default)]
+pub struct HealthRegistry {
+    tasks: Arc<Mutex<BTreeMap<String, TaskHealth>>>,
+}
+
+impl HealthRegistry {
+    pub fn snapshot(&self) -> Vec<TaskHealth> {
+        self.tasks.lock().unwrap().values().cloned().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<TaskHealth> {
+        self.tasks.lock().unwrap().get(name).cloned()
+    }
+
+    fn register(&self, name: &str, period: Duration) {
+        self.tasks.lock().unwrap().insert(
+            name.to_string(),
+            TaskHealth {
+                name: name.to_string(),
+                state: TaskState::Idle,
+                period_secs: period.as_secs(),
+                runs: 0,
+                consecutive_failures: 0,
+                panics: 0,
+                last_success: None,
+                last_error: None,
+                last_error_kind: None,
+                last_error_at: None,
+            },
+        );
+    }
+
+    fn update(&self, name: &str, f: impl FnOnce(&mut TaskHealth)) {
+        if let Some(health) = self.tasks.lock().unwrap().get_mut(name) {
+            f(health);
+        }
+    }
+}
+
+/// Wake-ups for supervised tasks, keyed by task name
+#[derive(Clone, Default)]
+pub struct TaskTriggers {
+    wakers: Arc<Mutex<BTreeMap<String, Arc<Notify>>>>,
+}
+
+impl TaskTriggers {
+    /// Run `name` now; false if no such task is supervised
+    pub fn trigger(&self, name: &str) -> bool {
+        match self.wakers.lock().unwrap().get(name) {
+            Some(waker) => {
+                waker.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn register(&self, name: &str) -> Arc<Notify> {
+        self.wakers
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+}
+
+pub struct Supervisor {
+    runtime: Handle,
+    cancel: CancellationToken,
+    health: HealthRegistry,
+    triggers: TaskTriggers,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+/// Lock a mutex shared with task bodies, recovering it if a panicked run
+/// poisoned it
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Text of a caught panic payload
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+pub(crate) fn backoff(consecutive_failures: u32, period: Duration) -> Duration {
+    let exponent = consecutive_failures.saturating_sub(1).min(10);
+    (INITIAL_BACKOFF * 2u32.pow(exponent)).min(period.max(INITIAL_BACKOFF))
+}
+
+/// Delay before the next run after a failed one (None: the run panicked)
+fn retry_delay(error: Option<&AgentError>, consecutive_failures: u32, period: Duration) -> Duration {
+    let backoff = backoff(consecutive_failures, period);
+    match error {
+        None => backoff,
+        Some(e) if e.is_retryable() => e.retry_after().map_or(backoff, |wait| wait.max(backoff)),
+        Some(_) => period,
+    }
+}
+
+impl Supervisor {
+    /// Supervise tasks on `runtime` (Tauri's runtime in the app)
+    pub fn new(runtime: Handle) -> Self {
+        Self::with_health(runtime, HealthRegistry::default())
+    }
+
+    /// Supervise tasks, recording their health in an existing registry
+    pub fn with_health(runtime: Handle, health: HealthRegistry) -> Self {
+        Self {
+            runtime,
+            cancel: CancellationToken::new(),
+            health,
+            triggers: TaskTriggers::default(),
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Share wake-ups with an existing TaskTriggers
+    pub fn with_triggers(mut self, triggers: TaskTriggers) -> Self {
+        self.triggers = triggers;
+        self
+    }
+
+    pub fn health(&self) -> HealthRegistry {
+        self.health.clone()
+    }
+
+    pub fn triggers(&self) -> TaskTriggers {
+        self.triggers.clone()
+    }
+
+    /// Token that is cancelled when the supervisor shuts down
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Run `job` now and then every `period` until shutdown
+    pub fn spawn_periodic<F, Fut>(&mut self, name: &str, period: Duration, job: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), AgentError>> + Send + 'static,
+    {
+        let name = name.to_string();
+        let health = self.health.clone();
+        let cancel = self.cancel.clone();
+        let runtime = self.runtime.clone();
+        health.register(&name, period);
+        let wake = self.triggers.register(&name);
+
+        // Every line a run logs carries the task name (logging.rs)
+        let span = tracing::info_span!("task", task = %name);
+        let loop_span = span.clone();
+
+        let task = self.runtime.spawn(async move {
+            let mut delay = Duration::ZERO;
+            loop {
+                tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = wake.notified() => {}
+                }
+
+                health.update(&name, |h| h.state = TaskState::Running);
+                let outcome = runtime.spawn(job().instrument(span.clone())).await;
+                let now = unix_now();
+
+                // (message, kind, the error itself unless the run panicked)
+                let failure = match outcome {
+                    Ok(Ok(())) => None,
+                    Ok(Err(e)) => Some((e.to_string(), e.kind(), Some(e))),
+                    // The run was aborted because the runtime is shutting down:
+                    // neither a failure nor a panic
+                    Err(join_error) if join_error.is_cancelled() => break,
+                    Err(join_error) => {
+                        let message = format!("panicked: {}", panic_message(join_error.into_panic()));
+                        health.update(&name, |h| h.panics += 1);
+                        Some((message, "panic", None))
+                    }
+                };
+
+                let mut failures = 0;
+                health.update(&name, |h| {
+                    h.runs += 1;
+                    match &failure {
+                        None => {
+                            h.state = TaskState::Idle;
+                            h.consecutive_failures = 0;
+                            h.last_success = Some(now);
+                        }
+                        Some((message, kind, _)) => {
+                            h.state = TaskState::Backoff;
+                            h.consecutive_failures += 1;
+                            h.last_error = Some(message.clone());
+                            h.last_error_kind = Some(kind.to_string());
+                            h.last_error_at = Some(now);
+                        }
+                    }
+                    failures = h.consecutive_failures;
+                });
+
+                delay = match failure {
+                    None => period,
+                    Some((message, kind, error)) => {
+                        error!(kind = kind, failures, "Task failed: {}", message);
+                        retry_delay(error.as_ref(), failures, period)
+                    }
+                };
+            }
+            health.update(&name, |h| h.state = TaskState::Stopped);
+        }.instrument(loop_span));
+        self.tasks.push(task);
+    }
+
+    /// Cancel every task and wait until they stopped
+    pub async fn shutdown(&mut self) {
+        self.cancel.cancel();
+        for task in self.tasks.drain(..) {
+            let _ = task.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn wait_for_runs(health: &HealthRegistry, name: &str, runs: u64) -> TaskHealth {
+        for _ in 0..100 {
+            if let Some(task) = health.get(name).filter(|t| t.runs >= runs) {
+                return task;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("task {} did not run", name);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_period() {
+        let period = Duration::from_secs(60);
+        assert_eq!(backoff(1, period), Duration::from_secs(5));
+        assert_eq!(backoff(2, period), Duration::from_secs(10));
+        assert_eq!(backoff(4, period), Duration::from_secs(40));
+        assert_eq!(backoff(5, period), period);
+        assert_eq!(backoff(u32::MAX, period), period);
+    }
+
+    #[test]
+    fn retry_delay_follows_error_kind() {
+        let period = Duration::from_secs(300);
+        let network = AgentError::Network("connection refused".into());
+        let limited = AgentError::RateLimited { retry_after_secs: Some(120) };
+        let unauthorized = AgentError::Unauthorized("token expired".into());
+
+        assert_eq!(retry_delay(Some(&network), 1, period), Duration::from_secs(5));
+        assert_eq!(retry_delay(Some(&limited), 1, period), Duration::from_secs(120));
+        assert_eq!(retry_delay(Some(&unauthorized), 1, period), period);
+        assert_eq!(retry_delay(None, 2, period), Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn panic_is_captured_and_recorded() {
+        let mut supervisor = Supervisor::new(Handle::current());
+        let health = supervisor.health();
+        supervisor.spawn_periodic("panicky", Duration::from_secs(60), || async {
+            panic!("boom");
+        });
+        supervisor.spawn_periodic("steady", Duration::from_secs(60), || async { Ok(()) });
+
+        let panicky = wait_for_runs(&health, "panicky", 1).await;
+        assert_eq!(panicky.state, TaskState::Backoff);
+        assert_eq!(panicky.panics, 1);
+        assert_eq!(panicky.consecutive_failures, 1);
+        assert_eq!(panicky.last_error.as_deref(), Some("panicked: boom"));
+
+        let steady = wait_for_runs(&health, "steady", 1).await;
+        assert_eq!(steady.state, TaskState::Idle);
+        assert!(steady.last_success.is_some());
+
+        supervisor.shutdown().await;
+        assert!(health.snapshot().iter().all(|t| t.state == TaskState::Stopped));
+    }
+
+    #[tokio::test]
+    async fn panic_holding_a_lock_does_not_fail_later_runs() {
+        let mut supervisor = Supervisor::new(Handle::current());
+        let health = supervisor.health();
+        let triggers = supervisor.triggers();
+        let shared = Arc::new(Mutex::new(0u32));
+        let counter = shared.clone();
+        supervisor.spawn_periodic("poisoner", Duration::from_secs(3600), move || {
+            let counter = counter.clone();
+            async move {
+                let mut runs = lock(&counter);
+                *runs += 1;
+                if *runs == 1 {
+                    panic!("boom");
+                }
+                Ok(())
+            }
+        });
+
+        assert_eq!(wait_for_runs(&health, "poisoner", 1).await.panics, 1);
+        assert!(shared.is_poisoned());
+        assert!(triggers.trigger("poisoner"));
+        let task = wait_for_runs(&health, "poisoner", 2).await;
+        assert_eq!(task.state, TaskState::Idle);
+        assert_eq!(task.panics, 1);
+        assert_eq!(*lock(&shared), 2);
+
+        supervisor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn trigger_runs_a_task_before_its_period() {
+        let mut supervisor = Supervisor::new(Handle::current());
+        let health = supervisor.health();
+        let triggers = supervisor.triggers();
+        supervisor.spawn_periodic("sync", Duration::from_secs(3600), || async { Ok(()) });
+
+        wait_for_runs(&health, "sync", 1).await;
+        assert!(triggers.trigger("sync"));
+        assert!(!triggers.trigger("missing"));
+        assert_eq!(wait_for_runs(&health, "sync", 2).await.runs, 2);
+
+        supervisor.shutdown().await;
+    }
+}
@@ -0,0 +1,482 @@
+// ============================================================================
+// Threshold Alerts Module
+// ============================================================================
+// Server-defined rules over the host metrics, evaluated on the device by
+// the "thresholds" task:
+//
+//   GET /api/agent/threshold-rules?device_id=<id>
+//   [ { "id": 3, "name": "Disk almost full", "metric": "disk_used_percent",
+//       "comparator": ">", "value": 90, "duration_secs": 0,
+//       "severity": "High" },
+//     { "id": 4, "name": "CPU pegged", "metric": "cpu_percent",
+//       "comparator": ">", "value": 95, "duration_secs": 600 } ]
+//
+// Rules are refetched every settings.thresholds.rules_refresh_secs; a
+// failed fetch keeps the last rules, a 404 (older backend) means none.
+// Invalid rules are skipped one by one.
+//
+// Metrics come from the latest perf sample (perf.rs) and the disks:
+//
+// | metric                   | unit    | metric                  | unit    |
+// |--------------------------|---------|-------------------------|---------|
+// | cpu_percent              | %       | disk_used_percent       | % (*)   |
+// | memory_used_percent      | %       | disk_free_bytes         | B (*)   |
+// | memory_total_bytes       | B       | disk_read_bytes_per_sec | B/s     |
+// | memory_available_bytes   | B       | disk_write_bytes_per_sec| B/s     |
+// | swap_used_percent        | %       | net_rx_bytes_per_sec    | B/s     |
+// | load_1                   |         | net_tx_bytes_per_sec    | B/s     |
+//
+// (*) the fullest fixed disk. Comparators: > >= < <= == (or gt, gte, lt,
+// lte, eq).
+//
+// A rule fires once the condition has held for duration_secs (0: on the
+// first breaching reading) and resolves on the first reading where it no
+// longer holds. A firing rule that is removed or edited on the server
+// resolves too. Both go through the outbox to POST /api/alerts with
+// alert_type "threshold" (older servers store them as ordinary alerts):
+//
+//   { "device_id": "...", "app_detected": "CPU pegged: cpu_percent > 95",
+//     "severity": "Medium", "alert_type": "threshold",
+//     "threshold": { "rule_id": "4", "state": "firing", "metric": ...,
+//                    "comparator": ">", "value": 95, "observed": 99.1,
+//                    "duration_secs": 600, "since": 1718000000 } }
+// ============================================================================
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+use sysinfo::Disks;
+use tracing::{info, warn};
+
+use crate::api::{self, unix_now, Backend};
+use crate::error::AgentError;
+use crate::forbidden::get_device_id;
+use crate::perf::PerfSample;
+use crate::remote_tasks::id_string;
+use crate::state::AgentState;
+use crate::supervisor::lock;
+
+pub const ALERT_TYPE: &str = "threshold";
+pub const RULES_PATH: &str = "/api/agent/threshold-rules";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    CpuPercent,
+    MemoryUsedPercent,
+    MemoryTotalBytes,
+    MemoryAvailableBytes,
+    SwapUsedPercent,
+    #[serde(rename = "load_1")]
+    Load1,
+    DiskUsedPercent,
+    DiskFreeBytes,
+    DiskReadBytesPerSec,
+    DiskWriteBytesPerSec,
+    NetRxBytesPerSec,
+    NetTxBytesPerSec,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Comparator {
+    #[serde(rename = ">", alias = "gt")]
+    Gt,
+    #[serde(rename = ">=", alias = "gte")]
+    Gte,
+    #[serde(rename = "<", alias = "lt")]
+    Lt,
+    #[serde(rename = "<=", alias = "lte")]
+    Lte,
+    #[serde(rename = "==", alias = "eq")]
+    Eq,
+}
+
+impl Comparator {
+    pub fn holds(self, observed: f64, value: f64) -> bool {
+        match self {
+            Comparator::Gt => observed > value,
+            Comparator::Gte => observed >= value,
+            Comparator::Lt => observed < value,
+            Comparator::Lte => observed <= value,
+            Comparator::Eq => observed == value,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Comparator::Gt => ">",
+            Comparator::Gte => ">=",
+            Comparator::Lt => "<",
+            Comparator::Lte => "<=",
+            Comparator::Eq => "==",
+        }
+    }
+}
+
+fn default_severity() -> String {
+    "Medium".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThresholdRule {
+    #[serde(deserialize_with = "id_string")]
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    pub metric: Metric,
+    pub comparator: Comparator,
+    pub value: f64,
+    #[serde(default)]
+    pub duration_secs: u64,
+    /// Low, Medium, High or Critical, as for forbidden apps
+    #[serde(default = "default_severity")]
+    pub severity: String,
+}
+
+impl ThresholdRule {
+    fn describe(&self) -> String {
+        let condition = format!(
+            "{} {} {}",
+            serde_json::to_value(self.metric).ok().and_then(|m| m.as_str().map(String::from)).unwrap_or_default(),
+            self.comparator.symbol(),
+            self.value
+        );
+        if self.name.is_empty() {
+            condition
+        } else {
+            format!("{}: {}", self.name, condition)
+        }
+    }
+}
+
+/// Parse the served rules, skipping the ones this agent does not understand
+pub fn parse_rules(served: Vec<Value>) -> Vec<ThresholdRule> {
+    served
+        .into_iter()
+        .filter_map(|rule| match serde_json::from_value::<ThresholdRule>(rule.clone()) {
+            Ok(rule) => Some(rule),
+            Err(e) => {
+                warn!(rule = %rule, "Skipping threshold rule: {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Current metric values; a metric without a reading is never evaluated
+#[derive(Debug, Clone, Default)]
+pub struct Readings {
+    values: BTreeMap<Metric, f64>,
+}
+
+impl Readings {
+    pub fn set(&mut self, metric: Metric, value: f64) {
+        self.values.insert(metric, value);
+    }
+
+    pub fn get(&self, metric: Metric) -> Option<f64> {
+        self.values.get(&metric).copied()
+    }
+
+    /// From a perf sample and the fixed disks
+    pub fn collect(sample: Option<&PerfSample>, disks: &Disks) -> Self {
+        let mut readings = Readings::default();
+        let percent = |used: u64, total: u64| (total > 0).then(|| used as f64 * 100.0 / total as f64);
+        if let Some(sample) = sample {
+            readings.set(Metric::CpuPercent, sample.cpu_percent);
+            if let Some(used) = percent(sample.memory_used_bytes, sample.memory_total_bytes) {
+                readings.set(Metric::MemoryUsedPercent, used);
+            }
+            readings.set(Metric::MemoryTotalBytes, sample.memory_total_bytes as f64);
+            readings.set(
+                Metric::MemoryAvailableBytes,
+                sample.memory_total_bytes.saturating_sub(sample.memory_used_bytes) as f64,
+            );
+            if let Some(used) = percent(sample.swap_used_bytes, sample.swap_total_bytes) {
+                readings.set(Metric::SwapUsedPercent, used);
+            }
+            readings.set(Metric::Load1, sample.load_1);
+            readings.set(Metric::DiskReadBytesPerSec, sample.disk_read_bytes_per_sec);
+            readings.set(Metric::DiskWriteBytesPerSec, sample.disk_write_bytes_per_sec);
+            readings.set(Metric::NetRxBytesPerSec, sample.net_rx_bytes_per_sec);
+            readings.set(Metric::NetTxBytesPerSec, sample.net_tx_bytes_per_sec);
+        }
+
+        let fixed = disks.iter().filter(|d| !d.is_removable() && d.total_space() > 0);
+        let fullest = fixed.max_by(|a, b| {
+            let used = |d: &sysinfo::Disk| 1.0 - d.available_space() as f64 / d.total_space() as f64;
+            used(a).total_cmp(&used(b))
+        });
+        if let Some(disk) = fullest {
+            let used = disk.total_space().saturating_sub(disk.available_space());
+            readings.set(Metric::DiskUsedPercent, percent(used, disk.total_space()).unwrap_or_default());
+            readings.set(Metric::DiskFreeBytes, disk.available_space() as f64);
+        }
+        readings
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    Firing,
+    Resolved,
+}
+
+/// A rule that started or stopped firing
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThresholdEvent {
+    pub rule: ThresholdRule,
+    pub state: AlertState,
+    /// None when the rule was removed or edited on the server
+    pub observed: Option<f64>,
+    /// When the condition started to hold, seconds since UNIX epoch
+    pub since: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ThresholdDetails {
+    pub rule_id: String,
+    pub state: AlertState,
+    pub metric: Metric,
+    pub comparator: Comparator,
+    pub value: f64,
+    pub observed: Option<f64>,
+    pub duration_secs: u64,
+    pub since: u64,
+}
+
+/// Payload for POST /api/alerts
+#[derive(Debug, Clone, Serialize)]
+pub struct ThresholdAlert {
+    pub device_id: String,
+    pub app_detected: String,
+    pub severity: String,
+    pub alert_type: &'static str,
+    pub threshold: ThresholdDetails,
+}
+
+impl ThresholdEvent {
+    pub fn to_alert(&self, device_id: &str) -> ThresholdAlert {
+        let description = self.rule.describe();
+        ThresholdAlert {
+            device_id: device_id.to_string(),
+            app_detected: match self.state {
+                AlertState::Firing => description,
+                AlertState::Resolved => format!("Resolved - {}", description),
+            },
+            severity: self.rule.severity.clone(),
+            alert_type: ALERT_TYPE,
+            threshold: ThresholdDetails {
+                rule_id: self.rule.id.clone(),
+                state: self.state,
+                metric: self.rule.metric,
+                comparator: self.rule.comparator,
+                value: self.rule.value,
+                observed: self.observed,
+                duration_secs: self.rule.duration_secs,
+                since: self.since,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct RuleState {
+    breach_since: Option<u64>,
+    firing: bool,
+}
+
+/// Rules and how long each has been breached
+#[derive(Debug, Default)]
+pub struct ThresholdEngine {
+    rules: Vec<ThresholdRule>,
+    states: HashMap<String, RuleState>,
+    fetched_at: Option<Instant>,
+}
+
+impl ThresholdEngine {
+    pub fn rules(&self) -> &[ThresholdRule] {
+        &self.rules
+    }
+
+    pub fn rules_due(&self, refresh: Duration) -> bool {
+        !matches!(self.fetched_at, Some(at) if at.elapsed() < refresh)
+    }
+
+    /// Replace the rules; firing rules that were removed or edited resolve
+    pub fn set_rules(&mut self, rules: Vec<ThresholdRule>, now: u64) -> Vec<ThresholdEvent> {
+        self.fetched_at = Some(Instant::now());
+        let mut events = Vec::new();
+        for old in &self.rules {
+            if rules.contains(old) {
+                continue;
+            }
+            if let Some(state) = self.states.remove(&old.id) {
+                if state.firing {
+                    events.push(ThresholdEvent {
+                        rule: old.clone(),
+                        state: AlertState::Resolved,
+                        observed: None,
+                        since: state.breach_since.unwrap_or(now),
+                    });
+                }
+            }
+        }
+        self.rules = rules;
+        events
+    }
+
+    /// Compare every rule with the readings
+    pub fn evaluate(&mut self, readings: &Readings, now: u64) -> Vec<ThresholdEvent> {
+        let mut events = Vec::new();
+        for rule in &self.rules {
+            let Some(observed) = readings.get(rule.metric) else {
+                continue;
+            };
+            let state = self.states.entry(rule.id.clone()).or_default();
+            if rule.comparator.holds(observed, rule.value) {
+                let since = *state.breach_since.get_or_insert(now);
+                if !state.firing && now.saturating_sub(since) >= rule.duration_secs {
+                    state.firing = true;
+                    events.push(ThresholdEvent {
+                        rule: rule.clone(),
+                        state: AlertState::Firing,
+                        observed: Some(observed),
+                        since,
+                    });
+                }
+            } else if let Some(since) = state.breach_since.take() {
+                if state.firing {
+                    state.firing = false;
+                    events.push(ThresholdEvent {
+                        rule: rule.clone(),
+                        state: AlertState::Resolved,
+                        observed: Some(observed),
+                        since,
+                    });
+                }
+            }
+        }
+        events
+    }
+
+    /// Rules firing right now
+    pub fn firing(&self) -> usize {
+        self.states.values().filter(|s| s.firing).count()
+    }
+}
+
+/// GET the rules; an endpoint the backend does not have means no rules
+pub async fn fetch_rules(backend: &Backend, token: &str) -> Result<Vec<ThresholdRule>, AgentError> {
+    let url = format!("{}?device_id={}", backend.url(RULES_PATH), get_device_id());
+    let response = api::send(backend.client().get(&url).header("Authorization", format!("Bearer {}", token))).await?;
+    let response = match api::ensure_success(response).await {
+        Ok(response) => response,
+        Err(AgentError::Server { status: 404, .. }) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let served: Vec<Value> = response.json().await.map_err(|e| AgentError::Parse(e.to_string()))?;
+    Ok(parse_rules(served))
+}
+
+/// Refresh the rules when due, evaluate them and send what changed
+pub async fn check(state: &AgentState, token: &str) -> Result<Vec<ThresholdEvent>, AgentError> {
+    let settings = &state.settings.thresholds;
+    let now = unix_now();
+    let mut events = Vec::new();
+
+    let due = lock(&state.thresholds).rules_due(Duration::from_secs(settings.rules_refresh_secs));
+    if due {
+        match fetch_rules(state.backend(), token).await {
+            Ok(rules) => events.extend(lock(&state.thresholds).set_rules(rules, now)),
+            Err(e) => warn!("Failed to fetch threshold rules, keeping the last ones: {}", e),
+        }
+    }
+
+    let readings = {
+        let perf = lock(&state.perf);
+        Readings::collect(perf.latest(), &Disks::new_with_refreshed_list())
+    };
+    events.extend(lock(&state.thresholds).evaluate(&readings, now));
+    if events.is_empty() {
+        return Ok(events);
+    }
+
+    let device_id = get_device_id();
+    let mut monitor = state.monitor.lock().await;
+    for event in &events {
+        info!(rule = %event.rule.id, state = ?event.state, observed = ?event.observed, "Threshold alert");
+        monitor.enqueue("/api/alerts", serde_json::to_value(event.to_alert(&device_id))?);
+    }
+    monitor.flush_outbox(token).await?;
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rules(served: Value) -> Vec<ThresholdRule> {
+        parse_rules(serde_json::from_value(served).unwrap())
+    }
+
+    fn cpu(value: f64) -> Readings {
+        let mut readings = Readings::default();
+        readings.set(Metric::CpuPercent, value);
+        readings
+    }
+
+    #[test]
+    fn invalid_rules_are_skipped() {
+        let parsed = rules(json!([
+            { "id": 1, "metric": "disk_used_percent", "comparator": "gt", "value": 90 },
+            { "id": 2, "metric": "gpu_temperature", "comparator": ">", "value": 80 },
+            { "id": "3", "metric": "memory_total_bytes", "comparator": "<", "value": 8e9, "severity": "Low" },
+        ]));
+        assert_eq!(parsed.len(), 2);
+        assert_eq!((parsed[0].comparator, parsed[0].severity.as_str()), (Comparator::Gt, "Medium"));
+        assert_eq!((parsed[1].id.as_str(), parsed[1].metric), ("3", Metric::MemoryTotalBytes));
+    }
+
+    #[test]
+    fn rule_fires_after_duration_and_resolves_once() {
+        let mut engine = ThresholdEngine::default();
+        let set = engine.set_rules(
+            rules(json!([{ "id": 4, "name": "CPU pegged", "metric": "cpu_percent", "comparator": ">", "value": 95, "duration_secs": 600 }])),
+            0,
+        );
+        assert!(set.is_empty());
+
+        assert!(engine.evaluate(&cpu(99.0), 1_000).is_empty());
+        // A dip restarts the clock
+        assert!(engine.evaluate(&cpu(50.0), 1_300).is_empty());
+        assert!(engine.evaluate(&cpu(99.0), 1_400).is_empty());
+        assert!(engine.evaluate(&cpu(99.0), 1_900).is_empty());
+        assert!(engine.evaluate(&Readings::default(), 1_950).is_empty());
+
+        let fired = engine.evaluate(&cpu(97.5), 2_000);
+        assert_eq!(fired.len(), 1);
+        assert_eq!((fired[0].state, fired[0].observed, fired[0].since), (AlertState::Firing, Some(97.5), 1_400));
+        assert!(engine.evaluate(&cpu(99.0), 2_060).is_empty());
+        assert_eq!(engine.firing(), 1);
+
+        let resolved = engine.evaluate(&cpu(20.0), 2_120);
+        assert_eq!(resolved[0].state, AlertState::Resolved);
+        let alert = resolved[0].to_alert("laptop");
+        assert_eq!(alert.app_detected, "Resolved - CPU pegged: cpu_percent > 95");
+        assert!(engine.evaluate(&cpu(20.0), 2_180).is_empty());
+
+        // A firing rule edited on the server resolves
+        engine.evaluate(&cpu(99.0), 3_000);
+        engine.evaluate(&cpu(99.0), 3_600);
+        let edited = engine.set_rules(
+            rules(json!([{ "id": 4, "metric": "cpu_percent", "comparator": ">", "value": 99, "duration_secs": 600 }])),
+            3_700,
+        );
+        assert_eq!((edited[0].state, edited[0].observed), (AlertState::Resolved, None));
+        assert_eq!(engine.firing(), 0);
+    }
+}
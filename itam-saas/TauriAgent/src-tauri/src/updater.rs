@@ -0,0 +1,471 @@
+// ============================================================================
+// Self-Update Module
+// ============================================================================
+// The "update" task (lib.rs) replaces the agent executable with a newer
+// signed release and restarts; the next start decides whether to keep it.
+//
+// Release manifest (GET <api>/api/agent/releases/{channel}; a build with
+// ITAM_AGENT_UPDATE_URL set uses that instead, e.g. a local update server):
+// { "version": "0.2.0", "notes": "...", "pub_date": "2026-01-01T00:00:00Z",
+//   "platforms": { "windows-x86_64": { "url": "https://.../tauriagent.exe",
+//                  "sha256": "<hex>", "signature": "<base64>" } } }
+//
+// Channels: "stable" ignores pre-release versions, "beta" takes them too.
+// Only versions newer than CARGO_PKG_VERSION are installed, never the
+// version that was last rolled back (it would crash and roll back again),
+// and never an artifact larger than MAX_ARTIFACT_BYTES.
+//
+// Signature: ed25519 over
+//   tauriagent-update:<version>:<platform>:<sha256 hex>
+// so a signed old build cannot be replayed as a new version. The key is
+// built in from ITAM_AGENT_UPDATE_PUBKEY (tests set
+// settings.update.public_key, which settings.json cannot); without a key
+// nothing is installed.
+//
+// Install (atomic renames in the executable's directory):
+//   tauriagent.new  <- download, fsync
+//   tauriagent      -> tauriagent.bak   (rollback copy)
+//   tauriagent.new  -> tauriagent
+// A running executable can be renamed on every OS, just not overwritten.
+//
+// Health check after the restart (update_state.json in the config dir):
+// - every start of the new version counts a boot; more than
+//   MAX_UNCONFIRMED_BOOTS without confirmation (crash loop) rolls back
+// - after settings.update.health_check_secs the agent status must show no
+//   panicked task and, when logged in, a successful sync or heartbeat;
+//   then the backup is deleted, otherwise the backup is moved back and the
+//   agent restarts on the previous version
+// ============================================================================
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::api::{self, unix_now, Backend};
+use crate::error::AgentError;
+use crate::settings::{agent_config_dir, UpdateSettings};
+use crate::status::{AgentStatus, AuthState};
+
+/// Release manifest URL, embedded at build time
+const BUILT_IN_MANIFEST_URL: Option<&str> = option_env!("ITAM_AGENT_UPDATE_URL");
+/// Release signing key, embedded at build time
+const BUILT_IN_PUBLIC_KEY: Option<&str> = option_env!("ITAM_AGENT_UPDATE_PUBKEY");
+/// Starts of an unconfirmed version before it is rolled back
+pub const MAX_UNCONFIRMED_BOOTS: u32 = 3;
+/// Largest release artifact that is downloaded
+pub const MAX_ARTIFACT_BYTES: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseManifest {
+    pub version: String,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub pub_date: Option<String>,
+    /// Keyed by platform(), e.g. "windows-x86_64"
+    pub platforms: BTreeMap<String, ReleaseArtifact>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseArtifact {
+    pub url: String,
+    pub sha256: String,
+    /// Base64 ed25519 signature over signed_message()
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdatePhase {
+    PendingHealthCheck,
+    Confirmed,
+    RolledBack,
+}
+
+/// Last installed update, persisted across the restart
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateState {
+    pub from_version: String,
+    pub to_version: String,
+    pub target: PathBuf,
+    pub backup: PathBuf,
+    pub installed_at: u64,
+    /// Starts of to_version so far
+    pub boots: u32,
+    pub phase: UpdatePhase,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum UpdateOutcome {
+    UpToDate { version: String },
+    /// The last update has not passed its health check yet
+    AwaitingHealthCheck { version: String },
+    /// The manifest still offers the version that was rolled back
+    RolledBack { version: String },
+    Installed { from_version: String, to_version: String },
+}
+
+fn current_version() -> Version {
+    Version::parse(env!("CARGO_PKG_VERSION")).expect("CARGO_PKG_VERSION is semver")
+}
+
+/// "<os>-<arch>", the manifest's platform key
+pub fn platform() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// What the release key signs for one artifact
+pub fn signed_message(version: &str, platform: &str, sha256: &str) -> String {
+    format!("tauriagent-update:{}:{}:{}", version, platform, sha256.to_lowercase())
+}
+
+pub fn manifest_url(settings: &UpdateSettings, backend: &Backend) -> String {
+    settings
+        .manifest_url
+        .clone()
+        .or_else(|| BUILT_IN_MANIFEST_URL.map(String::from))
+        .unwrap_or_else(|| backend.url("/api/agent/releases/{channel}"))
+        .replace("{channel}", &settings.channel)
+}
+
+fn public_key(settings: &UpdateSettings) -> Result<VerifyingKey, AgentError> {
+    let encoded = settings
+        .public_key
+        .as_deref()
+        .or(BUILT_IN_PUBLIC_KEY)
+        .ok_or_else(|| AgentError::Policy("No release signing key configured".to_string()))?;
+    let invalid = |e: String| AgentError::Policy(format!("Invalid release signing key: {}", e));
+    let bytes: [u8; 32] = BASE64
+        .decode(encoded.trim())
+        .map_err(|e| invalid(e.to_string()))?
+        .try_into()
+        .map_err(|_| invalid("expected 32 bytes".to_string()))?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| invalid(e.to_string()))
+}
+
+/// The manifest's release, if it is newer than `current` and meant for
+/// this channel and platform
+pub fn select_update<'a>(
+    manifest: &'a ReleaseManifest,
+    current: &Version,
+    channel: &str,
+    platform: &str,
+) -> Result<Option<(Version, &'a ReleaseArtifact)>, AgentError> {
+    let version = Version::parse(manifest.version.trim_start_matches('v'))
+        .map_err(|e| AgentError::Parse(format!("Release version {:?}: {}", manifest.version, e)))?;
+    if !version.pre.is_empty() && channel != "beta" {
+        return Ok(None);
+    }
+    if version <= *current {
+        return Ok(None);
+    }
+    Ok(manifest.platforms.get(platform).map(|artifact| (version, artifact)))
+}
+
+/// Checksum and release signature of a downloaded artifact
+pub fn verify_artifact(
+    bytes: &[u8],
+    version: &Version,
+    platform: &str,
+    artifact: &ReleaseArtifact,
+    key: &VerifyingKey,
+) -> Result<(), AgentError> {
+    let digest = hex::encode(Sha256::digest(bytes));
+    if !digest.eq_ignore_ascii_case(artifact.sha256.trim()) {
+        return Err(AgentError::Policy(format!(
+            "Update checksum mismatch: expected {}, got {}",
+            artifact.sha256, digest
+        )));
+    }
+    let signature = BASE64
+        .decode(artifact.signature.trim())
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or_else(|| AgentError::Policy("Update signature is malformed".to_string()))?;
+    key.verify(signed_message(&version.to_string(), platform, &digest).as_bytes(), &signature)
+        .map_err(|_| AgentError::Policy("Update signature is invalid".to_string()))
+}
+
+/// "<file name>.<suffix>" next to `path`
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Replace `target` with `bytes`; returns the backup of the old file
+pub fn install(bytes: &[u8], target: &Path) -> Result<PathBuf, AgentError> {
+    let staged = sibling(target, "new");
+    let backup = sibling(target, "bak");
+
+    let mut file = fs::File::create(&staged)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+    if let Ok(metadata) = fs::metadata(target) {
+        fs::set_permissions(&staged, metadata.permissions())?;
+    }
+
+    let _ = fs::remove_file(&backup);
+    fs::rename(target, &backup)?;
+    if let Err(e) = fs::rename(&staged, target) {
+        let _ = fs::rename(&backup, target);
+        return Err(e.into());
+    }
+    Ok(backup)
+}
+
+/// Move the backup back in place of the failed version
+pub fn rollback_files(state: &UpdateState) -> Result<(), AgentError> {
+    let failed = sibling(&state.target, "failed");
+    let _ = fs::remove_file(&failed);
+    fs::rename(&state.target, &failed)?;
+    if let Err(e) = fs::rename(&state.backup, &state.target) {
+        let _ = fs::rename(&failed, &state.target);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+fn get_state_path() -> PathBuf {
+    agent_config_dir().join("update_state.json")
+}
+
+pub fn load_state() -> Option<UpdateState> {
+    let json = fs::read_to_string(get_state_path()).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+fn save_state(state: &UpdateState) -> Result<(), AgentError> {
+    fs::write(get_state_path(), serde_json::to_string_pretty(state)?)?;
+    Ok(())
+}
+
+async fn fetch_manifest(backend: &Backend, url: &str, token: Option<&str>) -> Result<ReleaseManifest, AgentError> {
+    let mut request = backend.client().get(url);
+    if let Some(token) = token {
+        request = request.header("Authorization", format!("Bearer {}", token));
+    }
+    let response = api::ensure_success(api::send(request).await?).await?;
+    Ok(response.json().await?)
+}
+
+/// Artifacts may live on a CDN: no token is sent
+async fn download(backend: &Backend, url: &str) -> Result<Vec<u8>, AgentError> {
+    let too_large = || AgentError::Policy(format!("Update artifact exceeds {} bytes", MAX_ARTIFACT_BYTES));
+    let mut response = api::ensure_success(api::send(backend.client().get(url)).await?).await?;
+    if response.content_length().is_some_and(|len| len > MAX_ARTIFACT_BYTES) {
+        return Err(too_large());
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if (bytes.len() + chunk.len()) as u64 > MAX_ARTIFACT_BYTES {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// Check the manifest and install a newer signed release over `target`
+pub async fn check_for_update(
+    settings: &UpdateSettings,
+    backend: &Backend,
+    token: Option<&str>,
+    target: &Path,
+) -> Result<UpdateOutcome, AgentError> {
+    let current = current_version();
+    let last = load_state();
+    if let Some(state) = last.as_ref().filter(|s| s.phase == UpdatePhase::PendingHealthCheck) {
+        return Ok(UpdateOutcome::AwaitingHealthCheck {
+            version: state.to_version.clone(),
+        });
+    }
+    let key = public_key(settings)?;
+
+    let manifest = fetch_manifest(backend, &manifest_url(settings, backend), token).await?;
+    let platform = platform();
+    let Some((version, artifact)) = select_update(&manifest, &current, &settings.channel, &platform)? else {
+        return Ok(UpdateOutcome::UpToDate {
+            version: current.to_string(),
+        });
+    };
+    if last.is_some_and(|s| s.phase == UpdatePhase::RolledBack && s.to_version == version.to_string()) {
+        return Ok(UpdateOutcome::RolledBack {
+            version: version.to_string(),
+        });
+    }
+
+    info!(version = %version, channel = %settings.channel, "Downloading update");
+    let bytes = download(backend, &artifact.url).await?;
+    verify_artifact(&bytes, &version, &platform, artifact, &key)?;
+    let backup = install(&bytes, target)?;
+
+    save_state(&UpdateState {
+        from_version: current.to_string(),
+        to_version: version.to_string(),
+        target: target.to_path_buf(),
+        backup,
+        installed_at: unix_now(),
+        boots: 0,
+        phase: UpdatePhase::PendingHealthCheck,
+    })?;
+    info!(from = %current, to = %version, "Update installed, restart pending");
+    Ok(UpdateOutcome::Installed {
+        from_version: current.to_string(),
+        to_version: version.to_string(),
+    })
+}
+
+/// Count this start against an update waiting for its health check;
+/// returns that update, if this is the version it installed
+pub fn record_boot() -> Option<UpdateState> {
+    let mut state = load_state().filter(|s| s.phase == UpdatePhase::PendingHealthCheck)?;
+    if state.to_version != current_version().to_string() {
+        // Started from another binary than the one installed (reinstalled
+        // by hand, or the restart picked up the old file): nothing to judge
+        warn!(expected = %state.to_version, "Running version does not match the pending update, dropping it");
+        state.phase = UpdatePhase::RolledBack;
+        let _ = save_state(&state);
+        return None;
+    }
+    state.boots += 1;
+    if let Err(e) = save_state(&state) {
+        warn!("Failed to record update boot: {}", e);
+    }
+    Some(state)
+}
+
+/// Whether a freshly updated agent works: no task panicked, and when
+/// logged in the backend has been reached at least once
+pub fn is_healthy(status: &AgentStatus) -> bool {
+    let no_panics = status.tasks.iter().all(|t| t.panics == 0);
+    let reached_backend = match status.auth {
+        AuthState::Authenticated => status.last_sync.is_some() || status.last_heartbeat.is_some(),
+        // Nothing to prove without a token, and an expired one is not the
+        // new version's fault
+        AuthState::LoggedOut | AuthState::Expired => true,
+    };
+    no_panics && reached_backend
+}
+
+/// Keep the new version and delete the backup
+pub fn confirm(mut state: UpdateState) -> Result<(), AgentError> {
+    let _ = fs::remove_file(&state.backup);
+    state.phase = UpdatePhase::Confirmed;
+    save_state(&state)?;
+    info!(version = %state.to_version, "Update confirmed");
+    Ok(())
+}
+
+/// Restore the previous version; the caller restarts the agent
+pub fn roll_back(mut state: UpdateState) -> Result<(), AgentError> {
+    rollback_files(&state)?;
+    state.phase = UpdatePhase::RolledBack;
+    save_state(&state)?;
+    warn!(from = %state.to_version, to = %state.from_version, "Update rolled back");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const PLATFORM: &str = "windows-x86_64";
+
+    fn signed(bytes: &[u8], version: &str, key: &SigningKey) -> ReleaseArtifact {
+        let sha256 = hex::encode(Sha256::digest(bytes));
+        let signature = key.sign(signed_message(version, PLATFORM, &sha256).as_bytes());
+        ReleaseArtifact {
+            url: "https://releases.example.com/tauriagent.exe".to_string(),
+            sha256,
+            signature: BASE64.encode(signature.to_bytes()),
+        }
+    }
+
+    fn manifest(version: &str) -> ReleaseManifest {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        ReleaseManifest {
+            version: version.to_string(),
+            notes: None,
+            pub_date: None,
+            platforms: BTreeMap::from([(PLATFORM.to_string(), signed(b"agent", version, &key))]),
+        }
+    }
+
+    #[test]
+    fn select_update_follows_version_channel_and_platform() {
+        let current = Version::parse("0.1.4").unwrap();
+        let pick = |m: &ReleaseManifest, channel: &str, platform: &str| {
+            select_update(m, &current, channel, platform)
+                .unwrap()
+                .map(|(v, _)| v.to_string())
+        };
+
+        assert_eq!(pick(&manifest("0.2.0"), "stable", PLATFORM), Some("0.2.0".to_string()));
+        assert_eq!(pick(&manifest("v0.2.0"), "stable", PLATFORM), Some("0.2.0".to_string()));
+        assert_eq!(pick(&manifest("0.1.4"), "stable", PLATFORM), None);
+        assert_eq!(pick(&manifest("0.1.3"), "beta", PLATFORM), None);
+        assert_eq!(pick(&manifest("0.2.0-beta.1"), "stable", PLATFORM), None);
+        assert_eq!(pick(&manifest("0.2.0-beta.1"), "beta", PLATFORM), Some("0.2.0-beta.1".to_string()));
+        assert_eq!(pick(&manifest("0.2.0"), "stable", "linux-aarch64"), None);
+        assert!(matches!(
+            select_update(&manifest("latest"), &current, "stable", PLATFORM),
+            Err(AgentError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn verify_rejects_tampered_replayed_or_foreign_artifacts() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let version = Version::parse("0.2.0").unwrap();
+        let artifact = signed(b"agent", "0.2.0", &key);
+        let verifying = key.verifying_key();
+
+        assert!(verify_artifact(b"agent", &version, PLATFORM, &artifact, &verifying).is_ok());
+        // Different bytes
+        assert!(verify_artifact(b"evil", &version, PLATFORM, &artifact, &verifying).is_err());
+        // Old signed build offered as a newer version
+        let newer = Version::parse("0.3.0").unwrap();
+        assert!(verify_artifact(b"agent", &newer, PLATFORM, &artifact, &verifying).is_err());
+        // Signed by someone else
+        let other = SigningKey::from_bytes(&[8; 32]).verifying_key();
+        assert!(verify_artifact(b"agent", &version, PLATFORM, &artifact, &other).is_err());
+    }
+
+    #[test]
+    fn install_keeps_a_backup_that_rollback_restores() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("tauriagent");
+        fs::write(&target, "old build").unwrap();
+
+        let backup = install(b"new build", &target).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new build");
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "old build");
+        assert!(!dir.path().join("tauriagent.new").exists());
+
+        let state = UpdateState {
+            from_version: "0.1.4".to_string(),
+            to_version: "0.2.0".to_string(),
+            target: target.clone(),
+            backup,
+            installed_at: 0,
+            boots: 1,
+            phase: UpdatePhase::PendingHealthCheck,
+        };
+        rollback_files(&state).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "old build");
+        assert_eq!(fs::read_to_string(dir.path().join("tauriagent.failed")).unwrap(), "new build");
+    }
+}
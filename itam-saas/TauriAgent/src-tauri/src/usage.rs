@@ -0,0 +1,97 @@
+// ============================================================================
+// Usage Tracking Module
+// ============================================================================
+// Tracks which application is in use, polled every few seconds by the
+// "usage" task:
+// - current-activity: name of the most active process, on every poll
+// - usage-update: UsageData for the session that just ended, whenever the
+//   most active process changes
+//
+// "Most active" is the process with the highest CPU usage (simplified - in
+// production, use the Windows API for the foreground window).
+// ============================================================================
+
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+
+use crate::process_source::{ProcessSource, SysinfoSource};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageData {
+    pub app_name: String,
+    pub window_title: String,
+    pub duration: u64,
+    pub timestamp: u64,
+}
+
+pub struct UsageTracker {
+    source: SysinfoSource,
+    last_process_name: String,
+    start_time: SystemTime,
+}
+
+impl Default for UsageTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UsageTracker {
+    pub fn new() -> Self {
+        Self {
+            source: SysinfoSource::new(),
+            last_process_name: String::new(),
+            start_time: SystemTime::now(),
+        }
+    }
+
+    /// Sample the process table; returns the current activity and, if it
+    /// changed, the session that just ended
+    pub fn poll(&mut self) -> (String, Option<UsageData>) {
+        let table = self.source.snapshot();
+
+        let current_process = table
+            .iter()
+            .max_by_key(|p| (p.cpu_usage * 100.0) as u64)
+            .map(|p| p.name.clone())
+            .unwrap_or_else(|| "Unknown".to_string());
+
+        if current_process == self.last_process_name {
+            return (current_process, None);
+        }
+
+        let ended = self.end_session();
+        self.last_process_name = current_process.clone();
+        (current_process, Some(ended))
+    }
+
+    /// Close the session in progress, e.g. when the agent stops
+    pub fn finish(&mut self) -> Option<UsageData> {
+        if self.last_process_name.is_empty() {
+            return None;
+        }
+        let ended = self.end_session();
+        self.last_process_name.clear();
+        Some(ended)
+    }
+
+    /// Close the current session and start a new one from now
+    fn end_session(&mut self) -> UsageData {
+        let duration = self
+            .start_time
+            .elapsed()
+            .unwrap_or(Duration::from_secs(0))
+            .as_secs();
+        self.start_time = SystemTime::now();
+
+        UsageData {
+            app_name: self.last_process_name.clone(),
+            window_title: "".to_string(),
+            duration,
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
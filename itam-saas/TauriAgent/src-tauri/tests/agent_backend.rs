@@ -0,0 +1,972 @@
+// ============================================================================
+// End-to-end tests: monitoring loop against the mock backend
+// ============================================================================
+// Each test points ForbiddenMonitor at a fresh MockBackend (tests/common),
+// replays a recorded process table and asserts on the requests the backend
+// received and the events the monitor raised.
+//
+// The agent keeps its cache, outbox and exceptions under
+// ITAM_AGENT_CONFIG_DIR, which is process-wide, so tests take TEST_LOCK and
+// get their own temporary directory.
+// ============================================================================
+
+mod common;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ed25519_dalek::{Signer, SigningKey};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, MutexGuard};
+use tokio_util::sync::CancellationToken;
+
+use common::{Failure, MockBackend, RecordedEvents};
+use tauriagent_lib::api::Backend;
+use tauriagent_lib::compliance;
+use tauriagent_lib::diagnostics::{self, check_endpoint, CheckStatus, DiagnosticsInput};
+use tauriagent_lib::exceptions::{submit_justification, JustificationRequest};
+use tauriagent_lib::forbidden::{cache_to_disk, get_device_id, ForbiddenApp, ForbiddenAppCache};
+use tauriagent_lib::heartbeat::{post_heartbeat, HeartbeatPayload, Lifecycle};
+use tauriagent_lib::logging::upload_recent_logs;
+use tauriagent_lib::metrics;
+use tauriagent_lib::monitor::{ForbiddenMonitor, MonitorEvent};
+use tauriagent_lib::perf::{self, PerfSample, PerfSampler};
+use tauriagent_lib::process_source::FixtureSource;
+use tauriagent_lib::push::{PushChannel, PushCommand, PushStatus};
+use tauriagent_lib::remote_tasks::poll_and_run;
+use tauriagent_lib::error::AgentError;
+use tauriagent_lib::settings::{AgentSettings, ApiSettings, MetricsSettings, UpdateSettings, CONFIG_DIR_ENV};
+use tauriagent_lib::state::AgentState;
+use tauriagent_lib::thresholds;
+use tauriagent_lib::status::{AuthState, OverallStatus};
+use tauriagent_lib::updater::{
+    check_for_update, load_state, platform, roll_back, signed_message, UpdateOutcome, UpdatePhase,
+};
+
+const RESPAWN: &str = include_str!("fixtures/respawn_and_pid_reuse.json");
+
+static TEST_LOCK: Mutex<()> = Mutex::const_new(());
+
+struct TestAgent {
+    backend: MockBackend,
+    events: RecordedEvents,
+    _config_dir: tempfile::TempDir,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl TestAgent {
+    async fn start() -> Self {
+        let guard = TEST_LOCK.lock().await;
+        let config_dir = tempfile::tempdir().unwrap();
+        std::env::set_var(CONFIG_DIR_ENV, config_dir.path());
+
+        Self {
+            backend: MockBackend::start().await,
+            events: RecordedEvents::default(),
+            _config_dir: config_dir,
+            _guard: guard,
+        }
+    }
+
+    /// The mock backend, with a short request timeout
+    fn api_settings(&self) -> ApiSettings {
+        ApiSettings {
+            base_url: self.backend.url.clone(),
+            request_timeout_secs: 1,
+        }
+    }
+
+    fn api(&self) -> Backend {
+        Backend::new(&self.api_settings())
+    }
+
+    fn state(&self, mut settings: AgentSettings) -> AgentState {
+        settings.api = self.api_settings();
+        AgentState::new(
+            settings,
+            Box::new(FixtureSource::from_json(RESPAWN).unwrap()),
+            Box::new(self.events.clone()),
+        )
+    }
+
+    fn monitor(&self, settings: AgentSettings) -> ForbiddenMonitor {
+        ForbiddenMonitor::new(
+            &self.api(),
+            settings,
+            Box::new(FixtureSource::from_json(RESPAWN).unwrap()),
+            Box::new(self.events.clone()),
+        )
+    }
+}
+
+/// Sync on every tick, no network rules (they need live sockets)
+fn test_settings() -> AgentSettings {
+    let mut settings = AgentSettings::default();
+    settings.policy.sync_interval_secs = 0;
+    settings.network.enabled = false;
+    settings
+}
+
+fn violations(events: &[MonitorEvent]) -> Vec<&tauriagent_lib::forbidden::ViolationReport> {
+    events
+        .iter()
+        .filter_map(|e| match e {
+            MonitorEvent::ViolationDetected(v) => Some(v),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn syncs_policy_and_reports_each_violation_once() {
+    let agent = TestAgent::start().await;
+    agent
+        .backend
+        .set_forbidden_apps(json!([{ "id": 7, "process_name": "steam", "severity": "High" }]));
+    let mut monitor = agent.monitor(test_settings());
+
+    monitor.tick(MockBackend::TOKEN).await;
+
+    let sync = agent.backend.requests_to("GET", "/api/forbidden-apps");
+    assert_eq!(sync.len(), 1);
+    assert_eq!(sync[0].header("authorization"), Some("Bearer test-token"));
+    assert_eq!(sync[0].header("if-none-match"), None);
+
+    // steam and steamwebhelper are different executables: two alerts
+    let alerts = agent.backend.alerts();
+    assert_eq!(alerts.len(), 2);
+    for alert in &alerts {
+        assert_eq!(alert["device_id"], json!(get_device_id()));
+        assert_eq!(alert["rule_id"], json!(7));
+        assert_eq!(alert["severity"], json!("High"));
+        assert_eq!(alert["aggregate"]["count"], json!(1));
+    }
+
+    let events = agent.events.take();
+    assert!(matches!(events[0], MonitorEvent::ForbiddenListUpdated(ref diff) if diff.added.len() == 1));
+    let reported = violations(&events);
+    assert_eq!(reported.len(), 2);
+    assert!(reported.iter().all(|v| v.alert_id.is_some()));
+
+    let status = monitor.status_handle().lock().unwrap().clone();
+    assert_eq!(status.policy.version.as_deref(), Some("W/\"v1\""));
+    assert_eq!(status.policy.rules, 1);
+    assert!(status.last_sync.is_some() && status.last_upload.is_some());
+    assert_eq!(status.active_violations, 2);
+
+    // Same processes, unchanged list: a 304 and no new alerts
+    monitor.tick(MockBackend::TOKEN).await;
+
+    let sync = agent.backend.requests_to("GET", "/api/forbidden-apps");
+    assert_eq!(sync[1].header("if-none-match"), Some("W/\"v1\""));
+    assert_eq!(sync[1].status, Some(304));
+    assert_eq!(agent.backend.alerts().len(), 2);
+    assert!(agent.events.take().is_empty());
+}
+
+#[tokio::test]
+async fn respawn_is_folded_into_open_alert_until_escalation() {
+    let agent = TestAgent::start().await;
+    agent
+        .backend
+        .set_forbidden_apps(json!([{ "id": 7, "process_name": "steam", "severity": "Medium" }]));
+    let mut settings = test_settings();
+    settings.aggregation.realert_after_count = 1;
+    settings.aggregation.escalate_severity = true;
+    let mut monitor = agent.monitor(settings);
+
+    monitor.tick(MockBackend::TOKEN).await;
+    monitor.tick(MockBackend::TOKEN).await;
+    let first_steam_alert = agent
+        .backend
+        .alerts()
+        .into_iter()
+        .find(|a| a["app_detected"] == "steam")
+        .unwrap();
+
+    // Third snapshot: steam respawned as PID 150
+    monitor.tick(MockBackend::TOKEN).await;
+
+    let alerts = agent.backend.alerts();
+    assert_eq!(alerts.len(), 3);
+    let realert = &alerts[2];
+    assert_eq!(realert["process_id"], json!(100));
+    assert_eq!(realert["severity"], json!("High"));
+    assert_eq!(realert["aggregate"]["count"], json!(2));
+    assert_eq!(realert["aggregate"]["process_ids"], json!([100, 150]));
+    assert_eq!(realert["aggregate"]["alert_number"], json!(2));
+    assert_eq!(realert["aggregate"]["previous_alert_id"], first_steam_alert["id"]);
+}
+
+#[tokio::test]
+async fn rejected_token_raises_auth_expired() {
+    let agent = TestAgent::start().await;
+    agent
+        .backend
+        .set_forbidden_apps(json!([{ "id": 7, "process_name": "steam", "severity": "High" }]));
+    let mut monitor = agent.monitor(test_settings());
+
+    monitor.tick("expired-token").await;
+
+    assert_eq!(agent.backend.requests_to("GET", "/api/forbidden-apps")[0].status, Some(401));
+    assert!(agent.backend.alerts().is_empty());
+    let events = agent.events.take();
+    assert!(events.iter().any(|e| matches!(e, MonitorEvent::AuthExpired)));
+    assert!(events.iter().any(|e| matches!(e, MonitorEvent::PolicyStale(s) if s.stale)));
+    let status = monitor.status_handle().lock().unwrap().clone();
+    assert!(status.auth_rejected);
+    assert!(status.policy.stale);
+}
+
+#[tokio::test]
+async fn failed_alert_is_retried_on_next_tick() {
+    let agent = TestAgent::start().await;
+    agent
+        .backend
+        .set_forbidden_apps(json!([{ "id": 7, "process_name": "steam", "severity": "High" }]));
+    agent.backend.fail_next("POST", "/api/alerts", Failure::Status(500));
+    let mut monitor = agent.monitor(test_settings());
+
+    monitor.tick(MockBackend::TOKEN).await;
+    assert_eq!(agent.backend.alerts().len(), 1);
+
+    monitor.tick(MockBackend::TOKEN).await;
+    assert_eq!(agent.backend.alerts().len(), 2);
+    assert_eq!(agent.backend.requests_to("POST", "/api/alerts").len(), 3);
+    // The failed report is raised too, without an alert id
+    let events = agent.events.take();
+    let raised = violations(&events);
+    assert_eq!(raised.len(), 3);
+    assert_eq!(raised.iter().filter(|v| v.alert_id.is_none()).count(), 1);
+}
+
+#[tokio::test]
+async fn unreported_alerts_are_queued_and_flushed_on_shutdown() {
+    let agent = TestAgent::start().await;
+    agent
+        .backend
+        .set_forbidden_apps(json!([{ "id": 7, "process_name": "steam", "severity": "High" }]));
+    let mut monitor = agent.monitor(test_settings());
+
+    monitor.tick(MockBackend::TOKEN).await;
+    monitor.tick(MockBackend::TOKEN).await;
+    // steam respawned as PID 150: folded into the open alert, not due yet
+    monitor.tick(MockBackend::TOKEN).await;
+    assert_eq!(agent.backend.alerts().len(), 2);
+
+    monitor.queue_unreported_alerts();
+    assert_eq!(monitor.status_handle().lock().unwrap().outbox_depth, 1);
+    monitor.flush_outbox(MockBackend::TOKEN).await.unwrap();
+
+    let alerts = agent.backend.alerts();
+    assert_eq!(alerts.len(), 3);
+    assert_eq!(alerts[2]["app_detected"], json!("steam"));
+    assert_eq!(alerts[2]["aggregate"]["process_ids"], json!([100, 150]));
+    assert_eq!(alerts[2]["aggregate"]["alert_number"], json!(2));
+
+    // Everything is covered now
+    monitor.queue_unreported_alerts();
+    assert_eq!(monitor.status_handle().lock().unwrap().outbox_depth, 0);
+}
+
+#[tokio::test]
+async fn failed_alert_is_retried_without_aggregation() {
+    let agent = TestAgent::start().await;
+    agent
+        .backend
+        .set_forbidden_apps(json!([{ "id": 7, "process_name": "steam", "severity": "High" }]));
+    agent.backend.fail_next("POST", "/api/alerts", Failure::Status(500));
+    let mut settings = test_settings();
+    settings.aggregation.enabled = false;
+    let mut monitor = agent.monitor(settings);
+
+    monitor.tick(MockBackend::TOKEN).await;
+    assert_eq!(agent.backend.alerts().len(), 1);
+
+    monitor.tick(MockBackend::TOKEN).await;
+    let alerts = agent.backend.alerts();
+    assert_eq!(alerts.len(), 2);
+    assert!(alerts.iter().all(|a| a["aggregate"].is_null()));
+    let events = agent.events.take();
+    let raised = violations(&events);
+    assert_eq!(raised.len(), 3);
+    assert_eq!(raised.iter().filter(|v| v.alert_id.is_none()).count(), 1);
+}
+
+#[tokio::test]
+async fn unresponsive_backend_times_out_and_cached_policy_still_applies() {
+    let agent = TestAgent::start().await;
+    cache_to_disk(&ForbiddenAppCache {
+        apps: vec![ForbiddenApp {
+            id: Some(9),
+            process_name: "firefox".to_string(),
+            severity: "Low".to_string(),
+            mode: Default::default(),
+            schedule: None,
+            scope: None,
+            exceptions: None,
+        }],
+        last_updated: 1,
+        etag: None,
+    })
+    .unwrap();
+    agent
+        .backend
+        .fail_next("GET", "/api/forbidden-apps", Failure::Timeout(Duration::from_secs(5)));
+    let mut monitor = agent.monitor(test_settings());
+
+    let started = Instant::now();
+    monitor.tick(MockBackend::TOKEN).await;
+
+    assert!(started.elapsed() < Duration::from_secs(4));
+    let alerts = agent.backend.alerts();
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0]["app_detected"], json!("firefox"));
+    assert_eq!(alerts[0]["rule_id"], json!(9));
+}
+
+#[tokio::test]
+async fn audit_matches_survive_rate_limiting_in_the_outbox() {
+    let agent = TestAgent::start().await;
+    agent.backend.set_forbidden_apps(json!([
+        { "id": 7, "process_name": "steam", "severity": "High", "mode": "audit" }
+    ]));
+    agent
+        .backend
+        .fail_next("POST", "/api/agent/policy-audit", Failure::Status(429));
+    let mut monitor = agent.monitor(test_settings());
+
+    monitor.tick(MockBackend::TOKEN).await;
+
+    let attempts = agent.backend.requests_to("POST", "/api/agent/policy-audit");
+    assert_eq!(attempts.len(), 1);
+    assert_eq!(attempts[0].status, Some(429));
+    assert!(agent.backend.alerts().is_empty());
+    let audit_events = agent
+        .events
+        .take()
+        .into_iter()
+        .filter(|e| matches!(e, MonitorEvent::PolicyAuditMatch(_)))
+        .count();
+    assert_eq!(audit_events, 2);
+
+    monitor.tick(MockBackend::TOKEN).await;
+
+    let delivered: Vec<_> = agent
+        .backend
+        .requests_to("POST", "/api/agent/policy-audit")
+        .into_iter()
+        .filter(|r| r.status == Some(201))
+        .map(|r| r.body.unwrap())
+        .collect();
+    assert_eq!(delivered.len(), 2);
+    assert_eq!(delivered[0]["process_id"], json!(100));
+    assert_eq!(delivered[1]["process_id"], json!(101));
+    assert!(delivered.iter().all(|d| d["mode"] == "audit"));
+}
+
+#[tokio::test]
+async fn approved_exception_is_picked_up_on_sync() {
+    let agent = TestAgent::start().await;
+    agent
+        .backend
+        .set_forbidden_apps(json!([{ "id": 31, "process_name": "systemd", "severity": "Medium" }]));
+    let mut monitor = agent.monitor(test_settings());
+
+    monitor.tick(MockBackend::TOKEN).await;
+    let alert_id = agent.backend.alerts()[0]["id"].as_i64().unwrap();
+
+    let request = JustificationRequest {
+        justification: "Needed for vendor portal".to_string(),
+        exception_requested: true,
+        exception_duration_minutes: Some(60),
+    };
+    submit_justification(&agent.api(), MockBackend::TOKEN, alert_id, &request)
+        .await
+        .unwrap();
+    monitor.exceptions().record_request(alert_id, 3600);
+
+    let patch = &agent.backend.requests_to("PATCH", &format!("/api/alerts/{}", alert_id))[0];
+    assert_eq!(patch.body.as_ref().unwrap()["exception_duration_minutes"], json!(60));
+
+    agent
+        .backend
+        .update_alert(alert_id, json!({ "exception_status": "approved" }));
+    monitor.tick(MockBackend::TOKEN).await;
+
+    // Alert statuses are fetched on every sync
+    let device_path = format!("/api/alerts/device/{}", get_device_id());
+    assert_eq!(agent.backend.requests_to("GET", &device_path).len(), 2);
+    let events = agent.events.take();
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, MonitorEvent::ExceptionApproved(applied) if applied[0].alert_id == alert_id)));
+    assert!(monitor.exceptions().is_excepted(Some(31), "systemd"));
+    assert!(!monitor.exceptions().is_excepted(Some(32), "systemd"));
+
+    // A revoked exception ends at the next sync
+    agent
+        .backend
+        .update_alert(alert_id, json!({ "exception_status": "revoked" }));
+    monitor.tick(MockBackend::TOKEN).await;
+    assert!(!monitor.exceptions().is_excepted(Some(31), "systemd"));
+}
+
+#[tokio::test]
+async fn device_tags_are_taken_from_the_heartbeat_response() {
+    let agent = TestAgent::start().await;
+    agent.backend.set_forbidden_apps(json!([{
+        "id": 9, "process_name": "steam", "severity": "High",
+        "scope": { "device_tags": ["finance"] }
+    }]));
+    let state = agent.state(test_settings());
+
+    state.monitor.lock().await.tick(MockBackend::TOKEN).await;
+    assert!(agent.backend.alerts().is_empty());
+
+    agent.backend.set_device(json!({ "tags": ["Finance"], "org_unit": "EMEA/Finance" }));
+    let payload = HeartbeatPayload::collect(state.status(), Lifecycle::Running);
+    let response = post_heartbeat(&agent.api(), MockBackend::TOKEN, &payload)
+        .await
+        .unwrap();
+    state.set_device(response.device.unwrap());
+    assert_eq!(state.device().org_unit.as_deref(), Some("EMEA/Finance"));
+
+    state.monitor.lock().await.tick(MockBackend::TOKEN).await;
+    assert!(!agent.backend.alerts().is_empty());
+}
+
+#[tokio::test]
+async fn agent_state_status_follows_credentials_and_monitor() {
+    let agent = TestAgent::start().await;
+    agent
+        .backend
+        .set_forbidden_apps(json!([{ "id": 7, "process_name": "steam", "severity": "High" }]));
+    let state = agent.state(test_settings());
+
+    let status = state.status();
+    assert_eq!(status.auth, AuthState::LoggedOut);
+    assert_eq!(status.overall, OverallStatus::LoggedOut);
+    assert!(state.credentials.require().is_err());
+
+    state.credentials.set(MockBackend::TOKEN.to_string());
+    let token = state.credentials.require().unwrap();
+    state.monitor.lock().await.tick(&token).await;
+
+    let status = state.status();
+    assert_eq!(status.overall, OverallStatus::Healthy);
+    assert_eq!(status.policy.rules, 1);
+    assert_eq!(status.active_violations, 2);
+    assert_eq!(agent.backend.alerts().len(), 2);
+}
+
+#[tokio::test]
+async fn upload_logs_sends_only_the_requested_window() {
+    let agent = TestAgent::start().await;
+    let log_dir = tempfile::tempdir().unwrap();
+    let now = chrono::Utc::now();
+    let line = |age: chrono::Duration, message: &str| {
+        json!({
+            "timestamp": (now - age).to_rfc3339(),
+            "level": "WARN",
+            "fields": { "message": message },
+            "span": { "name": "task", "task": "policy-sync" },
+        })
+        .to_string()
+    };
+    let lines = [
+        line(chrono::Duration::hours(3), "old"),
+        line(chrono::Duration::minutes(5), "recent"),
+    ];
+    std::fs::write(
+        log_dir.path().join(format!("agent.{}.log", now.format("%Y-%m-%d"))),
+        lines.join("\n"),
+    )
+    .unwrap();
+
+    let uploaded = upload_recent_logs(&agent.api(), MockBackend::TOKEN, log_dir.path(), Duration::from_secs(3600))
+        .await
+        .unwrap();
+    assert_eq!(uploaded, 1);
+
+    let requests = agent.backend.requests_to("POST", "/api/agent/logs");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(
+        requests[0].header("authorization"),
+        Some(format!("Bearer {}", MockBackend::TOKEN).as_str())
+    );
+    let body = requests[0].body.as_ref().unwrap();
+    assert_eq!(body["device_id"], json!(get_device_id()));
+    assert_eq!(body["window_minutes"], json!(60));
+    assert_eq!(body["entries"][0]["fields"]["message"], json!("recent"));
+}
+
+#[tokio::test]
+async fn diagnostics_bundle_is_saved_and_uploaded() {
+    let agent = TestAgent::start().await;
+    let state = agent.state(test_settings());
+    state.credentials.set(MockBackend::TOKEN.to_string());
+    let token = state.credentials.require().unwrap();
+    state.monitor.lock().await.tick(&token).await;
+    state.monitor.lock().await.persist();
+
+    let out = tempfile::tempdir().unwrap();
+    let path = out.path().join("bundle.zip");
+    let input = DiagnosticsInput {
+        settings: state.settings.clone(),
+        token: Some(token),
+        status: Some(state.status()),
+        log_dir: out.path().to_path_buf(),
+    };
+    let report = diagnostics::collect(input, &path, true).await.unwrap();
+    assert!(report.uploaded);
+    assert!(report.files.contains(&"connectivity.json".to_string()));
+
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(&path).unwrap()).unwrap();
+    let read_json = |archive: &mut zip::ZipArchive<std::fs::File>, name: &str| -> serde_json::Value {
+        serde_json::from_reader(archive.by_name(name).unwrap()).unwrap()
+    };
+    let connectivity = read_json(&mut archive, "connectivity.json");
+    let checks: Vec<(&str, &str)> = connectivity
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| (c["check"].as_str().unwrap(), c["status"].as_str().unwrap()))
+        .collect();
+    assert_eq!(checks, [("dns", "ok"), ("http", "ok"), ("auth", "ok")]);
+    assert_eq!(read_json(&mut archive, "policy.json")["rules"], json!(0));
+    assert_eq!(read_json(&mut archive, "status.json")["auth"], json!("authenticated"));
+
+    let uploads = agent.backend.requests_to("POST", "/api/agent/diagnostics");
+    assert_eq!(uploads.len(), 1);
+    assert_eq!(uploads[0].header("content-type"), Some("application/zip"));
+}
+
+#[tokio::test]
+async fn diagnostics_report_rejected_token_and_skip_auth_without_one() {
+    let agent = TestAgent::start().await;
+    let checks = check_endpoint(&agent.api(), Some("expired")).await;
+    assert_eq!(checks.last().unwrap().check, "auth");
+    assert_eq!(checks.last().unwrap().status, CheckStatus::Failed);
+    assert!(checks.last().unwrap().detail.starts_with("Unauthorized"));
+
+    let checks = check_endpoint(&agent.api(), None).await;
+    assert_eq!(checks.last().unwrap().status, CheckStatus::Skipped);
+}
+
+#[tokio::test]
+async fn self_update_installs_signed_release_and_rolls_back() {
+    let agent = TestAgent::start().await;
+    let signing = SigningKey::from_bytes(&[42; 32]);
+    let settings = UpdateSettings {
+        public_key: Some(BASE64.encode(signing.verifying_key().to_bytes())),
+        ..UpdateSettings::default()
+    };
+
+    let release = |version: &str, served: &[u8], signed: &[u8]| {
+        let sha256 = hex::encode(Sha256::digest(signed));
+        let signature = signing.sign(signed_message(version, &platform(), &sha256).as_bytes());
+        let url = agent.backend.serve_download(&format!("tauriagent-{}", version), served.to_vec());
+        json!({
+            "version": version,
+            "platforms": { platform(): {
+                "url": url,
+                "sha256": sha256,
+                "signature": BASE64.encode(signature.to_bytes()),
+            } },
+        })
+    };
+    let install_dir = tempfile::tempdir().unwrap();
+    let target = install_dir.path().join("tauriagent");
+    std::fs::write(&target, "0.1.4 build").unwrap();
+    let api = agent.api();
+    let check = || check_for_update(&settings, &api, Some(MockBackend::TOKEN), &target);
+
+    // Nothing newer on the channel
+    agent.backend.publish_release("stable", release("0.1.4", b"0.1.4 build", b"0.1.4 build"));
+    assert!(matches!(check().await.unwrap(), UpdateOutcome::UpToDate { .. }));
+
+    // Served bytes differ from what was signed: refused, nothing touched
+    agent.backend.publish_release("stable", release("0.2.0", b"tampered", b"0.2.0 build"));
+    assert!(matches!(check().await, Err(AgentError::Policy(_))));
+    assert_eq!(std::fs::read_to_string(&target).unwrap(), "0.1.4 build");
+
+    agent.backend.publish_release("stable", release("0.2.0", b"0.2.0 build", b"0.2.0 build"));
+    assert_eq!(
+        check().await.unwrap(),
+        UpdateOutcome::Installed {
+            from_version: "0.1.4".to_string(),
+            to_version: "0.2.0".to_string(),
+        }
+    );
+    assert_eq!(std::fs::read_to_string(&target).unwrap(), "0.2.0 build");
+    // No second update until this one passed its health check
+    assert!(matches!(check().await.unwrap(), UpdateOutcome::AwaitingHealthCheck { .. }));
+
+    let pending = load_state().unwrap();
+    assert_eq!(pending.phase, UpdatePhase::PendingHealthCheck);
+    roll_back(pending).unwrap();
+    assert_eq!(std::fs::read_to_string(&target).unwrap(), "0.1.4 build");
+    assert_eq!(load_state().unwrap().phase, UpdatePhase::RolledBack);
+
+    // The rolled back release is not installed again, a newer one is
+    assert_eq!(
+        check().await.unwrap(),
+        UpdateOutcome::RolledBack {
+            version: "0.2.0".to_string(),
+        }
+    );
+    assert_eq!(std::fs::read_to_string(&target).unwrap(), "0.1.4 build");
+    agent.backend.publish_release("stable", release("0.2.1", b"0.2.1 build", b"0.2.1 build"));
+    assert!(matches!(check().await.unwrap(), UpdateOutcome::Installed { .. }));
+    assert_eq!(std::fs::read_to_string(&target).unwrap(), "0.2.1 build");
+
+    assert!(agent
+        .backend
+        .requests_to("GET", "/downloads/tauriagent-0.2.0")
+        .iter()
+        .all(|r| r.header("authorization").is_none()));
+}
+
+#[tokio::test]
+async fn push_stream_dispatches_commands_and_resumes_after_last_event() {
+    let agent = TestAgent::start().await;
+    agent.backend.push_stream(concat!(
+        ": connected\n\n",
+        "id: 1\nevent: policy-updated\ndata: {\"version\":\"v2\"}\n\n",
+        "event: ping\ndata: {}\n\n",
+        "id: 2\nevent: inventory-requested\ndata:\n\n",
+        "id: 3\nevent: device-revoked\ndata: {\"reason\":\"retired\"}\n\n",
+    ));
+    agent.backend.push_stream("id: 4\nevent: heartbeat-requested\n\n");
+
+    let status = std::sync::Arc::new(std::sync::Mutex::new(PushStatus::default()));
+    let mut channel = PushChannel::new(&agent.api(), status.clone());
+    let mut commands = Vec::new();
+    let mut collect = |command| commands.push(command);
+
+    let (established, result) = channel.connect(MockBackend::TOKEN, &mut collect).await;
+    assert!(established && result.is_ok());
+    let (established, result) = channel.connect(MockBackend::TOKEN, &mut collect).await;
+    assert!(established && result.is_ok());
+    // Nothing scripted any more: an older backend without the endpoint
+    let (established, result) = channel.connect(MockBackend::TOKEN, &mut collect).await;
+    assert!(!established);
+    assert!(matches!(result, Err(AgentError::Server { status: 404, .. })));
+
+    assert_eq!(
+        commands,
+        [
+            PushCommand::PolicyUpdated,
+            PushCommand::InventoryRequested,
+            PushCommand::DeviceRevoked {
+                reason: Some("retired".to_string())
+            },
+            PushCommand::HeartbeatRequested,
+        ]
+    );
+    let status = status.lock().unwrap().clone();
+    assert!(!status.connected);
+    assert!(status.last_connected.is_some() && status.last_event.is_some());
+
+    let streams: Vec<_> = agent
+        .backend
+        .requests()
+        .into_iter()
+        .filter(|r| r.path.starts_with("/api/agent/stream?device_id="))
+        .collect();
+    assert_eq!(streams.len(), 3);
+    assert_eq!(streams[0].header("accept"), Some("text/event-stream"));
+    assert_eq!(streams[0].header("last-event-id"), None);
+    assert_eq!(streams[1].header("last-event-id"), Some("3"));
+    assert_eq!(streams[2].header("last-event-id"), Some("4"));
+}
+
+#[tokio::test]
+async fn remote_tasks_run_from_the_allowlist_and_report_results() {
+    let agent = TestAgent::start().await;
+    agent
+        .backend
+        .set_forbidden_apps(json!([{ "id": 7, "process_name": "steam", "severity": "High" }]));
+    let state = agent.state(test_settings());
+    state.credentials.set(MockBackend::TOKEN.to_string());
+    let token = state.credentials.require().unwrap();
+    state.monitor.lock().await.sync(&token).await.unwrap();
+
+    agent.backend.queue_task(json!({ "id": 1, "type": "rescan" }));
+    agent.backend.queue_task(json!({ "id": "2", "type": "clear_policy_cache", "params": {} }));
+    agent.backend.queue_task(json!({ "id": 3, "type": "shell", "params": { "command": "id" } }));
+    // No LogControl in tests: the task runs and fails
+    agent.backend.queue_task(json!({ "id": 4, "type": "upload_logs", "params": { "window_minutes": 5 } }));
+
+    let results = poll_and_run(&state, &token).await.unwrap();
+    let statuses: Vec<(String, String)> = agent
+        .backend
+        .task_results()
+        .iter()
+        .map(|r| (r["task_id"].as_str().unwrap().to_string(), r["status"].as_str().unwrap().to_string()))
+        .collect();
+    assert_eq!(
+        statuses,
+        [
+            ("1".to_string(), "succeeded".to_string()),
+            ("2".to_string(), "succeeded".to_string()),
+            ("3".to_string(), "rejected".to_string()),
+            ("4".to_string(), "failed".to_string()),
+        ]
+    );
+    assert_eq!(results[0].output.as_ref().unwrap()["active_violations"], json!(2));
+    assert_eq!(results[1].output.as_ref().unwrap()["rules"], json!(1));
+    assert!(results[2].error.as_ref().unwrap().contains("Unknown task type"));
+    assert_eq!(agent.backend.requests_to("GET", "/api/forbidden-apps").last().unwrap().header("if-none-match"), None);
+    assert_eq!(agent.backend.alerts().len(), 2);
+
+    // Served again before the result arrived: not run twice
+    agent.backend.queue_task(json!({ "id": 1, "type": "rescan" }));
+    assert!(poll_and_run(&state, &token).await.unwrap().is_empty());
+    assert_eq!(agent.backend.task_results().len(), 4);
+
+    // Nor after a restart
+    let restarted = agent.state(test_settings());
+    assert!(poll_and_run(&restarted, &token).await.unwrap().is_empty());
+    assert_eq!(agent.backend.task_results().len(), 4);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn signed_script_runs_in_sandbox_and_every_attempt_is_audited() {
+    let agent = TestAgent::start().await;
+    let signing = SigningKey::from_bytes(&[9; 32]);
+    let mut settings = test_settings();
+    settings.scripts.public_key = Some(BASE64.encode(signing.verifying_key().to_bytes()));
+    let state = agent.state(settings);
+    state.credentials.set(MockBackend::TOKEN.to_string());
+    let token = state.credentials.require().unwrap();
+
+    let expires_at = chrono::Utc::now().timestamp() + 600;
+    let script = json!({
+        "id": "whoami",
+        "interpreter": "sh",
+        "body": "echo \"cwd=$(basename $(pwd)) home=${HOME:-unset}\"; exit 0",
+        "expires_at": expires_at,
+        "targets": { "device_ids": [get_device_id()] },
+    })
+    .to_string()
+    .into_bytes();
+    let params = json!({
+        "script": BASE64.encode(&script),
+        "signature": BASE64.encode(signing.sign(&script).to_bytes()),
+    });
+    let forged = json!({
+        "script": BASE64.encode(b"{\"id\":\"evil\"}"),
+        "signature": params["signature"].clone(),
+    });
+    agent.backend.queue_task(json!({ "id": 10, "type": "run_script", "params": params }));
+    agent.backend.queue_task(json!({ "id": 11, "type": "run_script", "params": forged }));
+
+    let results = poll_and_run(&state, &token).await.unwrap();
+    assert_eq!(results.len(), 2);
+    let output = results[0].output.as_ref().unwrap();
+    assert_eq!(output["exit_code"], json!(0));
+    // Fresh working directory, environment cleared
+    let digest = hex::encode(Sha256::digest(&script));
+    let stdout = output["stdout"].as_str().unwrap();
+    assert!(stdout.ends_with(&format!("-{} home=unset\n", &digest[..12])), "{}", stdout);
+    assert_eq!(results[1].error.as_deref(), Some("Policy error: Script signature is invalid"));
+
+    let audits = agent.backend.requests_to("POST", "/api/agent/script-audit");
+    let decisions: Vec<_> = audits
+        .iter()
+        .map(|r| {
+            let body = r.body.as_ref().unwrap();
+            (body["task_id"].clone(), body["decision"].clone(), body["exit_code"].clone())
+        })
+        .collect();
+    assert_eq!(
+        decisions,
+        [(json!("10"), json!("ran"), json!(0)), (json!("11"), json!("rejected"), json!(null))]
+    );
+    assert_eq!(audits[0].body.as_ref().unwrap()["sha256"], json!(digest));
+
+    let local = std::fs::read_to_string(tauriagent_lib::scripts::audit_log_path()).unwrap();
+    assert_eq!(local.lines().count(), 2);
+}
+
+#[tokio::test]
+async fn metrics_endpoint_exports_scans_requests_and_outbox() {
+    let agent = TestAgent::start().await;
+    agent
+        .backend
+        .set_forbidden_apps(json!([{ "id": 7, "process_name": "steam", "severity": "High" }]));
+    let state = Arc::new(agent.state(test_settings()));
+    state.credentials.set(MockBackend::TOKEN.to_string());
+    let token = state.credentials.require().unwrap();
+    {
+        let mut monitor = state.monitor.lock().await;
+        monitor.sync(&token).await.unwrap();
+        monitor.scan(&token).await.unwrap();
+    }
+
+    let settings = MetricsSettings {
+        enabled: true,
+        listen: "127.0.0.1:0".to_string(),
+    };
+    let listener = metrics::bind(&settings).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let cancel = CancellationToken::new();
+    let server = tokio::spawn(metrics::serve(listener, state.clone(), cancel.clone()));
+
+    let response = reqwest::get(format!("http://{}/metrics", addr)).await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/plain; version=0.0.4"));
+    let body = response.text().await.unwrap();
+    for line in ["itam_agent_up 1", "itam_agent_outbox_depth 0", "itam_agent_policy_rules 1"] {
+        assert!(body.lines().any(|l| l == line), "missing {:?} in\n{}", line, body);
+    }
+    // The registry is process-wide, so other tests add to the counters
+    for series in [
+        "itam_agent_http_requests_total{method=\"GET\",endpoint=\"/api/forbidden-apps\",status=\"200\"}",
+        "itam_agent_http_requests_total{method=\"POST\",endpoint=\"/api/alerts\",status=\"201\"}",
+        "itam_agent_scan_duration_seconds_count",
+        "itam_agent_violations_total{severity=\"High\"}",
+        "itam_host_memory_total_bytes",
+    ] {
+        assert!(body.lines().any(|l| l.starts_with(series)), "missing {:?} in\n{}", series, body);
+    }
+
+    assert_eq!(reqwest::get(format!("http://{}/", addr)).await.unwrap().status(), 404);
+    cancel.cancel();
+    server.await.unwrap().unwrap();
+
+    let exposed = MetricsSettings {
+        enabled: true,
+        listen: "0.0.0.0:9464".to_string(),
+    };
+    assert!(matches!(metrics::bind(&exposed).await, Err(AgentError::Policy(_))));
+}
+
+#[tokio::test]
+async fn perf_rollups_are_uploaded_in_batches() {
+    let agent = TestAgent::start().await;
+    let state = agent.state(test_settings());
+    state.credentials.set(MockBackend::TOKEN.to_string());
+    let token = state.credentials.require().unwrap();
+
+    // The live sampler needs a previous reading for rates and CPU usage
+    let mut sampler = PerfSampler::default();
+    assert!(sampler.sample(3).is_none());
+    let live = sampler.sample(3).unwrap();
+    assert!(live.memory_total_bytes > 0);
+    assert!(live.top_processes.len() <= 3);
+
+    // Nothing finished yet: no upload
+    state.perf.lock().unwrap().ingest(PerfSample { timestamp: 7_200, ..live.clone() });
+    assert_eq!(perf::upload(&state, &token).await.unwrap(), 0);
+
+    for timestamp in [7_230, 7_260, 10_800] {
+        state.perf.lock().unwrap().ingest(PerfSample { timestamp, ..live.clone() });
+    }
+    assert_eq!(perf::upload(&state, &token).await.unwrap(), 3);
+
+    let uploads = agent.backend.requests_to("POST", perf::PERF_ENDPOINT);
+    assert_eq!(uploads.len(), 1);
+    let body = uploads[0].body.as_ref().unwrap();
+    assert_eq!(body["device_id"], json!(get_device_id()));
+    let rollups: Vec<(&str, u64, u64)> = body["rollups"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| (r["resolution"].as_str().unwrap(), r["start"].as_u64().unwrap(), r["samples"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(rollups, [("minute", 7_200, 2), ("minute", 7_260, 1), ("hour", 7_200, 3)]);
+    assert_eq!(state.status().outbox_depth, 0);
+}
+
+#[tokio::test]
+async fn threshold_rules_fire_and_resolve_through_alerts() {
+    let agent = TestAgent::start().await;
+    let mut settings = test_settings();
+    settings.thresholds.rules_refresh_secs = 0;
+    let state = agent.state(settings);
+    state.credentials.set(MockBackend::TOKEN.to_string());
+    let token = state.credentials.require().unwrap();
+
+    let sample = |timestamp, cpu_percent| PerfSample {
+        timestamp,
+        cpu_percent,
+        memory_used_bytes: 6_000_000_000,
+        memory_total_bytes: 16_000_000_000,
+        swap_used_bytes: 0,
+        swap_total_bytes: 0,
+        load_1: 0.5,
+        load_5: 0.5,
+        load_15: 0.5,
+        disk_read_bytes_per_sec: 0.0,
+        disk_write_bytes_per_sec: 0.0,
+        net_rx_bytes_per_sec: 0.0,
+        net_tx_bytes_per_sec: 0.0,
+        top_processes: Vec::new(),
+    };
+    state.perf.lock().unwrap().ingest(sample(60, 99.0));
+
+    // Older backend without the endpoint: no rules, no alerts
+    assert!(thresholds::check(&state, &token).await.unwrap().is_empty());
+
+    agent.backend.set_threshold_rules(json!([
+        { "id": 4, "name": "CPU pegged", "metric": "cpu_percent", "comparator": ">", "value": 95, "severity": "High" },
+        { "id": 5, "metric": "memory_total_bytes", "comparator": "<", "value": 8e9 },
+        { "id": 6, "metric": "gpu_temperature", "comparator": ">", "value": 80 },
+    ]));
+    let fired = thresholds::check(&state, &token).await.unwrap();
+    assert_eq!(fired.len(), 1);
+    assert_eq!(state.thresholds.lock().unwrap().rules().len(), 2);
+
+    state.perf.lock().unwrap().ingest(sample(120, 12.0));
+    let resolved = thresholds::check(&state, &token).await.unwrap();
+    assert_eq!(resolved.len(), 1);
+
+    let alerts = agent.backend.alerts();
+    assert_eq!(alerts.len(), 2);
+    assert_eq!(alerts[0]["alert_type"], json!("threshold"));
+    assert_eq!(alerts[0]["app_detected"], json!("CPU pegged: cpu_percent > 95"));
+    assert_eq!(alerts[0]["severity"], json!("High"));
+    assert_eq!(alerts[0]["threshold"]["state"], json!("firing"));
+    assert_eq!(alerts[0]["threshold"]["observed"], json!(99.0));
+    assert_eq!(alerts[1]["threshold"]["state"], json!("resolved"));
+    assert_eq!(alerts[1]["threshold"]["rule_id"], json!("4"));
+}
+
+#[tokio::test]
+async fn compliance_report_is_scored_and_uploaded() {
+    let agent = TestAgent::start().await;
+    let state = agent.state(test_settings());
+    state.credentials.set(MockBackend::TOKEN.to_string());
+    let token = state.credentials.require().unwrap();
+
+    // Older backend without checks: nothing runs, nothing is sent
+    assert!(compliance::run_checks(&state, &token).await.unwrap().is_none());
+    assert!(agent.backend.requests_to("POST", compliance::REPORT_ENDPOINT).is_empty());
+
+    agent.backend.set_compliance_checks(json!([
+        { "id": "root-ssh", "name": "SSH root login disabled", "probe": "ssh_root_login", "expected": "no", "severity": "High" },
+        { "id": "luks", "probe": "disk_encryption", "expected": true, "weight": 3 },
+        { "id": "tpm", "probe": "tpm_present", "expected": true },
+        { "id": "broken" },
+    ]));
+    let report = compliance::run_checks(&state, &token).await.unwrap().unwrap();
+    assert_eq!(report.results.len(), 3);
+    assert_eq!(report.results[2].status, compliance::CheckStatus::Error);
+    assert_eq!(report.passed + report.failed + report.errors + report.not_applicable, 3);
+    assert!(report.score.unwrap() <= 100);
+    assert_eq!(state.compliance.lock().unwrap().as_ref().unwrap().checked_at, report.checked_at);
+
+    let uploads = agent.backend.requests_to("POST", compliance::REPORT_ENDPOINT);
+    assert_eq!(uploads.len(), 1);
+    let body = uploads[0].body.as_ref().unwrap();
+    assert_eq!(body["device_id"], json!(get_device_id()));
+    assert_eq!(body["score"], json!(report.score));
+    assert_eq!(body["results"][0]["id"], json!("root-ssh"));
+    assert_eq!(body["results"][2]["status"], json!("error"));
+}
@@ -0,0 +1,459 @@
+// ============================================================================
+// Mock Backend for Integration Tests
+// ============================================================================
+// A small in-process stand-in for the Express API (itam-saas/Agent/server.js)
+// so the agent's network paths can be exercised offline:
+//
+// - POST /api/auth/login, GET /api/auth/me
+// - POST /api/agent/* (usage, heartbeat, policy-audit, ...); the heartbeat
+//   answer carries the device set with set_device()
+// - GET  /api/forbidden-apps (with ETag / 304 like Express)
+// - POST /api/alerts, GET /api/alerts/device/:id, PATCH /api/alerts/:id
+// - GET  /api/agent/releases/:channel (update manifest) and
+//   GET /downloads/:name (release files, no token needed, like a CDN)
+// - GET  /api/agent/tasks (queued with queue_task() until a result is
+//   posted to /api/agent/tasks/:id/result)
+// - GET  /api/agent/stream (push channel): each connection sends the next
+//   script queued with push_stream() as text/event-stream, then closes;
+//   404 once none is left, like a backend without push
+//
+// Every request is recorded for assertions. Failures are scripted per
+// route and consumed in order:
+//
+//   backend.fail_next("GET", "/api/forbidden-apps", Failure::Status(500));
+//   backend.fail_next("POST", "/api/alerts", Failure::Timeout(Duration::from_secs(3)));
+//
+// Requests without `Authorization: Bearer <MockBackend::TOKEN>` get a 401,
+// like an expired session. One request per connection (Connection: close).
+// ============================================================================
+
+#![allow(dead_code)]
+
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use tauriagent_lib::monitor::{MonitorEvent, MonitorEvents};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Failure {
+    /// Answer with this status and an error body
+    Status(u16),
+    /// Hold the connection this long, then close it without answering
+    Timeout(Duration),
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    /// Lowercased header names
+    pub headers: HashMap<String, String>,
+    pub body: Option<Value>,
+    /// Status the mock answered with (None for timeouts)
+    pub status: Option<u16>,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(|v| v.as_str())
+    }
+}
+
+#[derive(Default)]
+struct State {
+    requests: Vec<RecordedRequest>,
+    failures: HashMap<(String, String), VecDeque<Failure>>,
+    forbidden_apps: Vec<Value>,
+    forbidden_version: u64,
+    alerts: Vec<Value>,
+    releases: HashMap<String, Value>,
+    downloads: HashMap<String, Vec<u8>>,
+    streams: VecDeque<String>,
+    tasks: Vec<Value>,
+    task_results: Vec<Value>,
+    threshold_rules: Option<Vec<Value>>,
+    compliance_checks: Option<Vec<Value>>,
+    device: Option<Value>,
+}
+
+pub struct MockBackend {
+    pub url: String,
+    state: Arc<Mutex<State>>,
+    server: JoinHandle<()>,
+}
+
+impl Drop for MockBackend {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+impl MockBackend {
+    pub const TOKEN: &'static str = "test-token";
+
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let server_state = state.clone();
+        let server = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle_connection(stream, server_state.clone()));
+            }
+        });
+
+        Self { url, state, server }
+    }
+
+    /// Tags and org unit returned with every heartbeat response
+    pub fn set_device(&self, device: Value) {
+        self.state.lock().unwrap().device = Some(device);
+    }
+
+    /// Replace the forbidden list; changes the ETag
+    pub fn set_forbidden_apps(&self, apps: Value) {
+        let mut state = self.state.lock().unwrap();
+        state.forbidden_apps = apps.as_array().cloned().unwrap_or_default();
+        state.forbidden_version += 1;
+    }
+
+    /// Script a failure for the next matching request (queued per route)
+    pub fn fail_next(&self, method: &str, path: &str, failure: Failure) {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .entry((method.to_string(), path.to_string()))
+            .or_default()
+            .push_back(failure);
+    }
+
+    /// Serve `manifest` at GET /api/agent/releases/<channel>
+    pub fn publish_release(&self, channel: &str, manifest: Value) {
+        self.state.lock().unwrap().releases.insert(channel.to_string(), manifest);
+    }
+
+    /// Serve `bytes` at GET /downloads/<name>; returns the URL
+    pub fn serve_download(&self, name: &str, bytes: Vec<u8>) -> String {
+        self.state.lock().unwrap().downloads.insert(name.to_string(), bytes);
+        format!("{}/downloads/{}", self.url, name)
+    }
+
+    /// Queue a remote task for the device
+    pub fn queue_task(&self, task: Value) {
+        self.state.lock().unwrap().tasks.push(task);
+    }
+
+    /// Remote task results posted so far
+    pub fn task_results(&self) -> Vec<Value> {
+        self.state.lock().unwrap().task_results.clone()
+    }
+
+    /// Serve threshold rules; until called the route answers 404
+    pub fn set_threshold_rules(&self, rules: Value) {
+        self.state.lock().unwrap().threshold_rules = rules.as_array().cloned();
+    }
+
+    /// Serve compliance checks; until called the route answers 404
+    pub fn set_compliance_checks(&self, checks: Value) {
+        self.state.lock().unwrap().compliance_checks = checks.as_array().cloned();
+    }
+
+    /// Queue the body of one push stream connection (raw SSE text)
+    pub fn push_stream(&self, events: &str) {
+        self.state.lock().unwrap().streams.push_back(events.to_string());
+    }
+
+    /// Set fields on a stored alert, as an admin would in the dashboard
+    pub fn update_alert(&self, alert_id: i64, fields: Value) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(alert) = state.alerts.iter_mut().find(|a| a["id"] == alert_id) {
+            merge(alert, &fields);
+        }
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    pub fn requests_to(&self, method: &str, path: &str) -> Vec<RecordedRequest> {
+        self.requests()
+            .into_iter()
+            .filter(|r| r.method == method && r.path == path)
+            .collect()
+    }
+
+    /// Alerts created so far (successful POST /api/alerts)
+    pub fn alerts(&self) -> Vec<Value> {
+        self.state.lock().unwrap().alerts.clone()
+    }
+}
+
+fn merge(target: &mut Value, fields: &Value) {
+    if let (Some(target), Some(fields)) = (target.as_object_mut(), fields.as_object()) {
+        for (key, value) in fields {
+            target.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Option<Value>,
+    /// Sent as-is instead of `body`
+    raw: Option<Vec<u8>>,
+}
+
+impl Response {
+    fn json(status: u16, body: Value) -> Self {
+        Self { status, headers: Vec::new(), body: Some(body), raw: None }
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> Option<(String, String, HashMap<String, String>, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+        .collect();
+
+    let length: usize = headers
+        .get("content-length")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    let mut body = buf[header_end..].to_vec();
+    while body.len() < length {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+
+    Some((method, path, headers, body))
+}
+
+async fn handle_connection(mut stream: TcpStream, state: Arc<Mutex<State>>) {
+    let Some((method, path, headers, body)) = read_request(&mut stream).await else {
+        return;
+    };
+    let body: Option<Value> = serde_json::from_slice(&body).ok();
+
+    let failure = state
+        .lock()
+        .unwrap()
+        .failures
+        .get_mut(&(method.clone(), path.clone()))
+        .and_then(|queue| queue.pop_front());
+
+    let response = match failure {
+        Some(Failure::Timeout(delay)) => {
+            record(&state, &method, &path, &headers, &body, None);
+            tokio::time::sleep(delay).await;
+            return;
+        }
+        Some(Failure::Status(status)) => Response::json(status, json!({ "error": "scripted failure" })),
+        None => route(&state, &method, &path, &headers, body.as_ref()),
+    };
+    record(&state, &method, &path, &headers, &body, Some(response.status));
+
+    let payload = match (response.raw, response.body) {
+        (Some(raw), _) => raw,
+        (None, body) => body.map(|b| b.to_string()).unwrap_or_default().into_bytes(),
+    };
+    let content_type = match response.headers.iter().find(|(name, _)| *name == "Content-Type") {
+        Some(_) => String::new(),
+        None => "Content-Type: application/json\r\n".to_string(),
+    };
+    let mut head = format!(
+        "HTTP/1.1 {} Mock\r\n{}Content-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        content_type,
+        payload.len()
+    );
+    for (name, value) in response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+
+    let _ = stream.write_all(head.as_bytes()).await;
+    let _ = stream.write_all(&payload).await;
+    let _ = stream.shutdown().await;
+}
+
+fn record(
+    state: &Arc<Mutex<State>>,
+    method: &str,
+    path: &str,
+    headers: &HashMap<String, String>,
+    body: &Option<Value>,
+    status: Option<u16>,
+) {
+    state.lock().unwrap().requests.push(RecordedRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        headers: headers.clone(),
+        body: body.clone(),
+        status,
+    });
+}
+
+fn route(
+    state: &Arc<Mutex<State>>,
+    method: &str,
+    path: &str,
+    headers: &HashMap<String, String>,
+    body: Option<&Value>,
+) -> Response {
+    let mut state = state.lock().unwrap();
+
+    if (method, path) == ("POST", "/api/auth/login") {
+        return match body.and_then(|b| b["password"].as_str()) {
+            Some("password") => Response::json(200, json!({ "token": MockBackend::TOKEN })),
+            _ => Response::json(401, json!({ "error": "Invalid credentials" })),
+        };
+    }
+
+    if let Some(name) = path.strip_prefix("/downloads/").filter(|_| method == "GET") {
+        return match state.downloads.get(name) {
+            Some(bytes) => Response { status: 200, headers: Vec::new(), body: None, raw: Some(bytes.clone()) },
+            None => Response::json(404, json!({ "error": "Not found" })),
+        };
+    }
+
+    let bearer = format!("Bearer {}", MockBackend::TOKEN);
+    if headers.get("authorization") != Some(&bearer) {
+        return Response::json(401, json!({ "error": "Invalid or expired token" }));
+    }
+
+    let route_path = path.split('?').next().unwrap_or(path);
+    let segments: Vec<&str> = route_path.trim_start_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("GET", ["api", "auth", "me"]) => {
+            Response::json(200, json!({ "id": 1, "username": "agent-test", "role": "user" }))
+        }
+        ("POST", ["api", "agent", "heartbeat"]) => match &state.device {
+            Some(device) => Response::json(201, json!({ "success": true, "device": device })),
+            None => Response::json(201, json!({ "success": true })),
+        },
+        ("POST", ["api", "agent", _]) => Response::json(201, json!({ "success": true })),
+        ("GET", ["api", "agent", "threshold-rules"]) => match &state.threshold_rules {
+            Some(rules) => Response::json(200, Value::Array(rules.clone())),
+            None => Response::json(404, json!({ "error": "Not found" })),
+        },
+        ("GET", ["api", "agent", "compliance-checks"]) => match &state.compliance_checks {
+            Some(checks) => Response::json(200, Value::Array(checks.clone())),
+            None => Response::json(404, json!({ "error": "Not found" })),
+        },
+        ("GET", ["api", "agent", "tasks"]) => Response::json(200, Value::Array(state.tasks.clone())),
+        ("POST", ["api", "agent", "tasks", id, "result"]) => {
+            state.tasks.retain(|t| t["id"].to_string().trim_matches('"') != *id);
+            if let Some(body) = body {
+                state.task_results.push(body.clone());
+            }
+            Response::json(201, json!({ "success": true }))
+        }
+        ("GET", ["api", "agent", "stream"]) => match state.streams.pop_front() {
+            Some(events) => Response {
+                status: 200,
+                headers: vec![("Content-Type", "text/event-stream".to_string())],
+                body: None,
+                raw: Some(events.into_bytes()),
+            },
+            None => Response::json(404, json!({ "error": "Not found" })),
+        },
+        ("GET", ["api", "agent", "releases", channel]) => match state.releases.get(*channel) {
+            Some(manifest) => Response::json(200, manifest.clone()),
+            None => Response::json(404, json!({ "error": "No release for this channel" })),
+        },
+        ("GET", ["api", "forbidden-apps"]) => {
+            let etag = format!("W/\"v{}\"", state.forbidden_version);
+            if headers.get("if-none-match") == Some(&etag) {
+                return Response { status: 304, headers: vec![("ETag", etag)], body: None, raw: None };
+            }
+            Response {
+                status: 200,
+                headers: vec![("ETag", etag)],
+                body: Some(Value::Array(state.forbidden_apps.clone())),
+                raw: None,
+            }
+        }
+        ("POST", ["api", "alerts"]) => {
+            let id = state.alerts.len() as i64 + 1;
+            let mut alert = json!({
+                "id": id,
+                "status": "New",
+                "notes": null,
+                "created_at": "2026-01-01T00:00:00Z",
+            });
+            if let Some(body) = body {
+                merge(&mut alert, body);
+                alert["app_name"] = body["app_detected"].clone();
+            }
+            state.alerts.push(alert.clone());
+            Response::json(201, alert)
+        }
+        ("GET", ["api", "alerts", "device", device_id]) => {
+            let rows: Vec<Value> = state
+                .alerts
+                .iter()
+                .filter(|a| a["device_id"] == *device_id)
+                .cloned()
+                .collect();
+            Response::json(200, Value::Array(rows))
+        }
+        ("PATCH", ["api", "alerts", id]) => {
+            let id: i64 = id.parse().unwrap_or_default();
+            match state.alerts.iter_mut().find(|a| a["id"] == id) {
+                Some(alert) => {
+                    if let Some(body) = body {
+                        merge(alert, body);
+                    }
+                    Response::json(200, alert.clone())
+                }
+                None => Response::json(404, json!({ "error": "Alert not found" })),
+            }
+        }
+        _ => Response::json(404, json!({ "error": "Not found" })),
+    }
+}
+
+/// MonitorEvents sink that keeps everything for assertions
+#[derive(Clone, Default)]
+pub struct RecordedEvents(Arc<Mutex<Vec<MonitorEvent>>>);
+
+impl RecordedEvents {
+    pub fn take(&self) -> Vec<MonitorEvent> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl MonitorEvents for RecordedEvents {
+    fn raise(&mut self, event: MonitorEvent) {
+        self.0.lock().unwrap().push(event);
+    }
+}
@@ -5,6 +5,25 @@ import { listen } from "@tauri-apps/api/event";
 import { openUrl } from "@tauri-apps/plugin-opener";
 import "./App.css";
 
+// Commands reject with an AgentError object: { kind, message, retryable }
+// (src-tauri/src/error.rs); plain JS errors and strings are handled too.
+function describeError(err) {
+  if (!err) return "Unknown error";
+  switch (err.kind) {
+    case "network":
+    case "timeout":
+      return "Cannot reach the server. Check your connection and try again.";
+    case "rate_limited":
+      return err.retry_after_secs
+        ? `Too many requests. Try again in ${err.retry_after_secs} seconds.`
+        : "Too many requests. Try again in a moment.";
+    case "server":
+      return err.retryable ? "The server is having trouble. Try again later." : err.message;
+    default:
+      return err.message || String(err);
+  }
+}
+
 function App() {
   // Authentication state
   const [isAuthenticated, setIsAuthenticated] = useState(false);
@@ -72,8 +91,7 @@ function App() {
       await invoke('set_monitoring_token', { token: trimmedToken });
       setTimeout(() => minimizeToTray(), 2000);
     } catch (err) {
-      const msg = (err && err.message) ? err.message : String(err);
-      setLoginError('Google sign-in failed: ' + msg);
+      setLoginError('Google sign-in failed: ' + describeError(err));
     }
   };
 
@@ -110,9 +128,15 @@ function App() {
         setLastSync(new Date());
         console.log("✅ Usage data sent:", result);
       } catch (err) {
-        setSyncStatus("Error");
-        setErrorMessage(String(err));
         console.error("❌ Failed to send usage data:", err);
+        if (err?.kind === "unauthorized") {
+          // The saved token expired or was revoked: sign in again
+          handleLogout();
+          setLoginError("Your session has expired. Please sign in again.");
+          return;
+        }
+        setSyncStatus("Error");
+        setErrorMessage(describeError(err));
       }
     };
 
@@ -131,7 +155,7 @@ function App() {
       const appWindow = getCurrentWindow();
       await appWindow.hide();
     } catch (err) {
-      const message = `Failed to minimize to tray: ${String(err)}`;
+      const message = `Failed to minimize to tray: ${describeError(err)}`;
       setErrorMessage(message);
       console.error(message, err);
     }
@@ -178,7 +202,7 @@ function App() {
         minimizeToTray();
       }, 2000);
     } catch (err) {
-      setLoginError(err);
+      setLoginError(err?.kind === "unauthorized" ? "Invalid username or password" : describeError(err));
     }
   };
 